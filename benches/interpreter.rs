@@ -0,0 +1,70 @@
+//! Measures interpreter throughput with and without the decode cache.
+//!
+//! Run with `cargo bench --bench interpreter`; the instructions per second
+//! of each pair are directly comparable.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use wasm::chip8::{Cpu, Platform, StepOutcome};
+
+/// Instructions run per benchmark iteration.
+const STEPS: u64 = 100_000;
+
+/// Instructions between timer ticks, as if running at 60000 Hz.
+const STEPS_PER_FRAME: u64 = 1000;
+
+/// Counts v2 from 0 to 249, writing its digits into the following code with
+/// BCD, and draws the digit loaded from there: a busy loop with a draw and
+/// self-modifying code.
+const COUNTER: [u16; 13] = [
+    0x6200, 0x7201, 0x42FA, 0x6200, 0xA211, 0xF233, 0x00E0, 0x6400, 0x6300, 0x0000, 0xF329, 0xD445,
+    0x1202,
+];
+
+/// Fills the SUPER-CHIP hi-res screen with 16x16 sprites, scrolling it
+/// between passes: how hi-res games spend their time.
+const HIRES: [u16; 14] = [
+    0x00FF, 0x6000, 0x6100, 0xA000, 0xD010, 0x7010, 0x3080, 0x1208, 0x6000, 0x7110, 0x3140, 0x1208,
+    0x00FB, 0x1204,
+];
+
+fn cpu(platform: Platform, program: &[u16], cached: bool) -> Cpu {
+    let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+    let mut cpu = Cpu::for_platform(platform);
+    cpu.quirks.display_wait = false;
+    cpu.set_decode_cache(cached);
+    cpu.load_rom(&rom).unwrap();
+    cpu
+}
+
+fn run(cpu: &mut Cpu) {
+    for step in 1..=STEPS {
+        match cpu.step() {
+            StepOutcome::Executed => (),
+            StepOutcome::Waiting => cpu.tick_timers(),
+            outcome => panic!("benchmark program stopped: {:?}", outcome),
+        }
+        if step % STEPS_PER_FRAME == 0 {
+            cpu.tick_timers();
+        }
+    }
+}
+
+fn interpreter(c: &mut Criterion) {
+    let programs = [
+        ("counter", Platform::Chip8, &COUNTER[..]),
+        ("hires", Platform::SuperChip, &HIRES[..]),
+    ];
+
+    let mut group = c.benchmark_group("interpreter");
+    group.throughput(Throughput::Elements(STEPS));
+    for &(name, platform, program) in programs.iter() {
+        for &(cache, cached) in [("decode cache", true), ("no cache", false)].iter() {
+            let mut cpu = cpu(platform, program, cached);
+            group.bench_function(BenchmarkId::new(name, cache), |b| b.iter(|| run(&mut cpu)));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, interpreter);
+criterion_main!(benches);
@@ -0,0 +1,511 @@
+//! Runs a ROM headlessly for a fixed number of frames or cycles, optionally
+//! dumping the screen to image files, and prints the registers on exit.
+
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::process;
+use std::time::Instant;
+use wasm::chip8::{
+    self, Cpu, Font, FontSize, Platform, Quirks, RomDatabase, RomInfo, StepOutcome, DEFAULT_PALETTE,
+};
+
+const USAGE: &str = "\
+usage: chip8-run [options] <rom>
+
+options:
+  --platform <name>      chip8, schip or xochip (default: the rom database's,
+                         or chip8)
+  --quirks <preset>      vip, chip48, schip or xochip (default: the rom
+                         database's, or the platform's)
+  --load-address <n>     where the rom is loaded and starts (default: the rom
+                         database's, or 0x200; ETI-660 programs start at 0x600)
+  --database <file>      merge entries in the chip-8-database programs.json
+                         format into the rom database, which picks the
+                         platform, quirks, speed and font of the roms it knows
+  --font <name>          chip48, vip, dream6800, eti660, fish, or schip for
+                         the big font; may be given for both sizes
+  --frames <n>           number of 60 Hz frames to run (default 60)
+  --cycles <n>           stop after n instructions instead
+  --ipf <n>              instructions per frame (default: the rom database's,
+                         or the platform's)
+  --seed <n>             seed for the random number generator
+  --key <frame>:<key>[:<frames>]
+                         hold hex key from a frame on, for 1 frame by default;
+                         may be given more than once
+  --out <prefix>         write the final screen to <prefix>.<format>
+  --every <k>            also write every kth frame to <prefix>-<frame>.<format>
+  --format <pbm|png>     image format (default pbm)
+  --trace <file>         write the last instructions executed to <file>, as
+                         JSON when it ends in .json and as text otherwise
+  --trace-depth <n>      number of instructions to trace (default 4096)
+  --reference <file>     instead of running frames, run in lockstep with a
+                         trace from another emulator and report the first
+                         instruction where the two differ; each line of the
+                         trace is `pc opcode v0 .. vf i` in hex
+  --profile <file>       write a profile of where the time went to <file>:
+                         the hottest addresses and instructions, draws and
+                         time per frame, and hot loops such as busy-waits
+  --coverage <file>      write which instructions of the rom ran to <file>,
+                         as JSON when it ends in .json and as lcov otherwise";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Pbm,
+    Png,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Pbm => "pbm",
+            Format::Png => "png",
+        }
+    }
+}
+
+struct KeyPress {
+    frame: u64,
+    key: u8,
+    frames: u64,
+}
+
+struct Options {
+    rom: String,
+    platform: Option<Platform>,
+    quirks: Option<Quirks>,
+    fonts: Vec<Font>,
+    load_address: Option<u16>,
+    database: Option<String>,
+    frames: Option<u64>,
+    cycles: Option<u64>,
+    cycles_per_frame: Option<u32>,
+    seed: Option<u64>,
+    keys: Vec<KeyPress>,
+    out: Option<String>,
+    every: Option<u64>,
+    format: Format,
+    trace: Option<String>,
+    trace_depth: usize,
+    reference: Option<String>,
+    profile: Option<String>,
+    coverage: Option<String>,
+}
+
+fn parse_number(value: &str) -> Result<u64, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number: {}", value))
+}
+
+fn parse_key(value: &str) -> Result<KeyPress, String> {
+    let invalid = || format!("invalid key press: {}", value);
+    let parts: Vec<&str> = value.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return Err(invalid());
+    }
+
+    let key = u8::from_str_radix(parts[1], 16).map_err(|_| invalid())?;
+    if key >= chip8::KEY_COUNT {
+        return Err(invalid());
+    }
+
+    let frames = match parts.get(2) {
+        Some(frames) => parse_number(frames)?,
+        None => 1,
+    };
+    if frames == 0 {
+        return Err(invalid());
+    }
+
+    Ok(KeyPress {
+        frame: parse_number(parts[0])?,
+        key,
+        frames,
+    })
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        platform: None,
+        quirks: None,
+        fonts: Vec::new(),
+        load_address: None,
+        database: None,
+        frames: None,
+        cycles: None,
+        cycles_per_frame: None,
+        seed: None,
+        keys: Vec::new(),
+        out: None,
+        every: None,
+        format: Format::Pbm,
+        trace: None,
+        trace_depth: chip8::DEFAULT_TRACE_CAPACITY,
+        reference: None,
+        profile: None,
+        coverage: None,
+    };
+    let mut rom = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            if rom.replace(arg.clone()).is_some() {
+                return Err("more than one rom given".to_string());
+            }
+            continue;
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--platform" => {
+                options.platform = Some(
+                    Platform::from_name(value)
+                        .ok_or_else(|| format!("unknown platform: {}", value))?,
+                )
+            }
+            "--quirks" => {
+                options.quirks = Some(
+                    Quirks::from_name(value)
+                        .ok_or_else(|| format!("unknown quirks preset: {}", value))?,
+                )
+            }
+            "--font" => options
+                .fonts
+                .push(Font::from_name(value).ok_or_else(|| format!("unknown font: {}", value))?),
+            "--load-address" => {
+                options.load_address = Some(
+                    u16::try_from(parse_number(value)?)
+                        .map_err(|_| format!("load address out of range: {}", value))?,
+                )
+            }
+            "--frames" => options.frames = Some(parse_number(value)?),
+            "--cycles" => options.cycles = Some(parse_number(value)?),
+            "--ipf" => {
+                options.cycles_per_frame = Some(
+                    u32::try_from(parse_number(value)?)
+                        .map_err(|_| format!("--ipf out of range: {}", value))?,
+                )
+            }
+            "--database" => options.database = Some(value.clone()),
+            "--seed" => options.seed = Some(parse_number(value)?),
+            "--key" => options.keys.push(parse_key(value)?),
+            "--out" => options.out = Some(value.clone()),
+            "--every" => options.every = Some(parse_number(value)?.max(1)),
+            "--format" => {
+                options.format = match value.as_str() {
+                    "pbm" => Format::Pbm,
+                    "png" => Format::Png,
+                    _ => return Err(format!("unknown image format: {}", value)),
+                }
+            }
+            "--trace" => options.trace = Some(value.clone()),
+            "--reference" => options.reference = Some(value.clone()),
+            "--trace-depth" => options.trace_depth = parse_number(value)? as usize,
+            "--profile" => options.profile = Some(value.clone()),
+            "--coverage" => options.coverage = Some(value.clone()),
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+
+    options.rom = rom.ok_or_else(|| "no rom given".to_string())?;
+    if options.frames.is_none() && options.cycles.is_none() {
+        options.frames = Some(60);
+    }
+    if options.every.is_some() && options.out.is_none() {
+        return Err("--every needs --out".to_string());
+    }
+    if options.trace_depth == 0 {
+        return Err("--trace-depth must be at least 1".to_string());
+    }
+    if options.cycles_per_frame == Some(0) {
+        return Err("--ipf must be at least 1".to_string());
+    }
+    Ok(options)
+}
+
+fn write_screen(cpu: &Cpu, path: &str, format: Format) -> Result<(), String> {
+    let image = match format {
+        Format::Pbm => chip8::encode_pbm(&cpu.screen),
+        Format::Png => chip8::encode_png(&cpu.screen, &DEFAULT_PALETTE),
+    };
+    fs::write(path, image).map_err(|err| format!("failed to write {}: {}", path, err))
+}
+
+fn print_registers(cpu: &Cpu, frames: u64, cycles: u64) {
+    println!("frames: {}  cycles: {}", frames, cycles);
+    println!(
+        "pc: {:#06x}  i: {:#06x}  sp: {}  dt: {}  st: {}",
+        cpu.pc(),
+        cpu.i(),
+        cpu.sp(),
+        cpu.timers.delay,
+        cpu.timers.sound
+    );
+    let registers: Vec<String> = cpu
+        .registers()
+        .iter()
+        .enumerate()
+        .map(|(index, value)| format!("v{:x}: {:#04x}", index, value))
+        .collect();
+    for row in registers.chunks(8) {
+        println!("{}", row.join("  "));
+    }
+    let stack: Vec<String> = cpu
+        .stack()
+        .iter()
+        .map(|address| format!("{:#06x}", address))
+        .collect();
+    println!("stack: [{}]", stack.join(", "));
+}
+
+/// Runs the loaded rom against the reference trace at `path` and returns
+/// whether the two agreed throughout.
+fn run_lockstep(cpu: &mut Cpu, path: &str, cycles_per_frame: u32) -> Result<bool, String> {
+    let text =
+        fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+    let reference =
+        chip8::ReferenceTrace::parse(&text).map_err(|err| format!("{}: {}", path, err))?;
+
+    let lockstep = reference.run_lockstep(cpu, cycles_per_frame);
+    println!(
+        "matched {} of {} instructions",
+        lockstep.matched,
+        reference.len()
+    );
+    match lockstep.divergence {
+        Some(divergence) => {
+            print!("{}", divergence);
+            Ok(false)
+        }
+        None => Ok(true),
+    }
+}
+
+/// Runs the rom and returns whether it finished without a fault.
+fn run(options: &Options) -> Result<bool, String> {
+    let rom =
+        fs::read(&options.rom).map_err(|err| format!("failed to read {}: {}", options.rom, err))?;
+
+    let mut database = RomDatabase::embedded();
+    if let Some(path) = &options.database {
+        let text =
+            fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+        database
+            .merge(&text)
+            .map_err(|err| format!("{}: {}", path, err))?;
+    }
+
+    // Options given on the command line win over the rom database.
+    let mut info = database
+        .lookup(&rom)
+        .unwrap_or_else(|| RomInfo::for_platform(Platform::default()));
+    if let Some(platform) = options.platform {
+        info.platform = platform;
+        info.quirks = platform.quirks();
+        info.tick_rate = platform.tick_rate();
+    }
+    if let Some(quirks) = options.quirks {
+        info.quirks = quirks;
+    }
+    if let Some(cycles_per_frame) = options.cycles_per_frame {
+        info.tick_rate = cycles_per_frame;
+    }
+    if let Some(address) = options.load_address {
+        info.start_address = address;
+    }
+
+    let mut cpu = Cpu::default();
+    if let Some(seed) = options.seed {
+        cpu.seed_rng(seed);
+    }
+    for &font in options.fonts.iter() {
+        match font.size() {
+            FontSize::Small => info.font = Some(font),
+            FontSize::Big => cpu
+                .load_font(font, chip8::BIG_FONT_ADDRESS)
+                .map_err(|err| err.to_string())?,
+        }
+    }
+    info.load(&mut cpu, &rom)
+        .map_err(|err| format!("{}: {}", options.rom, err))?;
+    if options.trace.is_some() {
+        cpu.enable_trace(options.trace_depth);
+    }
+    if options.profile.is_some() || options.coverage.is_some() {
+        cpu.enable_profiler();
+    }
+    if let Some(path) = &options.reference {
+        return run_lockstep(&mut cpu, path, info.tick_rate);
+    }
+
+    let mut frame = 0;
+    let mut cycles = 0;
+    let mut outcome = StepOutcome::Executed;
+    while options.frames.map_or(true, |frames| frame < frames)
+        && options.cycles.map_or(true, |limit| cycles < limit)
+    {
+        for press in options.keys.iter() {
+            if frame == press.frame {
+                cpu.keypad.key_down(press.key);
+            } else if frame == press.frame.saturating_add(press.frames) {
+                cpu.keypad.key_up(press.key);
+            }
+        }
+
+        let budget = match options.cycles {
+            Some(limit) => (limit - cycles).min(info.tick_rate as u64) as u32,
+            None => info.tick_rate,
+        };
+        let frame_start = Instant::now();
+        for _ in 0..budget {
+            outcome = cpu.step();
+            if outcome != StepOutcome::Executed {
+                break;
+            }
+            cycles += 1;
+        }
+        if let StepOutcome::Exited | StepOutcome::Fault(_) = outcome {
+            break;
+        }
+        if let Some(profiler) = cpu.profiler_mut() {
+            profiler.record_frame_time(frame_start.elapsed().as_secs_f64() * 1000.0);
+        }
+        cpu.tick_timers();
+        frame += 1;
+
+        if let (Some(prefix), Some(every)) = (&options.out, options.every) {
+            if frame % every == 0 {
+                let path = format!("{}-{:06}.{}", prefix, frame, options.format.extension());
+                write_screen(&cpu, &path, options.format)?;
+            }
+        }
+    }
+
+    if let StepOutcome::Fault(fault) = outcome {
+        eprintln!("cpu halted: {}", fault);
+    }
+    if let Some(prefix) = &options.out {
+        let path = format!("{}.{}", prefix, options.format.extension());
+        write_screen(&cpu, &path, options.format)?;
+    }
+    if let (Some(path), Some(trace)) = (&options.trace, cpu.trace()) {
+        let log = if path.ends_with(".json") {
+            trace.to_json()
+        } else {
+            trace.to_text()
+        };
+        fs::write(path, log).map_err(|err| format!("failed to write {}: {}", path, err))?;
+    }
+    if let (Some(path), Some(profiler)) = (&options.profile, cpu.profiler()) {
+        let report = profiler.report(cpu.memory());
+        fs::write(path, report).map_err(|err| format!("failed to write {}: {}", path, err))?;
+    }
+    if let (Some(path), Some(profiler)) = (&options.coverage, cpu.profiler()) {
+        let coverage = if path.ends_with(".json") {
+            profiler.coverage_json(&rom, info.start_address)
+        } else {
+            profiler.coverage_lcov(&options.rom, &rom, info.start_address)
+        };
+        fs::write(path, coverage).map_err(|err| format!("failed to write {}: {}", path, err))?;
+    }
+    print_registers(&cpu, frame, cycles);
+
+    Ok(!matches!(outcome, StepOutcome::Fault(_)))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("chip8-run: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    match run(&options) {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("chip8-run: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn defaults_to_sixty_frames() {
+        let options = parse_args(&args("game.ch8")).unwrap();
+
+        assert_eq!("game.ch8", options.rom);
+        assert_eq!(Some(60), options.frames);
+        assert_eq!(None, options.cycles);
+        assert!(options.format == Format::Pbm);
+    }
+
+    #[test]
+    fn parses_all_options() {
+        let options = parse_args(&args(
+            "--platform xochip --font vip --load-address 0x600 --database overrides.json --cycles 0x100 --ipf 20 --key 5:a:3 --key 9:F \
+             --out shot --every 10 --format png --trace log.json --trace-depth 100 --reference ref.txt \
+             --profile prof.txt --coverage cov.info game.ch8",
+        ))
+        .unwrap();
+
+        assert_eq!(Some(Platform::XoChip), options.platform);
+        assert_eq!(vec![Font::CosmacVip], options.fonts);
+        assert_eq!(Some(0x600), options.load_address);
+        assert_eq!(Some("overrides.json".to_string()), options.database);
+        assert_eq!(None, options.frames);
+        assert_eq!(Some(256), options.cycles);
+        assert_eq!(Some(20), options.cycles_per_frame);
+        assert_eq!((5, 0xA, 3), {
+            let press = &options.keys[0];
+            (press.frame, press.key, press.frames)
+        });
+        assert_eq!(1, options.keys[1].frames);
+        assert_eq!(Some(10), options.every);
+        assert!(options.format == Format::Png);
+        assert_eq!(Some("log.json".to_string()), options.trace);
+        assert_eq!(100, options.trace_depth);
+        assert_eq!(Some("ref.txt".to_string()), options.reference);
+        assert_eq!(Some("prof.txt".to_string()), options.profile);
+        assert_eq!(Some("cov.info".to_string()), options.coverage);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse_args(&args("")).is_err());
+        assert!(parse_args(&args("a.ch8 b.ch8")).is_err());
+        assert!(parse_args(&args("--frames")).is_err());
+        assert!(parse_args(&args("--font comic game.ch8")).is_err());
+        assert!(parse_args(&args("--load-address 0x10000 game.ch8")).is_err());
+        assert!(parse_args(&args("--key 1:10 game.ch8")).is_err());
+        assert!(parse_args(&args("--key 1:a:0 game.ch8")).is_err());
+        assert!(parse_args(&args("--ipf 0 game.ch8")).is_err());
+        assert!(parse_args(&args("--ipf 0x100000000 game.ch8")).is_err());
+        assert!(parse_args(&args("--every 5 game.ch8")).is_err());
+        assert!(parse_args(&args("--format gif game.ch8")).is_err());
+        assert!(parse_args(&args("--trace-depth 0 game.ch8")).is_err());
+    }
+}
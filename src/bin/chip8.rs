@@ -0,0 +1,177 @@
+//! Tools for working with CHIP-8 programs outside the emulator.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use wasm::chip8::{self, Syntax};
+
+const USAGE: &str = "\
+usage: chip8 <command> [options]
+
+commands:
+  asm [--out <rom>] <source>
+                         assemble Cowgod style source, into <source>.ch8 by
+                         default
+  octo [--out <rom>] [--symbols <file>] <source>
+                         compile Octo source, into <source>.ch8 by default,
+                         optionally writing `address name` lines for labels
+  disasm [--syntax <cowgod|octo>] [--origin <address>] <rom>
+                         print a listing of a rom, in Cowgod syntax by default";
+
+fn parse_address(value: &str) -> Result<u16, String> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("invalid address: {}", value))
+}
+
+/// `--name value` pairs and the file they apply to.
+type Arguments<'a> = (Vec<(&'a str, &'a str)>, &'a str);
+
+/// Splits `args` into `--name value` pairs and a single file argument.
+fn parse_options(args: &[String]) -> Result<Arguments<'_>, String> {
+    let mut options = Vec::new();
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg.starts_with("--") {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", arg))?;
+            options.push((arg.as_str(), value.as_str()));
+        } else if file.replace(arg.as_str()).is_some() {
+            return Err("more than one file given".to_string());
+        }
+    }
+
+    Ok((options, file.ok_or_else(|| "no file given".to_string())?))
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|err| format!("failed to read {}: {}", path, err))
+}
+
+fn asm(args: &[String]) -> Result<(), String> {
+    let (options, path) = parse_options(args)?;
+    let mut out = Path::new(path).with_extension("ch8");
+    for (name, value) in options {
+        match name {
+            "--out" => out = value.into(),
+            _ => return Err(format!("unknown option: {}", name)),
+        }
+    }
+
+    let source =
+        fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+    let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let mut include = |name: &str| {
+        fs::read_to_string(directory.join(name))
+            .map_err(|err| format!("failed to read {}: {}", name, err))
+    };
+    let program = chip8::assemble_with_includes(&source, path, &mut include)
+        .map_err(|err| err.to_string())?;
+
+    fs::write(&out, &program.rom)
+        .map_err(|err| format!("failed to write {}: {}", out.display(), err))
+}
+
+fn octo(args: &[String]) -> Result<(), String> {
+    let (options, path) = parse_options(args)?;
+    let mut out = Path::new(path).with_extension("ch8");
+    let mut symbols = None;
+    for (name, value) in options {
+        match name {
+            "--out" => out = value.into(),
+            "--symbols" => symbols = Some(value),
+            _ => return Err(format!("unknown option: {}", name)),
+        }
+    }
+
+    let source =
+        fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path, err))?;
+    let program = chip8::compile_octo(&source, path).map_err(|err| err.to_string())?;
+
+    fs::write(&out, &program.rom)
+        .map_err(|err| format!("failed to write {}: {}", out.display(), err))?;
+    if let Some(symbols) = symbols {
+        let listing: String = program
+            .symbols
+            .iter()
+            .map(|(name, address)| format!("0x{:03X} {}\n", address, name))
+            .collect();
+        fs::write(symbols, listing)
+            .map_err(|err| format!("failed to write {}: {}", symbols, err))?;
+    }
+    Ok(())
+}
+
+fn disasm(args: &[String]) -> Result<(), String> {
+    let (options, path) = parse_options(args)?;
+    let mut syntax = Syntax::Cowgod;
+    let mut origin = chip8::DEFAULT_ORIGIN;
+    for (name, value) in options {
+        match name {
+            "--syntax" => {
+                syntax =
+                    Syntax::from_name(value).ok_or_else(|| format!("unknown syntax: {}", value))?
+            }
+            "--origin" => origin = parse_address(value)?,
+            _ => return Err(format!("unknown option: {}", name)),
+        }
+    }
+
+    print!("{}", chip8::disassemble(&read(path)?, origin, syntax));
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("asm") => asm(&args[1..]),
+        Some("octo") => octo(&args[1..]),
+        Some("disasm") => disasm(&args[1..]),
+        Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            return;
+        }
+        Some(command) => Err(format!("unknown command: {}\n\n{}", command, USAGE)),
+        None => Err(USAGE.to_string()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("chip8: {}", err);
+        process::exit(2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn options_and_file_are_split() {
+        let args = args("--syntax octo game.ch8 --origin 0x600");
+
+        let (options, file) = parse_options(&args).unwrap();
+
+        assert_eq!(vec![("--syntax", "octo"), ("--origin", "0x600")], options);
+        assert_eq!("game.ch8", file);
+        assert_eq!(Ok(0x600), parse_address("0x600"));
+        assert_eq!(Ok(512), parse_address("512"));
+    }
+
+    #[test]
+    fn bad_arguments_are_rejected() {
+        assert!(parse_options(&args("")).is_err());
+        assert!(parse_options(&args("a.ch8 b.ch8")).is_err());
+        assert!(parse_options(&args("a.ch8 --syntax")).is_err());
+        assert!(parse_address("0x10000").is_err());
+    }
+}
@@ -0,0 +1,1197 @@
+use crate::chip8::disasm::DEFAULT_ORIGIN;
+use crate::chip8::opcode::Opcode;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+
+/// How deep `INCLUDE` may nest, which also catches files including
+/// themselves.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// An assembled program: the bytes to load at `origin`, the address of
+/// every label and any breakpoints the source asks for, by address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub origin: u16,
+    pub rom: Vec<u8>,
+    pub symbols: BTreeMap<String, u16>,
+    pub breakpoints: BTreeMap<u16, String>,
+}
+
+/// An error in the source, pointing at the file, line and column it was
+/// found at. Lines and columns count from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+}
+
+impl Location {
+    fn error(&self, column: usize, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(Vec<u8>),
+    Punct(&'static str),
+}
+
+/// A token, operand or other piece of a line with the column it starts at.
+#[derive(Debug, Clone)]
+struct Located<T> {
+    value: T,
+    column: usize,
+}
+
+const PUNCTUATION: &[&str] = &[
+    "<<", ">>", ",", ":", "(", ")", "[", "]", "+", "-", "*", "/", "%", "&", "|", "^", "~", "$", "=",
+];
+
+fn tokenize(text: &str, location: &Location) -> Result<Vec<Located<Token>>, AsmError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "_.".contains(chars[i])) {
+                i += 1;
+            }
+            let ident = chars[start..i].iter().collect();
+            tokens.push(Located {
+                value: Token::Ident(ident),
+                column,
+            });
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().filter(|&&c| c != '_').collect();
+            let lower = literal.to_ascii_lowercase();
+            let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+                i64::from_str_radix(hex, 16)
+            } else if let Some(binary) = lower.strip_prefix("0b") {
+                i64::from_str_radix(binary, 2)
+            } else {
+                lower.parse()
+            };
+            let value = parsed
+                .map_err(|_| location.error(column, format!("invalid number `{}`", literal)))?;
+            tokens.push(Located {
+                value: Token::Number(value),
+                column,
+            });
+        } else if c == '"' || c == '\'' {
+            let mut bytes = Vec::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(location.error(column, "unterminated string")),
+                    Some(&end) if end == c => break,
+                    Some('\\') if i + 1 < chars.len() => {
+                        let byte = match chars[i + 1] {
+                            'n' => b'\n',
+                            't' => b'\t',
+                            'r' => b'\r',
+                            '0' => 0,
+                            escaped @ ('\\' | '"' | '\'') => escaped as u8,
+                            escaped if !escaped.is_ascii() => {
+                                return Err(location.error(i + 2, "strings must be ASCII"))
+                            }
+                            escaped => {
+                                return Err(location
+                                    .error(i + 1, format!("unknown escape `\\{}`", escaped)))
+                            }
+                        };
+                        bytes.push(byte);
+                        i += 2;
+                    }
+                    Some(&other) if other.is_ascii() => {
+                        bytes.push(other as u8);
+                        i += 1;
+                    }
+                    Some(_) => return Err(location.error(i + 1, "strings must be ASCII")),
+                }
+            }
+            i += 1;
+            let token = if c == '"' {
+                Token::Str(bytes)
+            } else if bytes.len() == 1 {
+                Token::Number(bytes[0] as i64)
+            } else {
+                return Err(location.error(column, "character literals hold one character"));
+            };
+            tokens.push(Located {
+                value: token,
+                column,
+            });
+        } else {
+            let rest: String = chars[i..].iter().take(2).collect();
+            let punct = PUNCTUATION
+                .iter()
+                .find(|punct| rest.starts_with(*punct))
+                .ok_or_else(|| location.error(column, format!("unexpected character `{}`", c)))?;
+            i += punct.len();
+            tokens.push(Located {
+                value: Token::Punct(punct),
+                column,
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Symbol(String, usize),
+    Here,
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>, usize),
+}
+
+/// The registers and keywords that can appear as instruction operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    V(u8),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    HF,
+    B,
+    R,
+    Pitch,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Register(Register),
+    Long(Expr),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone)]
+enum DataItem {
+    Expr(Expr, usize),
+    Str(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Instruction(String, Vec<Located<Operand>>),
+    Db(Vec<DataItem>),
+    Dw(Vec<(Expr, usize)>),
+    Org(Expr, usize),
+    Equ(String, Expr),
+}
+
+#[derive(Debug, Clone)]
+struct Statement {
+    location: Location,
+    column: usize,
+    label: Option<(String, usize)>,
+    kind: Option<Kind>,
+}
+
+fn register(name: &str) -> Option<Register> {
+    let upper = name.to_ascii_uppercase();
+    let register = match upper.as_str() {
+        "I" => Register::I,
+        "DT" => Register::DT,
+        "ST" => Register::ST,
+        "K" => Register::K,
+        "F" => Register::F,
+        "HF" => Register::HF,
+        "B" => Register::B,
+        "R" => Register::R,
+        "PITCH" => Register::Pitch,
+        _ => {
+            let index = upper.strip_prefix('V')?;
+            if index.len() != 1 {
+                return None;
+            }
+            Register::V(u8::from_str_radix(index, 16).ok()?)
+        }
+    };
+    Some(register)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Located<Token>],
+    position: usize,
+    location: &'a Location,
+    end_column: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position).map(|located| &located.value)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.end_column, |located| located.column)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Punct(candidate)) if *candidate == punct) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), AsmError> {
+        if !self.eat(punct) {
+            return Err(self.error(format!("expected `{}`", punct)));
+        }
+        Ok(())
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        self.location.error(self.column(), message)
+    }
+
+    fn expr(&mut self) -> Result<Expr, AsmError> {
+        self.binary(0)
+    }
+
+    /// Precedence climbing over the C operator precedences.
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, AsmError> {
+        let mut left = self.unary()?;
+
+        loop {
+            let (op, precedence) = match self.peek() {
+                Some(Token::Punct("|")) => (BinaryOp::Or, 1),
+                Some(Token::Punct("^")) => (BinaryOp::Xor, 2),
+                Some(Token::Punct("&")) => (BinaryOp::And, 3),
+                Some(Token::Punct("<<")) => (BinaryOp::Shl, 4),
+                Some(Token::Punct(">>")) => (BinaryOp::Shr, 4),
+                Some(Token::Punct("+")) => (BinaryOp::Add, 5),
+                Some(Token::Punct("-")) => (BinaryOp::Sub, 5),
+                Some(Token::Punct("*")) => (BinaryOp::Mul, 6),
+                Some(Token::Punct("/")) => (BinaryOp::Div, 6),
+                Some(Token::Punct("%")) => (BinaryOp::Rem, 6),
+                _ => return Ok(left),
+            };
+            if precedence < min_precedence {
+                return Ok(left);
+            }
+
+            let column = self.column();
+            self.position += 1;
+            let right = self.binary(precedence + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right), column);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, AsmError> {
+        let column = self.column();
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(*value)),
+            Some(Token::Ident(name)) if register(name).is_none() => {
+                Ok(Expr::Symbol(name.clone(), column))
+            }
+            Some(Token::Punct("$")) => Ok(Expr::Here),
+            Some(Token::Punct("-")) => Ok(Expr::Unary(UnaryOp::Negate, Box::new(self.unary()?))),
+            Some(Token::Punct("~")) => Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?))),
+            Some(Token::Punct("+")) => self.unary(),
+            Some(Token::Punct("(")) => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            _ => Err(self.location.error(column, "expected an expression")),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Located<Operand>, AsmError> {
+        let column = self.column();
+        let operand = match self.peek() {
+            Some(Token::Punct("[")) => {
+                self.position += 1;
+                match self.next() {
+                    Some(Token::Ident(name)) if register(name) == Some(Register::I) => (),
+                    _ => return Err(self.location.error(column, "expected `[I]`")),
+                }
+                self.expect("]")?;
+                Operand::Register(Register::IndirectI)
+            }
+            Some(Token::Ident(name)) if name.eq_ignore_ascii_case("long") => {
+                self.position += 1;
+                Operand::Long(self.expr()?)
+            }
+            Some(Token::Ident(name)) if register(name).is_some() => {
+                self.position += 1;
+                Operand::Register(register(name).unwrap())
+            }
+            _ => Operand::Expr(self.expr()?),
+        };
+        Ok(Located {
+            value: operand,
+            column,
+        })
+    }
+
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, AsmError>,
+    ) -> Result<Vec<T>, AsmError> {
+        let mut items = Vec::new();
+        if self.at_end() {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if !self.eat(",") {
+                return Ok(items);
+            }
+        }
+    }
+}
+
+/// Reads source lines into statements, expanding `INCLUDE`s through
+/// `include`.
+fn parse(
+    source: &str,
+    file: &str,
+    include: &mut dyn FnMut(&str) -> Result<String, String>,
+    depth: usize,
+    statements: &mut Vec<Statement>,
+) -> Result<(), AsmError> {
+    for (index, text) in source.lines().enumerate() {
+        let location = Location {
+            file: file.to_string(),
+            line: index + 1,
+        };
+        let tokens = tokenize(text, &location)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            location: &location,
+            end_column: text.chars().count() + 1,
+        };
+
+        let mut statement = Statement {
+            location: location.clone(),
+            column: 1,
+            label: None,
+            kind: None,
+        };
+
+        if let (Some(Token::Ident(name)), Some(Token::Punct(":"))) = (
+            tokens.first().map(|token| &token.value),
+            tokens.get(1).map(|token| &token.value),
+        ) {
+            statement.label = Some((name.clone(), tokens[0].column));
+            parser.position = 2;
+        }
+
+        let column = parser.column();
+        statement.column = column;
+        let directive = match parser.next() {
+            None => {
+                statements.push(statement);
+                continue;
+            }
+            Some(Token::Ident(name)) => name.clone(),
+            Some(_) => return Err(location.error(column, "expected an instruction")),
+        };
+
+        let is_equ = match parser.peek() {
+            Some(Token::Ident(word)) => word.eq_ignore_ascii_case("equ"),
+            Some(Token::Punct("=")) => true,
+            _ => false,
+        };
+        if is_equ {
+            if statement.label.is_some() {
+                return Err(location.error(column, "constants can't be labelled"));
+            }
+            parser.position += 1;
+            statement.kind = Some(Kind::Equ(directive, parser.expr()?));
+        } else {
+            let kind = match directive.to_ascii_uppercase().as_str() {
+                "DB" => Kind::Db(parser.list(|parser| {
+                    let column = parser.column();
+                    match parser.peek() {
+                        Some(Token::Str(bytes)) => {
+                            parser.position += 1;
+                            Ok(DataItem::Str(bytes.clone()))
+                        }
+                        _ => Ok(DataItem::Expr(parser.expr()?, column)),
+                    }
+                })?),
+                "DW" => Kind::Dw(parser.list(|parser| {
+                    let column = parser.column();
+                    Ok((parser.expr()?, column))
+                })?),
+                "ORG" => {
+                    let column = parser.column();
+                    Kind::Org(parser.expr()?, column)
+                }
+                "INCLUDE" => {
+                    let name = match parser.next() {
+                        Some(Token::Str(name)) => String::from_utf8_lossy(name).into_owned(),
+                        _ => return Err(location.error(column, "expected a file name")),
+                    };
+                    if !parser.at_end() {
+                        return Err(parser.error("unexpected text after the file name"));
+                    }
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(location.error(column, "includes nest too deeply"));
+                    }
+                    let included = include(&name).map_err(|err| location.error(column, err))?;
+                    if statement.label.is_some() {
+                        statements.push(statement);
+                    }
+                    parse(&included, &name, include, depth + 1, statements)?;
+                    continue;
+                }
+                _ => Kind::Instruction(directive, parser.list(Parser::operand)?),
+            };
+            statement.kind = Some(kind);
+        }
+
+        if !parser.at_end() {
+            return Err(parser.error("unexpected text"));
+        }
+        statements.push(statement);
+    }
+
+    Ok(())
+}
+
+/// The size in bytes a statement assembles to.
+fn size_of(kind: &Kind) -> usize {
+    match kind {
+        Kind::Instruction(_, operands) => {
+            let long = operands
+                .iter()
+                .any(|operand| matches!(operand.value, Operand::Long(_)));
+            if long {
+                4
+            } else {
+                2
+            }
+        }
+        Kind::Db(items) => items
+            .iter()
+            .map(|item| match item {
+                DataItem::Expr(..) => 1,
+                DataItem::Str(bytes) => bytes.len(),
+            })
+            .sum(),
+        Kind::Dw(items) => items.len() * 2,
+        Kind::Org(..) | Kind::Equ(..) => 0,
+    }
+}
+
+struct Symbols<'a> {
+    labels: &'a HashMap<String, u16>,
+    constants: &'a HashMap<String, (Expr, Location)>,
+}
+
+impl<'a> Symbols<'a> {
+    fn evaluate(&self, expr: &Expr, here: u16, location: &Location) -> Result<i64, AsmError> {
+        self.evaluate_nested(expr, here, location, 0)
+    }
+
+    fn evaluate_nested(
+        &self,
+        expr: &Expr,
+        here: u16,
+        location: &Location,
+        depth: usize,
+    ) -> Result<i64, AsmError> {
+        let value = match expr {
+            Expr::Number(value) => *value,
+            Expr::Here => here as i64,
+            Expr::Symbol(name, column) => {
+                if let Some(&address) = self.labels.get(name) {
+                    address as i64
+                } else if let Some((expr, defined_at)) = self.constants.get(name) {
+                    if depth > self.constants.len() {
+                        let message = format!("`{}` is defined in terms of itself", name);
+                        return Err(location.error(*column, message));
+                    }
+                    self.evaluate_nested(expr, here, defined_at, depth + 1)?
+                } else {
+                    return Err(location.error(*column, format!("unknown symbol `{}`", name)));
+                }
+            }
+            Expr::Unary(op, operand) => {
+                let operand = self.evaluate_nested(operand, here, location, depth)?;
+                match op {
+                    UnaryOp::Negate => operand.wrapping_neg(),
+                    UnaryOp::Not => !operand,
+                }
+            }
+            Expr::Binary(op, left, right, column) => {
+                let left = self.evaluate_nested(left, here, location, depth)?;
+                let right = self.evaluate_nested(right, here, location, depth)?;
+                match op {
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Sub => left.wrapping_sub(right),
+                    BinaryOp::Mul => left.wrapping_mul(right),
+                    BinaryOp::Div | BinaryOp::Rem if right == 0 => {
+                        return Err(location.error(*column, "division by zero"))
+                    }
+                    BinaryOp::Div => left.wrapping_div(right),
+                    BinaryOp::Rem => left.wrapping_rem(right),
+                    BinaryOp::And => left & right,
+                    BinaryOp::Or => left | right,
+                    BinaryOp::Xor => left ^ right,
+                    BinaryOp::Shl => left.checked_shl(right as u32).unwrap_or(0),
+                    BinaryOp::Shr => left.checked_shr(right as u32).unwrap_or(0),
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Evaluates instruction operands into the fields of an `Opcode`.
+struct Operands<'a> {
+    operands: &'a [Located<Operand>],
+    symbols: &'a Symbols<'a>,
+    here: u16,
+    location: &'a Location,
+}
+
+impl<'a> Operands<'a> {
+    fn shape(&self) -> Vec<Option<Register>> {
+        self.operands
+            .iter()
+            .map(|operand| match operand.value {
+                Operand::Register(register) => Some(register),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn value(&self, index: usize, min: i64, max: i64, what: &str) -> Result<u16, AsmError> {
+        let operand = &self.operands[index];
+        let expr = match &operand.value {
+            Operand::Expr(expr) | Operand::Long(expr) => expr,
+            Operand::Register(_) => unreachable!("operand shapes are checked first"),
+        };
+        let value = self.symbols.evaluate(expr, self.here, self.location)?;
+        if value < min || value > max {
+            return Err(self
+                .location
+                .error(operand.column, format!("{} out of range: {}", what, value)));
+        }
+        Ok(value as u16)
+    }
+
+    fn address(&self, index: usize) -> Result<u16, AsmError> {
+        self.value(index, 0, 0xFFF, "address")
+    }
+
+    fn byte(&self, index: usize) -> Result<u8, AsmError> {
+        Ok(self.value(index, -128, 255, "byte")? as u8)
+    }
+
+    fn nibble(&self, index: usize) -> Result<u8, AsmError> {
+        Ok(self.value(index, 0, 15, "nibble")? as u8)
+    }
+}
+
+fn encode_instruction(
+    mnemonic: &str,
+    column: usize,
+    operands: &Operands,
+) -> Result<Vec<u8>, AsmError> {
+    use Register::*;
+
+    let shape = operands.shape();
+    let upper = mnemonic.to_ascii_uppercase();
+    let long = operands
+        .operands
+        .iter()
+        .position(|operand| matches!(operand.value, Operand::Long(_)));
+    let is_long = match long {
+        Some(1) if upper == "LD" && shape[0] == Some(I) => true,
+        Some(index) => {
+            let column = operands.operands[index].column;
+            return Err(operands
+                .location
+                .error(column, "`LONG` only goes with `LD I`"));
+        }
+        None => false,
+    };
+
+    let opcode = match (upper.as_str(), shape.as_slice()) {
+        ("CLS", []) => Opcode::CLS,
+        ("RET", []) => Opcode::RET,
+        ("SCR", []) => Opcode::SCR,
+        ("SCL", []) => Opcode::SCL,
+        ("EXIT", []) => Opcode::EXIT,
+        ("LOW", []) => Opcode::LOW,
+        ("HIGH", []) => Opcode::HIGH,
+        ("AUDIO", []) => Opcode::AUDIO,
+        ("SYS", [None]) => Opcode::SYS(operands.address(0)?),
+        ("SCD", [None]) => Opcode::SCD(operands.nibble(0)?),
+        ("SCU", [None]) => Opcode::SCU(operands.nibble(0)?),
+        ("PLANE", [None]) => Opcode::PLANE(operands.nibble(0)?),
+        ("JP", [None]) => Opcode::JP(operands.address(0)?),
+        ("JP", [Some(V(0)), None]) => Opcode::JPR(operands.address(1)?),
+        ("CALL", [None]) => Opcode::CALL(operands.address(0)?),
+        ("SE", [Some(V(x)), None]) => Opcode::SE(*x, operands.byte(1)?),
+        ("SE", [Some(V(x)), Some(V(y))]) => Opcode::SER(*x, *y),
+        ("SNE", [Some(V(x)), None]) => Opcode::SNE(*x, operands.byte(1)?),
+        ("SNE", [Some(V(x)), Some(V(y))]) => Opcode::SNER(*x, *y),
+        ("SAVE", [Some(V(x)), Some(V(y))]) => Opcode::SAVE(*x, *y),
+        ("LOAD", [Some(V(x)), Some(V(y))]) => Opcode::LOAD(*x, *y),
+        ("LD", [Some(I), None]) if is_long => {
+            let address = operands.value(1, 0, 0xFFFF, "address")?;
+            let mut bytes = Opcode::LDIL.encode().to_be_bytes().to_vec();
+            bytes.extend_from_slice(&address.to_be_bytes());
+            return Ok(bytes);
+        }
+        ("LD", [Some(V(x)), None]) => Opcode::LD(*x, operands.byte(1)?),
+        ("LD", [Some(V(x)), Some(V(y))]) => Opcode::LDR(*x, *y),
+        ("LD", [Some(I), None]) => Opcode::LDI(operands.address(1)?),
+        ("LD", [Some(V(x)), Some(DT)]) => Opcode::LDDT(*x),
+        ("LD", [Some(V(x)), Some(K)]) => Opcode::LDK(*x),
+        ("LD", [Some(DT), Some(V(x))]) => Opcode::DTLD(*x),
+        ("LD", [Some(ST), Some(V(x))]) => Opcode::STLD(*x),
+        ("LD", [Some(F), Some(V(x))]) => Opcode::LDF(*x),
+        ("LD", [Some(HF), Some(V(x))]) => Opcode::LDHF(*x),
+        ("LD", [Some(B), Some(V(x))]) => Opcode::LDB(*x),
+        ("LD", [Some(IndirectI), Some(V(x))]) => Opcode::LDIR(*x),
+        ("LD", [Some(V(x)), Some(IndirectI)]) => Opcode::LDRI(*x),
+        ("LD", [Some(R), Some(V(x))]) => Opcode::STRPL(*x),
+        ("LD", [Some(V(x)), Some(R)]) => Opcode::LDRPL(*x),
+        ("LD", [Some(Pitch), Some(V(x))]) => Opcode::PITCH(*x),
+        ("ADD", [Some(V(x)), None]) => Opcode::ADD(*x, operands.byte(1)?),
+        ("ADD", [Some(V(x)), Some(V(y))]) => Opcode::ADDR(*x, *y),
+        ("ADD", [Some(I), Some(V(x))]) => Opcode::ADDI(*x),
+        ("OR", [Some(V(x)), Some(V(y))]) => Opcode::OR(*x, *y),
+        ("AND", [Some(V(x)), Some(V(y))]) => Opcode::AND(*x, *y),
+        ("XOR", [Some(V(x)), Some(V(y))]) => Opcode::XOR(*x, *y),
+        ("SUB", [Some(V(x)), Some(V(y))]) => Opcode::SUBR(*x, *y),
+        ("SUBN", [Some(V(x)), Some(V(y))]) => Opcode::SUBN(*x, *y),
+        // Without a second register the shift reads and writes Vx, so it
+        // behaves the same whether or not the shift quirk is on.
+        ("SHR", [Some(V(x))]) => Opcode::SHR(*x, *x),
+        ("SHR", [Some(V(x)), Some(V(y))]) => Opcode::SHR(*x, *y),
+        ("SHL", [Some(V(x))]) => Opcode::SHL(*x, *x),
+        ("SHL", [Some(V(x)), Some(V(y))]) => Opcode::SHL(*x, *y),
+        ("RND", [Some(V(x)), None]) => Opcode::RND(*x, operands.byte(1)?),
+        ("DRW", [Some(V(x)), Some(V(y)), None]) => Opcode::DRW(*x, *y, operands.nibble(2)?),
+        ("SKP", [Some(V(x))]) => Opcode::SKP(*x),
+        ("SKNP", [Some(V(x))]) => Opcode::SKNP(*x),
+        _ => {
+            let known = [
+                "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "AUDIO", "SYS", "SCD", "SCU",
+                "PLANE", "JP", "CALL", "SE", "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND",
+                "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP",
+            ];
+            let message = if known.contains(&upper.as_str()) {
+                format!("invalid operands for `{}`", mnemonic)
+            } else {
+                format!("unknown instruction `{}`", mnemonic)
+            };
+            return Err(operands.location.error(column, message));
+        }
+    };
+
+    Ok(opcode.encode().to_be_bytes().to_vec())
+}
+
+/// Assembles Cowgod style source into a program. `INCLUDE "name"` lines are
+/// an error; use `assemble_with_includes` to resolve them.
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    assemble_with_includes(source, "<source>", &mut |name| {
+        Err(format!("can't include `{}` here", name))
+    })
+}
+
+/// Assembles Cowgod style source into a program, reading `INCLUDE`d files
+/// through `include`. `file` names the source in errors.
+///
+/// Besides the instructions, the source can hold `label:` definitions,
+/// `NAME EQU expr` (or `NAME = expr`) constants, `DB` bytes and strings,
+/// big endian `DW` words and `ORG address`. Expressions use C operators,
+/// `$` for the current address and decimal, `0x` or `0b` numbers.
+pub fn assemble_with_includes(
+    source: &str,
+    file: &str,
+    include: &mut dyn FnMut(&str) -> Result<String, String>,
+) -> Result<Program, AsmError> {
+    let mut statements = Vec::new();
+    parse(source, file, include, 0, &mut statements)?;
+
+    // First pass: lay the statements out and collect the symbols.
+    let mut labels = HashMap::new();
+    let mut constants = HashMap::new();
+    let mut addresses = Vec::with_capacity(statements.len());
+    let mut origin = None;
+    let mut address = DEFAULT_ORIGIN as i64;
+    for statement in statements.iter() {
+        if let Some((name, column)) = &statement.label {
+            if labels.contains_key(name) || constants.contains_key(name) {
+                let message = format!("`{}` is already defined", name);
+                return Err(statement.location.error(*column, message));
+            }
+            labels.insert(name.clone(), address as u16);
+        }
+
+        match &statement.kind {
+            Some(Kind::Equ(name, expr)) => {
+                if labels.contains_key(name) || constants.contains_key(name) {
+                    let message = format!("`{}` is already defined", name);
+                    return Err(statement.location.error(statement.column, message));
+                }
+                constants.insert(name.clone(), (expr.clone(), statement.location.clone()));
+            }
+            Some(Kind::Org(expr, column)) => {
+                let symbols = Symbols {
+                    labels: &labels,
+                    constants: &constants,
+                };
+                let target = symbols.evaluate(expr, address as u16, &statement.location)?;
+                let started = origin.is_some();
+                if !(0..=0xFFFF).contains(&target) || (started && target < address) {
+                    let message = format!("can't move the origin to {:#x}", target);
+                    return Err(statement.location.error(*column, message));
+                }
+                if !started {
+                    for label in labels.values_mut() {
+                        *label = target as u16;
+                    }
+                }
+                address = target;
+            }
+            _ => (),
+        }
+
+        let size = statement.kind.as_ref().map_or(0, size_of);
+        if size > 0 && origin.is_none() {
+            origin = Some(address);
+        }
+        addresses.push(address);
+        address += size as i64;
+        if address > 0x10000 {
+            return Err(statement
+                .location
+                .error(statement.column, "program too large"));
+        }
+    }
+
+    // Second pass: emit the bytes with every symbol known.
+    let origin = origin.unwrap_or(address);
+    let symbols = Symbols {
+        labels: &labels,
+        constants: &constants,
+    };
+    let mut rom = Vec::new();
+    for (statement, &address) in statements.iter().zip(addresses.iter()) {
+        let location = &statement.location;
+        let here = address as u16;
+        let offset = (address - origin) as usize;
+        if rom.len() < offset {
+            rom.resize(offset, 0);
+        }
+
+        match &statement.kind {
+            Some(Kind::Instruction(mnemonic, operands)) => {
+                let operands = Operands {
+                    operands,
+                    symbols: &symbols,
+                    here,
+                    location,
+                };
+                rom.extend(encode_instruction(mnemonic, statement.column, &operands)?);
+            }
+            Some(Kind::Db(items)) => {
+                for item in items {
+                    match item {
+                        DataItem::Str(bytes) => rom.extend_from_slice(bytes),
+                        DataItem::Expr(expr, column) => {
+                            let value = symbols.evaluate(expr, here, location)?;
+                            if !(-128..=255).contains(&value) {
+                                let message = format!("byte out of range: {}", value);
+                                return Err(location.error(*column, message));
+                            }
+                            rom.push(value as u8);
+                        }
+                    }
+                }
+            }
+            Some(Kind::Dw(items)) => {
+                for (expr, column) in items {
+                    let value = symbols.evaluate(expr, here, location)?;
+                    if !(-32768..=0xFFFF).contains(&value) {
+                        let message = format!("word out of range: {}", value);
+                        return Err(location.error(*column, message));
+                    }
+                    rom.extend_from_slice(&(value as u16).to_be_bytes());
+                }
+            }
+            Some(Kind::Equ(_, expr)) => {
+                symbols.evaluate(expr, here, location)?;
+            }
+            Some(Kind::Org(..)) | None => (),
+        }
+    }
+
+    Ok(Program {
+        origin: origin as u16,
+        rom,
+        symbols: labels.into_iter().collect(),
+        breakpoints: BTreeMap::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::disasm::{disassemble, Syntax};
+    use crate::chip8::rng::Rng;
+
+    fn rom(source: &str) -> Vec<u8> {
+        match assemble(source) {
+            Ok(program) => program.rom,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn error(source: &str) -> (usize, usize, String) {
+        let err = assemble(source).unwrap_err();
+        (err.line, err.column, err.message)
+    }
+
+    #[test]
+    fn assembles_every_instruction_form() {
+        let source = "
+            CLS
+            RET
+            SYS 0x123
+            SCD 10
+            SCU 4
+            SCR
+            SCL
+            EXIT
+            LOW
+            HIGH
+            JP 0x345
+            JP V0, 0x678
+            CALL 0x456
+            SE V1, 0x23
+            SE V3, V4
+            SNE V2, 0x34
+            SNE VF, V0
+            SAVE V1, V5
+            LOAD V5, V1
+            LD V4, 0x56
+            LD V6, V7
+            LD I, 0x567
+            LD I, LONG 0xABCD
+            LD V4, DT
+            LD V5, K
+            LD DT, V6
+            LD ST, V7
+            LD F, V9
+            LD HF, VD
+            LD B, VA
+            LD [I], VB
+            LD VC, [I]
+            LD R, VE
+            LD VF, R
+            LD PITCH, V9
+            ADD V5, 0x67
+            ADD VA, VB
+            ADD I, V8
+            OR V7, V8
+            AND V8, V9
+            XOR V9, VA
+            SUB VB, VC
+            SUBN VD, VE
+            SHR VC, VD
+            SHL VE, VF
+            RND V1, 0xFF
+            DRW V1, V2, 3
+            SKP V2
+            SKNP V3
+            PLANE 3
+            AUDIO
+        ";
+
+        let words: Vec<u16> = rom(source)
+            .chunks(2)
+            .map(|pair| (pair[0] as u16) << 8 | pair[1] as u16)
+            .collect();
+
+        assert_eq!(
+            vec![
+                0x00E0, 0x00EE, 0x0123, 0x00CA, 0x00D4, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF,
+                0x1345, 0xB678, 0x2456, 0x3123, 0x5340, 0x4234, 0x9F00, 0x5152, 0x5513, 0x6456,
+                0x8670, 0xA567, 0xF000, 0xABCD, 0xF407, 0xF50A, 0xF615, 0xF718, 0xF929, 0xFD30,
+                0xFA33, 0xFB55, 0xFC65, 0xFE75, 0xFF85, 0xF93A, 0x7567, 0x8AB4, 0xF81E, 0x8781,
+                0x8892, 0x89A3, 0x8BC5, 0x8DE7, 0x8CD6, 0x8EFE, 0xC1FF, 0xD123, 0xE29E, 0xE3A1,
+                0xF301, 0xF002,
+            ],
+            words
+        );
+    }
+
+    #[test]
+    fn mnemonics_and_registers_are_case_insensitive() {
+        assert_eq!(vec![0x6A, 0x01, 0xFB, 0x65], rom("ld va, 1\nLd vB, [i]"));
+    }
+
+    #[test]
+    fn labels_resolve_forwards_and_backwards() {
+        let program = assemble(
+            "start:  CALL sub    ; forward\n\
+             \x20       JP start\n\
+             sub:    RET",
+        )
+        .unwrap();
+
+        assert_eq!(vec![0x22, 0x04, 0x12, 0x00, 0x00, 0xEE], program.rom);
+        assert_eq!(Some(&0x204), program.symbols.get("sub"));
+        assert_eq!(0x200, program.origin);
+    }
+
+    #[test]
+    fn constants_and_expressions() {
+        let source = "
+            SPEED EQU BASE * 2 + 1
+            BASE = 0x10
+            LD V0, SPEED
+            LD V1, (1 << 4 | 3) & ~1
+            LD V2, -1
+            LD V3, 'A'
+            LD I, sprite + 2
+            JP $
+            sprite: DB 0b1111_0000, END - sprite, \"hi\"
+            DW 0x1234, $
+            END:
+        ";
+
+        assert_eq!(
+            vec![
+                0x60, 0x21, 0x61, 0x12, 0x62, 0xFF, 0x63, 0x41, 0xA2, 0x0E, 0x12, 0x0A, 0xF0, 0x08,
+                0x68, 0x69, 0x12, 0x34, 0x02, 0x10,
+            ],
+            rom(source)
+        );
+    }
+
+    #[test]
+    fn strings_take_the_usual_escapes() {
+        assert_eq!(
+            vec![b'a', b'\n', b'\t', b'\r', 0, b'\\', b'"', b'\''],
+            rom(r#"DB "a\n\t\r\0\\\"\'""#)
+        );
+        assert_eq!(vec![0x60, b'\n'], rom(r"LD V0, '\n'"));
+    }
+
+    #[test]
+    fn org_sets_the_origin_and_pads_forward() {
+        let program = assemble("ORG 0x600\nstart: JP start\nORG 0x606\nDB 1").unwrap();
+
+        assert_eq!(0x600, program.origin);
+        assert_eq!(vec![0x16, 0x00, 0, 0, 0, 0, 1], program.rom);
+    }
+
+    #[test]
+    fn includes_are_read_through_the_callback() {
+        let mut include = |name: &str| match name {
+            "sprites.asm" => Ok("ball: DB 0x80\nINCLUDE \"consts.asm\"".to_string()),
+            "consts.asm" => Ok("X = 3".to_string()),
+            _ => Err(format!("no such file: {}", name)),
+        };
+
+        let program = assemble_with_includes(
+            "LD I, ball\nLD V0, X\nINCLUDE \"sprites.asm\"",
+            "main.asm",
+            &mut include,
+        )
+        .unwrap();
+        assert_eq!(vec![0xA2, 0x04, 0x60, 0x03, 0x80], program.rom);
+
+        let err =
+            assemble_with_includes("\nINCLUDE \"nope.asm\"", "main.asm", &mut include).unwrap_err();
+        assert_eq!("main.asm:2:1: no such file: nope.asm", err.to_string());
+    }
+
+    #[test]
+    fn errors_in_included_files_name_the_file() {
+        let mut include = |_: &str| Ok("CLS\n  BOGUS".to_string());
+
+        let err =
+            assemble_with_includes("INCLUDE \"lib.asm\"", "main.asm", &mut include).unwrap_err();
+
+        assert_eq!("lib.asm:2:3: unknown instruction `BOGUS`", err.to_string());
+    }
+
+    #[test]
+    fn recursive_includes_are_rejected() {
+        let mut include = |_: &str| Ok("INCLUDE \"self.asm\"".to_string());
+
+        let err =
+            assemble_with_includes("INCLUDE \"self.asm\"", "main.asm", &mut include).unwrap_err();
+
+        assert_eq!("includes nest too deeply", err.message);
+    }
+
+    #[test]
+    fn errors_point_at_line_and_column() {
+        assert_eq!(
+            (2, 5, "unknown instruction `MOV`".to_string()),
+            error("CLS\n    MOV V1, V2")
+        );
+        assert_eq!(
+            (1, 1, "invalid operands for `DRW`".to_string()),
+            error("DRW V1, 3, 4")
+        );
+        assert_eq!(
+            (1, 8, "byte out of range: 256".to_string()),
+            error("LD V1, 0x100")
+        );
+        assert_eq!(
+            (1, 4, "address out of range: 4096".to_string()),
+            error("JP 0x1000")
+        );
+        assert_eq!(
+            (1, 15, "unknown symbol `missing`".to_string()),
+            error("LD I, 1 + 2 * missing")
+        );
+        assert_eq!(
+            (2, 1, "`a` is already defined".to_string()),
+            error("a: CLS\na: CLS")
+        );
+        assert_eq!((1, 8, "unexpected text".to_string()), error("SKP V1 V2"));
+        assert_eq!(
+            (1, 7, "unterminated string".to_string()),
+            error("DB 1, \"abc")
+        );
+        assert_eq!(
+            (1, 6, "strings must be ASCII".to_string()),
+            error("DB \"\\é\"")
+        );
+        assert_eq!(
+            (1, 5, "strings must be ASCII".to_string()),
+            error("DB \"é\"")
+        );
+        assert_eq!(
+            (1, 5, "unknown escape `\\q`".to_string()),
+            error("DB \"\\q\"")
+        );
+        assert_eq!(
+            (1, 11, "division by zero".to_string()),
+            error("LD V0, 10 / 0")
+        );
+        assert_eq!(
+            (2, 7, "`SIZE` is defined in terms of itself".to_string()),
+            error("SIZE = LEN\nLEN = SIZE + 1\nLD V0, SIZE")
+        );
+        assert_eq!(
+            (2, 5, "can't move the origin to 0x100".to_string()),
+            error("DB 1\nORG 0x100")
+        );
+        assert_eq!(
+            (1, 8, "`LONG` only goes with `LD I`".to_string()),
+            error("LD V1, LONG 5")
+        );
+    }
+
+    #[test]
+    fn disassembled_roms_reassemble_byte_for_byte() {
+        let tetris = include_bytes!("../../roms/tetris.rom");
+        let listing = disassemble(tetris, DEFAULT_ORIGIN, Syntax::Cowgod);
+
+        assert_eq!(&tetris[..], &rom(&listing)[..]);
+    }
+
+    #[test]
+    fn random_roms_reassemble_byte_for_byte() {
+        let mut rng = Rng::new(12);
+
+        for size in 1..200 {
+            let bytes: Vec<u8> = (0..size).map(|_| rng.next_u8()).collect();
+            for &origin in &[0x200, 0x600] {
+                let listing = disassemble(&bytes, origin, Syntax::Cowgod);
+                let program =
+                    assemble(&listing).unwrap_or_else(|err| panic!("{}\n{}", err, listing));
+
+                assert_eq!(bytes, program.rom, "{}", listing);
+                assert_eq!(origin, program.origin);
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::chip8::state::{StateError, StateReader, StateWriter};
+
+/// Number of bytes in the XO-CHIP audio pattern buffer.
+pub const PATTERN_LEN: usize = 16;
+
+/// The pitch register value that plays the pattern at 4000 bits per second.
+const DEFAULT_PITCH: u8 = 64;
+
+/// The `Audio` type. Holds the XO-CHIP audio state: a 128 bit pattern that
+/// loops while the sound timer is running, and the pitch register that sets
+/// how fast it plays.
+pub struct Audio {
+    pub pattern: [u8; PATTERN_LEN],
+    pub pitch: u8,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Audio {
+            // A square wave, so programs that never touch the pattern buffer
+            // still beep.
+            pattern: [
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+                0xFF, 0xFF,
+            ],
+            pitch: DEFAULT_PITCH,
+        }
+    }
+}
+
+impl Audio {
+    pub fn new() -> Audio {
+        Audio::default()
+    }
+
+    /// The rate the pattern's bits are played back at, in bits per second.
+    pub fn playback_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - DEFAULT_PITCH as f64) / 48.0)
+    }
+
+    pub(crate) fn save(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.pattern);
+        writer.u8(self.pitch);
+    }
+
+    pub(crate) fn load(reader: &mut StateReader) -> Result<Audio, StateError> {
+        let mut pattern = [0; PATTERN_LEN];
+        pattern.copy_from_slice(reader.bytes(PATTERN_LEN)?);
+
+        Ok(Audio {
+            pattern,
+            pitch: reader.u8()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_pitch_plays_at_4000_hz() {
+        assert_eq!(4000.0, Audio::new().playback_rate());
+    }
+
+    #[test]
+    fn pitch_raises_rate_by_an_octave_every_48_steps() {
+        let mut audio = Audio::new();
+
+        audio.pitch = 112;
+
+        assert_eq!(8000.0, audio.playback_rate());
+    }
+}
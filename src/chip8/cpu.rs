@@ -1,8 +1,21 @@
+use crate::chip8::audio::Audio;
+use crate::chip8::debug::{AccessKind, MemoryAccess};
+use crate::chip8::decode_cache::DecodeCache;
+use crate::chip8::fault::{CpuFault, FaultKind, StepOutcome};
+use crate::chip8::font::{check_font, Font, FontError, FontSize, BIG_FONT_ADDRESS, FONT_ADDRESS};
+use crate::chip8::keypad::Keypad;
 use crate::chip8::opcode;
 use crate::chip8::opcode::Opcode;
+use crate::chip8::platform::Platform;
+use crate::chip8::profile::Profiler;
+use crate::chip8::quirks::Quirks;
+use crate::chip8::rng::Rng;
+use crate::chip8::state::{StateError, StateReader, StateWriter};
+use crate::chip8::timer::Timers;
+use crate::chip8::trace::Trace;
 use crate::chip8::Screen;
-use rand;
-use rand::Rng;
+use std::fmt;
+use std::ops::Range;
 
 pub struct Cpu {
     i: u16,
@@ -10,102 +23,634 @@ pub struct Cpu {
     register: [u8; 16],
     stack: [u16; 16],
     sp: u8,
-    memory: [u8; 4096],
-    soundtimer: u8,
-    delaytimer: u8,
-    pub key_state: u8,
+    memory: Vec<u8>,
+    /// The glyphs FX29 and FX30 point I at, kept so `reset` can put them
+    /// back.
+    font: Vec<u8>,
+    font_address: u16,
+    big_font: Vec<u8>,
+    big_font_address: u16,
+    /// `None` when turned off with `set_decode_cache`.
+    decode_cache: Option<DecodeCache>,
+    pub timers: Timers,
+    pub audio: Audio,
+    rng: Rng,
+    pub keypad: Keypad,
+    waiting_for_key: bool,
+    waiting_for_frame: bool,
+    rpl: [u8; 16],
+    exited: bool,
+    last_access: Option<MemoryAccess>,
+    trace: Option<Trace>,
+    profiler: Option<Profiler>,
+    pub platform: Platform,
+    pub quirks: Quirks,
     pub screen: Screen,
 }
 
-const FONT_START: usize = 0x50;
-const ROM_START: usize = 0x200;
-
-#[cfg_attr(rustfmt, rustfmt_skip)]
-static FONTS: &'static [u8] =
-&[
-  0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-  0x20, 0x60, 0x20, 0x20, 0x70, // 1
-  0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-  0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-  0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-  0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-  0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-  0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-  0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-  0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-  0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-  0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-  0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-  0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-  0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-  0xF0, 0x80, 0xF0, 0x80, 0x80  // F
-];
+const ROM_START: u16 = 0x200;
 
 impl Default for Cpu {
     fn default() -> Self {
-        let mut memory = [0u8; 4096];
-        for i in 0..FONTS.len() {
-            memory[i + FONT_START] = FONTS[i];
-        }
-        Cpu {
+        let mut cpu = Cpu {
             i: 0,
             pc: 0,
             register: [0u8; 16],
             stack: [0u16; 16],
             sp: 0,
-            memory: memory,
-            soundtimer: 0,
-            delaytimer: 0,
+            memory: Vec::new(),
+            font: Font::default().glyphs().to_vec(),
+            font_address: FONT_ADDRESS,
+            big_font: Font::SuperChipBig.glyphs().to_vec(),
+            big_font_address: BIG_FONT_ADDRESS,
+            decode_cache: Some(DecodeCache::new(0)),
+            timers: Timers::new(),
+            audio: Audio::new(),
+            rng: Rng::default(),
             screen: Screen::new(),
-            key_state: 0,
+            keypad: Keypad::new(),
+            waiting_for_key: false,
+            waiting_for_frame: false,
+            rpl: [0u8; 16],
+            exited: false,
+            last_access: None,
+            trace: None,
+            profiler: None,
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+        };
+        cpu.reset();
+        cpu
+    }
+}
+
+/// Returned when a rom can't be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// The load address lies past the end of memory.
+    AddressOutOfRange(u16),
+    /// The rom is `len` bytes but only `max` fit between the load address and
+    /// the end of memory.
+    TooLarge { len: usize, max: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::AddressOutOfRange(address) => {
+                write!(f, "load address {:#06x} is past the end of memory", address)
+            }
+            LoadError::TooLarge { len, max } => {
+                write!(f, "rom is {} bytes, but only {} fit in memory", len, max)
+            }
         }
     }
 }
 
 impl Cpu {
-    pub fn new() -> Cpu {
-        Cpu::default()
+    pub fn new(quirks: Quirks) -> Cpu {
+        Cpu {
+            quirks,
+            ..Cpu::default()
+        }
     }
 
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        for i in 0..rom.len() {
-            self.memory[(ROM_START + i) as usize] = rom[i];
+    /// Creates a cpu for `platform`, using the quirks its programs expect.
+    pub fn for_platform(platform: Platform) -> Cpu {
+        let mut cpu = Cpu::default();
+        cpu.set_platform(platform);
+        cpu
+    }
+
+    /// Switches to `platform` and the quirks its programs expect, growing or
+    /// shrinking memory to the platform's size.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        self.quirks = platform.quirks();
+        self.memory.resize(platform.memory_size(), 0);
+        self.invalidate_decode_cache();
+    }
+
+    /// Copies a built-in font to `address` and points FX29, or FX30 for a
+    /// big font, at it.
+    pub fn load_font(&mut self, font: Font, address: u16) -> Result<(), FontError> {
+        self.load_custom_font(font.glyphs(), font.size(), address)
+    }
+
+    /// Copies `glyphs` to `address` and points FX29 or FX30 at them. Small
+    /// fonts need all sixteen 5 byte glyphs, big fonts ten or sixteen 10 byte
+    /// ones. On error memory is left untouched.
+    pub fn load_custom_font(
+        &mut self,
+        glyphs: &[u8],
+        size: FontSize,
+        address: u16,
+    ) -> Result<(), FontError> {
+        check_font(glyphs, size)?;
+        let start = address as usize;
+        if start + glyphs.len() > self.memory.len() {
+            return Err(FontError::OutOfMemory(address));
+        }
+
+        self.memory[start..start + glyphs.len()].copy_from_slice(glyphs);
+        self.invalidate_decode_cache();
+        match size {
+            FontSize::Small => {
+                self.font = glyphs.to_vec();
+                self.font_address = address;
+            }
+            FontSize::Big => {
+                self.big_font = glyphs.to_vec();
+                self.big_font_address = address;
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts the machine back the way it powers on: memory holds nothing but
+    /// the fonts, and the registers, stack, timers, audio and screen are
+    /// cleared. The platform, quirks, fonts, held keys and random number
+    /// generator are kept, and the trace and profiler start over.
+    pub fn reset(&mut self) {
+        self.memory.clear();
+        self.memory.resize(self.platform.memory_size(), 0);
+        for (glyphs, address) in [
+            (&self.font, self.font_address),
+            (&self.big_font, self.big_font_address),
+        ]
+        .iter()
+        {
+            // A font loaded high in XO-CHIP memory is gone once the
+            // platform shrinks it.
+            let start = *address as usize;
+            if let Some(memory) = self.memory.get_mut(start..start + glyphs.len()) {
+                memory.copy_from_slice(glyphs);
+            }
+        }
+        self.invalidate_decode_cache();
+
+        self.i = 0;
+        self.pc = 0;
+        self.register = [0; 16];
+        self.stack = [0; 16];
+        self.sp = 0;
+        self.rpl = [0; 16];
+        self.timers = Timers::new();
+        self.audio = Audio::new();
+        self.screen = Screen::new();
+        self.screen.set_dirty();
+        self.keypad.clear_events();
+        self.waiting_for_key = false;
+        self.waiting_for_frame = false;
+        self.exited = false;
+        self.last_access = None;
+        if let Some(trace) = self.trace.as_mut() {
+            trace.clear();
+        }
+        if let Some(profiler) = self.profiler.as_mut() {
+            *profiler = Profiler::new();
         }
+    }
+
+    /// Resets the machine and loads `rom` at 0x200, where nearly every
+    /// program starts.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        self.load_rom_at(rom, ROM_START)
+    }
+
+    /// Resets the machine and loads `rom` at `address`, starting execution
+    /// there: ETI-660 programs, for one, start at 0x600. On error the cpu is
+    /// left untouched.
+    pub fn load_rom_at(&mut self, rom: &[u8], address: u16) -> Result<(), LoadError> {
+        self.platform.check_rom(rom, address)?;
 
-        self.pc = ROM_START as u16;
+        let start = address as usize;
+        self.reset();
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
+        self.pc = address;
+        Ok(())
     }
 
-    pub fn step(&mut self) {
-        let opcode = self.get_opcode();
-        self.pc += 2;
+    /// Fetches, decodes and executes a single instruction. When the
+    /// instruction faults the program counter is left pointing at it.
+    pub fn step(&mut self) -> StepOutcome {
+        self.last_access = None;
+        if self.exited {
+            return StepOutcome::Exited;
+        }
 
-        self.execute(opcode);
+        if self.waiting_for_frame {
+            return StepOutcome::Waiting;
+        }
 
-        if self.delaytimer > 0 {
-            self.delaytimer -= 1;
+        let pc = self.pc;
+        let (opcode, decoded) = match self.fetch() {
+            Some(entry) => entry,
+            None => {
+                return StepOutcome::Fault(CpuFault {
+                    pc,
+                    opcode: 0,
+                    kind: FaultKind::PcOutOfBounds,
+                })
+            }
+        };
+        self.pc = self.pc.wrapping_add(2);
+
+        // Only a traced cpu pays for copying the state before executing.
+        let before = self
+            .trace
+            .as_ref()
+            .map(|_| (self.register, self.i, self.word_at(self.pc).unwrap_or(0)));
+        let result = match decoded {
+            Some(op) => self.dispatch(op),
+            None => Err(FaultKind::UnknownOpcode),
+        };
+        if let (Some(trace), Some((register, i, long))) = (self.trace.as_mut(), before) {
+            let fault = result.err();
+            trace.record(
+                pc,
+                opcode,
+                long,
+                (register, i),
+                &self.register,
+                self.i,
+                fault,
+            );
         }
 
-        if self.soundtimer > 0 {
-            self.soundtimer -= 1;
+        if let Err(kind) = result {
+            self.pc = pc;
+            return StepOutcome::Fault(CpuFault { pc, opcode, kind });
         }
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(pc, opcode, self.pc);
+        }
+
+        if self.exited {
+            return StepOutcome::Exited;
+        }
+
+        StepOutcome::Executed
     }
 
-    fn get_opcode(&self) -> u16 {
-        (self.memory[self.pc as usize] as u16) << 8 | self.memory[(self.pc + 1) as usize] as u16
+    /// Runs one 60 Hz frame: up to `cycles` instructions followed by a timer
+    /// tick. The frame ends early when the cpu waits for the display, exits
+    /// or faults; the timers are not ticked after an exit or fault. Returns
+    /// the outcome of the last step.
+    pub fn run_frame(&mut self, cycles: u32) -> StepOutcome {
+        let mut outcome = StepOutcome::Executed;
+        for _ in 0..cycles {
+            outcome = self.step();
+            if outcome != StepOutcome::Executed {
+                break;
+            }
+        }
+
+        match outcome {
+            StepOutcome::Executed | StepOutcome::Waiting => self.tick_timers(),
+            StepOutcome::Exited | StepOutcome::Fault(_) => (),
+        }
+        outcome
     }
 
-    fn execute(&mut self, opcode: u16) {
-        let op = opcode::decode(opcode);
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    /// The general purpose registers V0 to VF.
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.register
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// The return addresses currently on the stack, oldest first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// The memory the last `step` read or wrote through I, if any.
+    pub fn last_access(&self) -> Option<MemoryAccess> {
+        self.last_access
+    }
+
+    /// Starts recording the last `capacity` executed instructions, replacing
+    /// any trace recorded so far.
+    pub fn enable_trace(&mut self, capacity: usize) {
+        self.trace = Some(Trace::new(capacity));
+    }
+
+    pub fn disable_trace(&mut self) {
+        self.trace = None;
+    }
+
+    /// The instructions recorded since `enable_trace`, if tracing.
+    pub fn trace(&self) -> Option<&Trace> {
+        self.trace.as_ref()
+    }
+
+    /// Whether `step` would execute an instruction rather than wait for the
+    /// display or report an exit.
+    pub(crate) fn is_running(&self) -> bool {
+        !self.exited && !self.waiting_for_frame
+    }
+
+    /// Applies the state a compiled block left behind.
+    pub(crate) fn finish_block(&mut self, registers: [u8; 16], i: u16, pc: u16) {
+        self.register = registers;
+        self.i = i;
+        self.pc = pc;
+    }
+
+    /// Turns the decode cache on or off. It's on by default; with it off
+    /// every instruction is decoded afresh, which is only useful to compare
+    /// against.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = if enabled {
+            Some(DecodeCache::new(self.memory.len()))
+        } else {
+            None
+        };
+    }
+
+    /// Starts counting executed instructions, draws and frames, replacing
+    /// any profile recorded so far.
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn disable_profiler(&mut self) {
+        self.profiler = None;
+    }
 
+    /// The profile recorded since `enable_profiler`, if profiling.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    /// Snapshots the whole machine: registers, memory, timers, keypad,
+    /// screen and random number generator. Restore it with `load_state`.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        writer.platform(self.platform);
+        writer.quirks(&self.quirks);
+        writer.u16(self.i);
+        writer.u16(self.pc);
+        writer.bytes(&self.register);
+        for address in self.stack.iter() {
+            writer.u16(*address);
+        }
+        writer.u8(self.sp);
+        writer.bytes(&self.rpl);
+        for (glyphs, address) in [
+            (&self.font, self.font_address),
+            (&self.big_font, self.big_font_address),
+        ]
+        .iter()
+        {
+            writer.u8(glyphs.len() as u8);
+            writer.bytes(glyphs);
+            writer.u16(*address);
+        }
+        writer.bool(self.waiting_for_key);
+        writer.bool(self.waiting_for_frame);
+        writer.bool(self.exited);
+        writer.u64(self.rng.state());
+        self.timers.save(&mut writer);
+        self.audio.save(&mut writer);
+        self.keypad.save(&mut writer);
+        self.screen.save(&mut writer);
+        writer.bytes(&self.memory);
+
+        writer.finish()
+    }
+
+    /// Restores a snapshot taken by `save_state`. On error the cpu is left
+    /// untouched.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::open(state)?;
+
+        let platform = reader.platform()?;
+        let quirks = reader.quirks()?;
+        let i = reader.u16()?;
+        let pc = reader.u16()?;
+        let mut register = [0u8; 16];
+        register.copy_from_slice(reader.bytes(16)?);
+        let mut stack = [0u16; 16];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+        let sp = reader.u8()?;
+        if sp as usize > stack.len() {
+            return Err(StateError::Invalid("stack pointer out of range"));
+        }
+        let mut rpl = [0u8; 16];
+        rpl.copy_from_slice(reader.bytes(16)?);
+        let mut fonts = Vec::new();
+        for &size in [FontSize::Small, FontSize::Big].iter() {
+            let len = reader.u8()? as usize;
+            let glyphs = reader.bytes(len)?.to_vec();
+            check_font(&glyphs, size)
+                .map_err(|_| StateError::Invalid("font has the wrong length"))?;
+            fonts.push((glyphs, reader.u16()?));
+        }
+        let (big_font, big_font_address) = fonts.pop().unwrap();
+        let (font, font_address) = fonts.pop().unwrap();
+        let waiting_for_key = reader.bool()?;
+        let waiting_for_frame = reader.bool()?;
+        let exited = reader.bool()?;
+        let rng = Rng::from_state(reader.u64()?)
+            .ok_or(StateError::Invalid("random number generator state is zero"))?;
+        let timers = Timers::load(&mut reader)?;
+        let audio = Audio::load(&mut reader)?;
+        let keypad = Keypad::load(&mut reader)?;
+        let screen = Screen::load(&mut reader)?;
+        let memory = reader.bytes(platform.memory_size())?.to_vec();
+        reader.finish()?;
+
+        *self = Cpu {
+            i,
+            pc,
+            register,
+            stack,
+            sp,
+            memory,
+            font,
+            font_address,
+            big_font,
+            big_font_address,
+            timers,
+            audio,
+            rng,
+            keypad,
+            waiting_for_key,
+            waiting_for_frame,
+            rpl,
+            exited,
+            decode_cache: self.decode_cache.take().map(|mut cache| {
+                cache.reset(platform.memory_size());
+                cache
+            }),
+            last_access: None,
+            trace: self.trace.take(),
+            profiler: self.profiler.take(),
+            platform,
+            quirks,
+            screen,
+        };
+
+        Ok(())
+    }
+
+    /// Reseeds the random number generator used by CXNN. Two cpus with the
+    /// same seed, program and input produce the same results.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Counts the delay and sound timers down by one and starts a new frame.
+    /// Call this once per 60 Hz frame, or use `update_timers` to drive them
+    /// from elapsed time.
+    pub fn tick_timers(&mut self) {
+        self.timers.tick();
+        self.waiting_for_frame = false;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.end_frame();
+        }
+    }
+
+    pub fn update_timers(&mut self, elapsed_secs: f64) {
+        let ticks = self.timers.update(elapsed_secs);
+        if ticks > 0 {
+            self.waiting_for_frame = false;
+        }
+        if let Some(profiler) = self.profiler.as_mut() {
+            for _ in 0..ticks {
+                profiler.end_frame();
+            }
+        }
+    }
+
+    /// The opcode at the program counter and what it decodes to, from the
+    /// decode cache when it's on.
+    fn fetch(&mut self) -> Option<(u16, Option<Opcode>)> {
+        match self.decode_cache.as_mut() {
+            Some(cache) => cache.fetch(&self.memory, self.pc),
+            None => {
+                let opcode = self.word_at(self.pc)?;
+                Some((opcode, opcode::decode(opcode).ok()))
+            }
+        }
+    }
+
+    fn invalidate_decode_cache(&mut self) {
+        if let Some(cache) = self.decode_cache.as_mut() {
+            cache.reset(self.memory.len());
+        }
+    }
+
+    pub(crate) fn word_at(&self, address: u16) -> Option<u16> {
+        let address = address as usize;
+        if address + 1 >= self.memory.len() {
+            return None;
+        }
+
+        Some((self.memory[address] as u16) << 8 | self.memory[address + 1] as u16)
+    }
+
+    /// Skips the next instruction. On XO-CHIP that may be the four byte
+    /// F000 NNNN.
+    fn skip(&mut self) {
+        let next = self.word_at(self.pc);
+        if self.platform.has_xo_chip() && next == Some(0xF000) {
+            self.pc = self.pc.wrapping_add(4);
+        } else {
+            self.pc = self.pc.wrapping_add(2);
+        }
+    }
+
+    /// Returns the memory range `start..start + len`, or a fault if any part
+    /// of it lies outside of memory.
+    fn memory_range(&self, start: u16, len: usize) -> Result<Range<usize>, FaultKind> {
+        let start = start as usize;
+        if start + len > self.memory.len() {
+            return Err(FaultKind::MemoryOutOfBounds(start.max(self.memory.len())));
+        }
+
+        Ok(start..start + len)
+    }
+
+    /// Like `memory_range`, remembering the range as read by this step.
+    fn read_range(&mut self, start: u16, len: usize) -> Result<Range<usize>, FaultKind> {
+        self.access_range(start, len, AccessKind::Read)
+    }
+
+    /// Like `memory_range`, remembering the range as written by this step.
+    fn write_range(&mut self, start: u16, len: usize) -> Result<Range<usize>, FaultKind> {
+        self.access_range(start, len, AccessKind::Write)
+    }
+
+    fn access_range(
+        &mut self,
+        start: u16,
+        len: usize,
+        kind: AccessKind,
+    ) -> Result<Range<usize>, FaultKind> {
+        let range = self.memory_range(start, len)?;
+        if let (AccessKind::Write, Some(cache)) = (kind, self.decode_cache.as_mut()) {
+            cache.invalidate(range.clone());
+        }
+        self.last_access = Some(MemoryAccess {
+            kind,
+            start,
+            len: len as u16,
+        });
+        Ok(range)
+    }
+
+    /// Decodes and runs a raw opcode, for tests that don't go through
+    /// memory.
+    #[cfg(test)]
+    fn execute(&mut self, opcode: u16) -> Result<(), FaultKind> {
+        let op = opcode::decode(opcode).map_err(|_| FaultKind::UnknownOpcode)?;
+        self.dispatch(op)
+    }
+
+    fn dispatch(&mut self, op: Opcode) -> Result<(), FaultKind> {
         match op {
-            Opcode::SYS => (),
+            Opcode::SYS(_) => (),
             Opcode::CLS => self.screen.clear(),
-            Opcode::RET => self.ret(),
+            Opcode::RET => self.ret()?,
+            Opcode::SCD(n) => self.super_chip()?.screen.scroll_down(n as usize),
+            Opcode::SCU(n) => self.xo_chip()?.screen.scroll_up(n as usize),
+            Opcode::SCR => self.super_chip()?.screen.scroll_right(4),
+            Opcode::SCL => self.super_chip()?.screen.scroll_left(4),
+            Opcode::EXIT => self.super_chip()?.exited = true,
+            Opcode::LOW => self.super_chip()?.screen.set_hires(false),
+            Opcode::HIGH => self.super_chip()?.screen.set_hires(true),
             Opcode::JP(address) => self.jump(address),
-            Opcode::CALL(address) => self.call(address),
+            Opcode::CALL(address) => self.call(address)?,
             Opcode::SE(x, kk) => self.skip_equal(x, kk),
             Opcode::SNE(x, kk) => self.skip_not_equal(x, kk),
             Opcode::SER(x, y) => self.skip_register_equal(x, y),
+            Opcode::SAVE(x, y) => self.xo_chip()?.save_range(x, y)?,
+            Opcode::LOAD(x, y) => self.xo_chip()?.load_range(x, y)?,
             Opcode::LD(x, kk) => self.load(x, kk),
             Opcode::ADD(x, kk) => self.add(x, kk),
             Opcode::LDR(x, y) => self.load_register(x, y),
@@ -114,14 +659,14 @@ impl Cpu {
             Opcode::XOR(x, y) => self.xor(x, y),
             Opcode::ADDR(x, y) => self.addr(x, y),
             Opcode::SUBR(x, y) => self.subr(x, y),
-            Opcode::SHR(x) => self.shr(x),
+            Opcode::SHR(x, y) => self.shr(x, y),
             Opcode::SUBN(x, y) => self.subn(x, y),
-            Opcode::SHL(x) => self.shl(x),
+            Opcode::SHL(x, y) => self.shl(x, y),
             Opcode::SNER(x, y) => self.skip_not_equal_registers(x, y),
             Opcode::LDI(nnn) => self.load_i(nnn),
             Opcode::JPR(nnn) => self.jumpr(nnn),
             Opcode::RND(x, kk) => self.rnd(x, kk),
-            Opcode::DRW(x, y, n) => self.draw(x, y, n),
+            Opcode::DRW(x, y, n) => self.draw(x, y, n)?,
             Opcode::SKP(x) => self.skip_when_key_pressed(x),
             Opcode::SKNP(x) => self.skip_when_key_not_pressed(x),
             Opcode::LDDT(x) => self.load_delay_timer(x),
@@ -130,42 +675,81 @@ impl Cpu {
             Opcode::STLD(x) => self.set_sound_timer(x),
             Opcode::ADDI(x) => self.addi(x),
             Opcode::LDF(x) => self.ldf(x),
-            Opcode::LDB(x) => self.ldb(x),
-            Opcode::LDIR(x) => self.ldir(x),
-            Opcode::LDRI(x) => self.ldri(x),
+            Opcode::LDB(x) => self.ldb(x)?,
+            Opcode::LDIR(x) => self.ldir(x)?,
+            Opcode::LDRI(x) => self.ldri(x)?,
+            Opcode::LDHF(x) => self.super_chip()?.ldhf(x),
+            Opcode::STRPL(x) => self.super_chip()?.store_rpl(x),
+            Opcode::LDRPL(x) => self.super_chip()?.load_rpl(x),
+            Opcode::LDIL => self.xo_chip()?.load_i_long()?,
+            Opcode::PLANE(n) => self.xo_chip()?.screen.select_planes(n),
+            Opcode::AUDIO => self.xo_chip()?.load_audio()?,
+            Opcode::PITCH(x) => self.xo_chip()?.audio.pitch = self.register[x as usize],
         }
+
+        Ok(())
     }
 
-    fn ret(&mut self) {
+    /// Lets SUPER-CHIP instructions through only when the platform supports
+    /// them; on plain chip8 they are unknown opcodes.
+    fn super_chip(&mut self) -> Result<&mut Self, FaultKind> {
+        if self.platform.has_super_chip() {
+            Ok(self)
+        } else {
+            Err(FaultKind::UnknownOpcode)
+        }
+    }
+
+    /// Lets XO-CHIP instructions through only when the platform supports
+    /// them.
+    fn xo_chip(&mut self) -> Result<&mut Self, FaultKind> {
+        if self.platform.has_xo_chip() {
+            Ok(self)
+        } else {
+            Err(FaultKind::UnknownOpcode)
+        }
+    }
+
+    fn ret(&mut self) -> Result<(), FaultKind> {
+        if self.sp == 0 {
+            return Err(FaultKind::StackUnderflow);
+        }
+
         self.sp -= 1;
         self.pc = self.stack[self.sp as usize];
+        Ok(())
     }
 
     fn jump(&mut self, address: u16) {
         self.pc = address;
     }
 
-    fn call(&mut self, address: u16) {
+    fn call(&mut self, address: u16) -> Result<(), FaultKind> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(FaultKind::StackOverflow);
+        }
+
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
         self.pc = address;
+        Ok(())
     }
 
     fn skip_equal(&mut self, x: u8, kk: u8) {
         if self.register[x as usize] == kk {
-            self.pc += 2;
+            self.skip();
         }
     }
 
     fn skip_not_equal(&mut self, x: u8, kk: u8) {
         if self.register[x as usize] != kk {
-            self.pc += 2;
+            self.skip();
         }
     }
 
     fn skip_register_equal(&mut self, x: u8, y: u8) {
         if self.register[x as usize] == self.register[y as usize] {
-            self.pc += 2;
+            self.skip();
         }
     }
 
@@ -173,8 +757,9 @@ impl Cpu {
         self.register[x as usize] = kk;
     }
 
+    /// 7XNN: adds without touching VF.
     fn add(&mut self, x: u8, kk: u8) {
-        self.register[x as usize] += kk;
+        self.register[x as usize] = self.register[x as usize].wrapping_add(kk);
     }
 
     fn load_register(&mut self, x: u8, y: u8) {
@@ -183,46 +768,72 @@ impl Cpu {
 
     fn or(&mut self, x: u8, y: u8) {
         self.register[x as usize] |= self.register[y as usize];
+        self.reset_vf();
     }
 
     fn and(&mut self, x: u8, y: u8) {
         self.register[x as usize] &= self.register[y as usize];
+        self.reset_vf();
     }
 
     fn xor(&mut self, x: u8, y: u8) {
         self.register[x as usize] ^= self.register[y as usize];
+        self.reset_vf();
     }
 
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.register[0xF] = 0;
+        }
+    }
+
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.register[y as usize]
+        } else {
+            self.register[x as usize]
+        }
+    }
+
+    /// 8XY4: VX += VY, then VF = carry. VF is written last, so with X = F it
+    /// ends up holding the flag rather than the sum.
     fn addr(&mut self, x: u8, y: u8) {
-        self.register[x as usize] += self.register[y as usize];
+        let (sum, carry) = self.register[x as usize].overflowing_add(self.register[y as usize]);
+        self.register[x as usize] = sum;
+        self.register[0xF] = carry as u8;
     }
 
+    /// 8XY5: VX -= VY, then VF = NOT borrow.
     fn subr(&mut self, x: u8, y: u8) {
-        self.register[x as usize] -= self.register[y as usize];
+        let (difference, borrow) =
+            self.register[x as usize].overflowing_sub(self.register[y as usize]);
+        self.register[x as usize] = difference;
+        self.register[0xF] = !borrow as u8;
     }
 
-    fn shr(&mut self, x: u8) {
-        self.register[0xF] = self.register[x as usize] & 1;
-        self.register[x as usize] >>= 1;
+    fn shr(&mut self, x: u8, y: u8) {
+        let value = self.shift_source(x, y);
+        self.register[x as usize] = value >> 1;
+        self.register[0xF] = value & 1;
     }
 
+    /// 8XY7: VX = VY - VX, then VF = NOT borrow.
     fn subn(&mut self, x: u8, y: u8) {
-        if self.register[x as usize] > self.register[y as usize] {
-            self.register[0xF] = 1;
-        }
-
-        self.register[x as usize] =
-            self.register[y as usize].wrapping_sub(self.register[x as usize]);
+        let (difference, borrow) =
+            self.register[y as usize].overflowing_sub(self.register[x as usize]);
+        self.register[x as usize] = difference;
+        self.register[0xF] = !borrow as u8;
     }
 
-    fn shl(&mut self, x: u8) {
-        self.register[0xF] = self.register[x as usize] >> 7;
-        self.register[x as usize] <<= 1;
+    fn shl(&mut self, x: u8, y: u8) {
+        let value = self.shift_source(x, y);
+        self.register[x as usize] = value << 1;
+        self.register[0xF] = value >> 7;
     }
 
     fn skip_not_equal_registers(&mut self, x: u8, y: u8) {
         if self.register[x as usize] != self.register[y as usize] {
-            self.pc += 2;
+            self.skip();
         }
     }
 
@@ -231,87 +842,211 @@ impl Cpu {
     }
 
     fn jumpr(&mut self, nnn: u16) {
-        self.pc = nnn + self.register[0] as u16;
+        let offset_register = if self.quirks.jump_with_vx {
+            (nnn >> 8) as usize
+        } else {
+            0
+        };
+        self.pc = nnn + self.register[offset_register] as u16;
     }
 
     fn rnd(&mut self, x: u8, kk: u8) {
-        let mut rng = rand::thread_rng();
-        self.register[x as usize] = kk & rng.gen::<u8>();
+        self.register[x as usize] = kk & self.rng.next_u8();
     }
 
-    fn draw(&mut self, mut x: u8, mut y: u8, n: u8) {
+    fn draw(&mut self, mut x: u8, mut y: u8, n: u8) -> Result<(), FaultKind> {
         x = self.register[x as usize];
         y = self.register[y as usize];
 
+        if n == 0 && self.platform.has_super_chip() {
+            return self.draw_large(x, y);
+        }
+
+        let len = n as usize * self.screen.selected_plane_count();
+        let range = self.read_range(self.i, len)?;
         self.register[0xF] = 0;
-        let sprite_data = &self.memory[(self.i) as usize..(n as u16 + self.i) as usize];
+        let sprite_data = &self.memory[range];
+
+        let collision = if self.quirks.clip_sprites {
+            self.screen
+                .draw_sprite_clipped(x as usize, y as usize, sprite_data)
+        } else {
+            self.screen.draw_sprite(x as usize, y as usize, sprite_data)
+        };
+        if collision {
+            self.register[0xF] = 1;
+        }
+        if self.quirks.display_wait {
+            self.waiting_for_frame = true;
+        }
+        Ok(())
+    }
 
-        if self.screen.draw_sprite(x as usize, y as usize, sprite_data) {
+    /// DXY0 on SUPER-CHIP: draws a 16x16 sprite from 32 bytes at I.
+    fn draw_large(&mut self, x: u8, y: u8) -> Result<(), FaultKind> {
+        let len = 32 * self.screen.selected_plane_count();
+        let range = self.read_range(self.i, len)?;
+        self.register[0xF] = 0;
+
+        let collision = self.screen.draw_large_sprite(
+            x as usize,
+            y as usize,
+            &self.memory[range],
+            self.quirks.clip_sprites,
+        );
+        if collision {
             self.register[0xF] = 1;
         }
+        Ok(())
     }
 
     fn skip_when_key_pressed(&mut self, x: u8) {
-        let key = 1 << self.register[x as usize];
-        if key & self.key_state == key {
-            self.pc += 2;
+        if self.keypad.is_pressed(self.register[x as usize] & 0xF) {
+            self.skip();
         }
     }
 
     fn skip_when_key_not_pressed(&mut self, x: u8) {
-        let key = 1 << self.register[x as usize];
-        if key & self.key_state != key {
-            self.pc += 2;
+        if !self.keypad.is_pressed(self.register[x as usize] & 0xF) {
+            self.skip();
         }
     }
 
     fn load_delay_timer(&mut self, x: u8) {
-        self.register[x as usize] = self.delaytimer;
+        self.register[x as usize] = self.timers.delay;
     }
 
+    /// Blocks by re-executing itself until a key is pressed and released
+    /// again, then stores that key in VX. Keys already held down when the wait
+    /// starts only count once they are pressed anew.
     fn wait_for_keypress(&mut self, x: u8) {
-        let key = self.register[x as usize];
-        if key & self.key_state != key {
-            self.pc -= 2;
+        if !self.waiting_for_key {
+            self.keypad.clear_events();
+            self.waiting_for_key = true;
+        }
+
+        match self.keypad.take_released_key() {
+            Some(key) => {
+                self.register[x as usize] = key;
+                self.waiting_for_key = false;
+            }
+            None => self.pc = self.pc.wrapping_sub(2),
         }
     }
 
     fn set_delay_timer(&mut self, x: u8) {
-        self.delaytimer = self.register[x as usize];
+        self.timers.delay = self.register[x as usize];
     }
 
     fn set_sound_timer(&mut self, x: u8) {
-        self.soundtimer = self.register[x as usize];
+        self.timers.sound = self.register[x as usize];
     }
 
     fn addi(&mut self, x: u8) {
-        self.i += self.register[x as usize] as u16;
+        self.i = self.i.wrapping_add(self.register[x as usize] as u16);
     }
 
+    /// FX29: points I at the font sprite for the low nibble of VX.
     fn ldf(&mut self, x: u8) {
-        self.i = self.register[x as usize] as u16 * 5;
+        let digit = (self.register[x as usize] & 0xF) as u16;
+        self.i = self.font_address.wrapping_add(digit * 5);
+    }
+
+    fn ldb(&mut self, x: u8) -> Result<(), FaultKind> {
+        let range = self.write_range(self.i, 3)?;
+        let start = range.start;
+        self.memory[start] = self.register[x as usize] / 100;
+        self.memory[start + 1] = (self.register[x as usize] / 10) % 10;
+        self.memory[start + 2] = (self.register[x as usize] % 100) % 10;
+        Ok(())
+    }
+
+    /// FX55: stores V0 through VX at I.
+    fn ldir(&mut self, x: u8) -> Result<(), FaultKind> {
+        let count = x as usize + 1;
+        let range = self.write_range(self.i, count)?;
+        self.memory[range].copy_from_slice(&self.register[..count]);
+        if self.quirks.increment_i {
+            self.i = self.i.wrapping_add(count as u16);
+        }
+        Ok(())
     }
 
-    fn ldb(&mut self, x: u8) {
-        self.memory[self.i as usize] = self.register[x as usize] / 100;
-        self.memory[(self.i + 1) as usize] = (self.register[x as usize] / 10) % 10;
-        self.memory[(self.i + 2) as usize] = (self.register[x as usize] % 100) % 10;
+    /// FX65: loads V0 through VX from I.
+    fn ldri(&mut self, x: u8) -> Result<(), FaultKind> {
+        let count = x as usize + 1;
+        let range = self.read_range(self.i, count)?;
+        self.register[..count].copy_from_slice(&self.memory[range]);
+        if self.quirks.increment_i {
+            self.i = self.i.wrapping_add(count as u16);
+        }
+        Ok(())
     }
 
-    fn ldir(&mut self, x: u8) {
-        for i in 0..(x as u16) {
-            self.memory[(i + self.i) as usize] = self.register[i as usize];
+    /// 5XY2: stores VX through VY at I, in descending order when X > Y. I
+    /// is left unchanged.
+    fn save_range(&mut self, x: u8, y: u8) -> Result<(), FaultKind> {
+        let registers = Cpu::register_range(x, y);
+        let range = self.write_range(self.i, registers.len())?;
+        for (address, register) in range.zip(registers) {
+            self.memory[address] = self.register[register];
         }
+        Ok(())
     }
 
-    fn ldri(&mut self, x: u8) {
-        for i in 0..(x as u16) {
-            self.register[i as usize] = self.memory[(i + self.i) as usize];
+    /// 5XY3: loads VX through VY from I, the inverse of `save_range`.
+    fn load_range(&mut self, x: u8, y: u8) -> Result<(), FaultKind> {
+        let registers = Cpu::register_range(x, y);
+        let range = self.read_range(self.i, registers.len())?;
+        for (address, register) in range.zip(registers) {
+            self.register[register] = self.memory[address];
         }
+        Ok(())
+    }
+
+    fn register_range(x: u8, y: u8) -> Vec<usize> {
+        if x <= y {
+            (x as usize..=y as usize).collect()
+        } else {
+            (y as usize..=x as usize).rev().collect()
+        }
+    }
+
+    /// F000 NNNN: loads the 16 bit word following the instruction into I.
+    fn load_i_long(&mut self) -> Result<(), FaultKind> {
+        let address = self
+            .word_at(self.pc)
+            .ok_or(FaultKind::MemoryOutOfBounds(self.pc as usize))?;
+        self.i = address;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    /// F002: loads the 16 byte audio pattern from I.
+    fn load_audio(&mut self) -> Result<(), FaultKind> {
+        let range = self.read_range(self.i, self.audio.pattern.len())?;
+        self.audio.pattern.copy_from_slice(&self.memory[range]);
+        Ok(())
+    }
+
+    fn ldhf(&mut self, x: u8) {
+        let digit = (self.register[x as usize] & 0xF) as u16;
+        self.i = self.big_font_address.wrapping_add(digit * 10);
+    }
+
+    fn store_rpl(&mut self, x: u8) {
+        let count = x as usize + 1;
+        self.rpl[..count].copy_from_slice(&self.register[..count]);
+    }
+
+    fn load_rpl(&mut self, x: u8) {
+        let count = x as usize + 1;
+        self.register[..count].copy_from_slice(&self.rpl[..count]);
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     use super::*;
 
@@ -319,8 +1054,8 @@ mod tests {
     fn cls_clears_the_screen() {
         let mut cpu = Cpu::default();
 
-        cpu.screen.draw_sprite(0, 0, &vec![0xFF]);
-        cpu.execute(0x00E0);
+        cpu.screen.draw_sprite(0, 0, &[0xFF]);
+        cpu.execute(0x00E0).unwrap();
 
         assert_eq!(0, cpu.screen.get_screen_data()[0]);
     }
@@ -332,7 +1067,7 @@ mod tests {
         cpu.sp = 5;
         cpu.stack[4] = 22;
 
-        cpu.execute(0x00EE);
+        cpu.execute(0x00EE).unwrap();
 
         assert_eq!(22, cpu.pc);
         assert_eq!(4, cpu.sp);
@@ -342,7 +1077,7 @@ mod tests {
     fn jp_sets_pc_to_address() {
         let mut cpu = Cpu::default();
 
-        cpu.execute(0x1F43);
+        cpu.execute(0x1F43).unwrap();
 
         assert_eq!(0xF43, cpu.pc);
     }
@@ -352,7 +1087,7 @@ mod tests {
         let mut cpu = Cpu::default();
         cpu.pc = 0x55;
 
-        cpu.execute(0x2F43);
+        cpu.execute(0x2F43).unwrap();
 
         assert_eq!(0x55, cpu.stack[0]);
         assert_eq!(1, cpu.sp);
@@ -365,7 +1100,7 @@ mod tests {
 
         cpu.register[2] = 0xFF;
 
-        cpu.execute(0x32FF);
+        cpu.execute(0x32FF).unwrap();
 
         assert_eq!(2, cpu.pc);
     }
@@ -376,7 +1111,7 @@ mod tests {
 
         cpu.register[2] = 0x44;
 
-        cpu.execute(0x32FF);
+        cpu.execute(0x32FF).unwrap();
 
         assert_eq!(0, cpu.pc);
     }
@@ -387,7 +1122,7 @@ mod tests {
 
         cpu.register[2] = 0x33;
 
-        cpu.execute(0x42FF);
+        cpu.execute(0x42FF).unwrap();
 
         assert_eq!(2, cpu.pc);
     }
@@ -398,7 +1133,7 @@ mod tests {
 
         cpu.register[2] = 0x22;
 
-        cpu.execute(0x4222);
+        cpu.execute(0x4222).unwrap();
 
         assert_eq!(0, cpu.pc);
     }
@@ -410,7 +1145,7 @@ mod tests {
         cpu.register[2] = 0x55;
         cpu.register[5] = 0x55;
 
-        cpu.execute(0x5250);
+        cpu.execute(0x5250).unwrap();
 
         assert_eq!(2, cpu.pc);
     }
@@ -419,7 +1154,7 @@ mod tests {
     fn load_sets_register() {
         let mut cpu = Cpu::default();
 
-        cpu.execute(0x6655);
+        cpu.execute(0x6655).unwrap();
 
         assert_eq!(0x55, cpu.register[6]);
     }
@@ -429,7 +1164,7 @@ mod tests {
         let mut cpu = Cpu::default();
         cpu.register[4] = 0x10;
 
-        cpu.execute(0x7410);
+        cpu.execute(0x7410).unwrap();
 
         assert_eq!(0x20, cpu.register[4]);
     }
@@ -439,7 +1174,7 @@ mod tests {
         let mut cpu = Cpu::default();
         cpu.register[4] = 0x55;
 
-        cpu.execute(0x8240);
+        cpu.execute(0x8240).unwrap();
 
         assert_eq!(0x55, cpu.register[2]);
     }
@@ -450,7 +1185,7 @@ mod tests {
         cpu.register[2] = 0b11100000;
         cpu.register[3] = 0b01001111;
 
-        cpu.execute(0x8231);
+        cpu.execute(0x8231).unwrap();
 
         assert_eq!(0b11101111, cpu.register[2]);
     }
@@ -461,7 +1196,7 @@ mod tests {
         cpu.register[2] = 0b11100000;
         cpu.register[3] = 0b01001111;
 
-        cpu.execute(0x8232);
+        cpu.execute(0x8232).unwrap();
 
         assert_eq!(0b01000000, cpu.register[2]);
     }
@@ -472,7 +1207,7 @@ mod tests {
         cpu.register[2] = 0b11100000;
         cpu.register[3] = 0b01001111;
 
-        cpu.execute(0x8233);
+        cpu.execute(0x8233).unwrap();
 
         assert_eq!(0b10101111, cpu.register[2]);
     }
@@ -484,7 +1219,7 @@ mod tests {
         cpu.register[2] = 0x10;
         cpu.register[3] = 0x10;
 
-        cpu.execute(0x8234);
+        cpu.execute(0x8234).unwrap();
 
         assert_eq!(0x20, cpu.register[2]);
     }
@@ -496,23 +1231,23 @@ mod tests {
         cpu.register[2] = 0x20;
         cpu.register[3] = 0x10;
 
-        cpu.execute(0x8235);
+        cpu.execute(0x8235).unwrap();
 
         assert_eq!(0x10, cpu.register[2]);
     }
 
     #[test]
     fn shr() {
-        let mut cpu = Cpu::default();
+        let mut cpu = Cpu::new(Quirks::chip48());
 
         cpu.register[2] = 0x20;
-        cpu.execute(0x8206);
+        cpu.execute(0x8206).unwrap();
 
         assert_eq!(0, cpu.register[0xF]);
         assert_eq!(0x10, cpu.register[0x2]);
 
         cpu.register[2] = 0x21;
-        cpu.execute(0x8206);
+        cpu.execute(0x8206).unwrap();
 
         assert_eq!(1, cpu.register[0xF]);
         assert_eq!(0x10, cpu.register[0x2]);
@@ -525,31 +1260,31 @@ mod tests {
         cpu.register[2] = 0x30;
         cpu.register[3] = 0x10;
 
-        cpu.execute(0x8327);
+        cpu.execute(0x8327).unwrap();
 
-        assert_eq!(0x0, cpu.register[0xF]);
+        assert_eq!(0x1, cpu.register[0xF]);
         assert_eq!(0x20, cpu.register[3]);
         cpu.register[2] = 0x10;
         cpu.register[3] = 0x30;
 
-        cpu.execute(0x8327);
+        cpu.execute(0x8327).unwrap();
 
-        assert_eq!(0x1, cpu.register[0xF]);
+        assert_eq!(0x0, cpu.register[0xF]);
         assert_eq!(0xE0, cpu.register[3]);
     }
 
     #[test]
     fn shl() {
-        let mut cpu = Cpu::default();
+        let mut cpu = Cpu::new(Quirks::chip48());
 
         cpu.register[2] = 0x20;
-        cpu.execute(0x820E);
+        cpu.execute(0x820E).unwrap();
 
         assert_eq!(0, cpu.register[0xF]);
         assert_eq!(0x40, cpu.register[2]);
 
         cpu.register[2] = 0xF0;
-        cpu.execute(0x820E);
+        cpu.execute(0x820E).unwrap();
 
         assert_eq!(1, cpu.register[0xF]);
         assert_eq!(0xF0u8.wrapping_mul(2), cpu.register[2]);
@@ -562,13 +1297,13 @@ mod tests {
         cpu.register[2] = 0x2;
         cpu.register[3] = 0x3;
 
-        cpu.execute(0x9230);
+        cpu.execute(0x9230).unwrap();
 
         assert_eq!(2, cpu.pc);
 
         cpu.register[3] = 0x2;
 
-        cpu.execute(0x9230);
+        cpu.execute(0x9230).unwrap();
 
         assert_eq!(2, cpu.pc);
     }
@@ -577,7 +1312,7 @@ mod tests {
     fn load_i() {
         let mut cpu = Cpu::default();
 
-        cpu.execute(0xA555);
+        cpu.execute(0xA555).unwrap();
 
         assert_eq!(0x555, cpu.i);
     }
@@ -587,7 +1322,7 @@ mod tests {
         let mut cpu = Cpu::default();
 
         cpu.register[0] = 0x10;
-        cpu.execute(0xBC23);
+        cpu.execute(0xBC23).unwrap();
 
         assert_eq!(0xC33, cpu.pc);
     }
@@ -596,7 +1331,14 @@ mod tests {
     fn rnd() {
         let mut cpu = Cpu::default();
 
-        cpu.execute(0xC222); // Can't assert anything since the result is random for now.
+        cpu.seed_rng(1234);
+        let mut rng = Rng::new(1234);
+
+        cpu.execute(0xC20F).unwrap();
+        assert_eq!(rng.next_u8() & 0x0F, cpu.register[2]);
+
+        cpu.execute(0xC2FF).unwrap();
+        assert_eq!(rng.next_u8(), cpu.register[2]);
     }
 
     #[test]
@@ -604,7 +1346,7 @@ mod tests {
         let mut cpu = Cpu::default();
 
         cpu.memory[0] = 0xFF;
-        cpu.execute(0xD001);
+        cpu.execute(0xD001).unwrap();
 
         assert_eq!(0, cpu.register[0xF]);
         let pixels = cpu.screen.get_screen_data();
@@ -617,8 +1359,8 @@ mod tests {
         let mut cpu = Cpu::default();
 
         cpu.memory[0] = 0xFF;
-        cpu.execute(0xD001);
-        cpu.execute(0xD001);
+        cpu.execute(0xD001).unwrap();
+        cpu.execute(0xD001).unwrap();
 
         assert_eq!(1, cpu.register[0xF]);
         let pixels = cpu.screen.get_screen_data();
@@ -629,9 +1371,9 @@ mod tests {
     fn load_delay_timer_loads_delaytimer_value() {
         let mut cpu = Cpu::default();
 
-        cpu.delaytimer = 5;
+        cpu.timers.delay = 5;
 
-        cpu.execute(0xF207);
+        cpu.execute(0xF207).unwrap();
 
         assert_eq!(5, cpu.register[2]);
     }
@@ -641,9 +1383,9 @@ mod tests {
         let mut cpu = Cpu::default();
 
         cpu.register[2] = 0x44;
-        cpu.execute(0xF215);
+        cpu.execute(0xF215).unwrap();
 
-        assert_eq!(0x44, cpu.delaytimer);
+        assert_eq!(0x44, cpu.timers.delay);
     }
 
     #[test]
@@ -651,9 +1393,9 @@ mod tests {
         let mut cpu = Cpu::default();
 
         cpu.register[2] = 0x44;
-        cpu.execute(0xF218);
+        cpu.execute(0xF218).unwrap();
 
-        assert_eq!(0x44, cpu.soundtimer);
+        assert_eq!(0x44, cpu.timers.sound);
     }
 
     #[test]
@@ -663,8 +1405,958 @@ mod tests {
         cpu.register[2] = 0x10;
         cpu.i = 0x20;
 
-        cpu.execute(0xF21E);
+        cpu.execute(0xF21E).unwrap();
 
         assert_eq!(0x30, cpu.i);
     }
+
+    #[test]
+    fn step_reports_unknown_opcode_as_fault() {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&[0x80, 0x0F]).unwrap();
+
+        let outcome = cpu.step();
+
+        assert_eq!(
+            StepOutcome::Fault(CpuFault {
+                pc: 0x200,
+                opcode: 0x800F,
+                kind: FaultKind::UnknownOpcode,
+            }),
+            outcome
+        );
+        assert_eq!(0x200, cpu.pc);
+    }
+
+    #[test]
+    fn step_reports_pc_outside_memory_as_fault() {
+        let mut cpu = Cpu::default();
+        cpu.pc = 0xFFF;
+
+        match cpu.step() {
+            StepOutcome::Fault(fault) => assert_eq!(FaultKind::PcOutOfBounds, fault.kind),
+            outcome => panic!("expected fault, got {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn ret_with_empty_stack_underflows() {
+        let mut cpu = Cpu::default();
+
+        assert_eq!(Err(FaultKind::StackUnderflow), cpu.execute(0x00EE));
+    }
+
+    #[test]
+    fn call_with_full_stack_overflows() {
+        let mut cpu = Cpu::default();
+
+        for _ in 0..16 {
+            cpu.execute(0x2300).unwrap();
+        }
+
+        assert_eq!(Err(FaultKind::StackOverflow), cpu.execute(0x2300));
+        assert_eq!(16, cpu.sp);
+    }
+
+    #[test]
+    fn draw_past_end_of_memory_faults() {
+        let mut cpu = Cpu::default();
+        cpu.i = 0xFFE;
+
+        assert_eq!(
+            Err(FaultKind::MemoryOutOfBounds(0x1000)),
+            cpu.execute(0xD005)
+        );
+    }
+
+    /// Runs `cpu` for one second of emulated time at `instructions_per_second`,
+    /// the way the frontend does: a batch of instructions per 60 Hz frame
+    /// followed by a timer update.
+    fn run_for_one_second(cpu: &mut Cpu, instructions_per_second: u32) {
+        let frame_secs = 1.0 / 60.0;
+        let mut pending = 0.0;
+
+        for _ in 0..60 {
+            pending += instructions_per_second as f64 * frame_secs;
+            while pending >= 1.0 {
+                assert_eq!(StepOutcome::Executed, cpu.step());
+                pending -= 1.0;
+            }
+            cpu.update_timers(frame_secs);
+        }
+    }
+
+    #[test]
+    fn step_does_not_touch_timers() {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&[0x12, 0x00]).unwrap();
+        cpu.timers.delay = 10;
+        cpu.timers.sound = 10;
+
+        for _ in 0..100 {
+            cpu.step();
+        }
+
+        assert_eq!(10, cpu.timers.delay);
+        assert_eq!(10, cpu.timers.sound);
+    }
+
+    #[test]
+    fn run_frame_steps_then_ticks_timers() {
+        let mut cpu = Cpu::default();
+        // 7001: V0 += 1, 1200: loop
+        cpu.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        cpu.timers.delay = 10;
+
+        assert_eq!(StepOutcome::Executed, cpu.run_frame(10));
+
+        assert_eq!(5, cpu.register[0]);
+        assert_eq!(9, cpu.timers.delay);
+    }
+
+    #[test]
+    fn run_frame_ends_early_on_display_wait_and_exit() {
+        let mut cpu = super_chip_cpu(&[0x00, 0xE0, 0xD0, 0x01, 0x70, 0x01, 0x00, 0xFD]);
+        cpu.quirks.display_wait = true;
+
+        assert_eq!(StepOutcome::Waiting, cpu.run_frame(10));
+        assert_eq!(0x204, cpu.pc);
+        assert_eq!(StepOutcome::Exited, cpu.run_frame(10));
+        assert_eq!(1, cpu.register[0]);
+    }
+
+    #[test]
+    fn register_accessors_expose_the_machine_state() {
+        let mut cpu = Cpu::default();
+        // 6105: V1 = 5, A123: I = 0x123, 2208: call 0x208, 0000, 1208: loop
+        cpu.load_rom(&[0x61, 0x05, 0xA1, 0x23, 0x22, 0x08, 0x00, 0x00, 0x12, 0x08])
+            .unwrap();
+        cpu.run_frame(4);
+
+        assert_eq!(0x208, cpu.pc());
+        assert_eq!(0x123, cpu.i());
+        assert_eq!(5, cpu.registers()[1]);
+        assert_eq!(1, cpu.sp());
+        assert_eq!(&[0x206], cpu.stack());
+        assert_eq!(0x61, cpu.memory()[0x200]);
+    }
+
+    #[test]
+    fn timers_count_at_sixty_hz_at_any_speed() {
+        for &instructions_per_second in &[1, 60, 400, 700, 1000, 5000, 100_000] {
+            let mut cpu = Cpu::default();
+            // 6078: V0 = 0x78, F015: DT = V0, F018: ST = V0, 1206: loop forever
+            cpu.load_rom(&[0x60, 0x78, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06])
+                .unwrap();
+            for _ in 0..3 {
+                cpu.step();
+            }
+
+            run_for_one_second(&mut cpu, instructions_per_second);
+
+            assert_eq!(60, cpu.timers.delay, "at {} ips", instructions_per_second);
+            assert_eq!(60, cpu.timers.sound, "at {} ips", instructions_per_second);
+        }
+    }
+
+    #[test]
+    fn tick_timers_counts_down_once() {
+        let mut cpu = Cpu::default();
+        cpu.timers.delay = 2;
+        cpu.timers.sound = 1;
+
+        cpu.tick_timers();
+
+        assert_eq!(1, cpu.timers.delay);
+        assert_eq!(0, cpu.timers.sound);
+    }
+
+    #[test]
+    fn skip_when_key_pressed_handles_high_keys() {
+        let mut cpu = Cpu::default();
+        cpu.pc = 0x200;
+        cpu.register[1] = 0xE;
+        cpu.keypad.key_down(0xE);
+
+        cpu.execute(0xE19E).unwrap();
+        assert_eq!(0x202, cpu.pc);
+
+        cpu.execute(0xE1A1).unwrap();
+        assert_eq!(0x202, cpu.pc);
+    }
+
+    #[test]
+    fn skip_when_key_not_pressed_skips_for_released_key() {
+        let mut cpu = Cpu::default();
+        cpu.pc = 0x200;
+        cpu.register[1] = 0x9;
+
+        cpu.execute(0xE1A1).unwrap();
+
+        assert_eq!(0x202, cpu.pc);
+    }
+
+    #[test]
+    fn wait_for_keypress_blocks_until_key_is_pressed_and_released() {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&[0xF3, 0x0A]).unwrap();
+
+        cpu.step();
+        assert_eq!(0x200, cpu.pc);
+
+        cpu.keypad.key_down(0xC);
+        cpu.step();
+        assert_eq!(0x200, cpu.pc);
+
+        cpu.keypad.key_up(0xC);
+        cpu.step();
+        assert_eq!(0x202, cpu.pc);
+        assert_eq!(0xC, cpu.register[3]);
+    }
+
+    #[test]
+    fn wait_for_keypress_ignores_key_held_before_wait() {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&[0xF3, 0x0A]).unwrap();
+        cpu.keypad.key_down(0x5);
+
+        cpu.step();
+        cpu.keypad.key_up(0x5);
+        cpu.step();
+        assert_eq!(0x200, cpu.pc);
+
+        cpu.keypad.key_down(0x5);
+        cpu.keypad.key_up(0x5);
+        cpu.step();
+        assert_eq!(0x202, cpu.pc);
+        assert_eq!(0x5, cpu.register[3]);
+    }
+
+    #[test]
+    fn shifts_use_vy_with_shift_quirk() {
+        let mut cpu = Cpu::new(Quirks::cosmac_vip());
+
+        cpu.register[3] = 0x81;
+        cpu.execute(0x8236).unwrap();
+
+        assert_eq!(0x40, cpu.register[2]);
+        assert_eq!(1, cpu.register[0xF]);
+
+        cpu.execute(0x823E).unwrap();
+
+        assert_eq!(0x02, cpu.register[2]);
+        assert_eq!(1, cpu.register[0xF]);
+        assert_eq!(0x81, cpu.register[3]);
+    }
+
+    #[test]
+    fn logic_ops_reset_vf_with_vf_reset_quirk() {
+        for &opcode in &[0x8231, 0x8232, 0x8233] {
+            let mut cpu = Cpu::new(Quirks::cosmac_vip());
+            cpu.register[0xF] = 1;
+            cpu.execute(opcode).unwrap();
+            assert_eq!(0, cpu.register[0xF]);
+
+            let mut cpu = Cpu::new(Quirks::chip48());
+            cpu.register[0xF] = 1;
+            cpu.execute(opcode).unwrap();
+            assert_eq!(1, cpu.register[0xF]);
+        }
+    }
+
+    #[test]
+    fn jumpr_uses_vx_with_jump_quirk() {
+        let mut cpu = Cpu::new(Quirks::chip48());
+
+        cpu.register[0] = 0x10;
+        cpu.register[0xC] = 0x20;
+        cpu.execute(0xBC23).unwrap();
+
+        assert_eq!(0xC43, cpu.pc);
+    }
+
+    #[test]
+    fn ldf_draws_every_font_glyph() {
+        for digit in 0..16u8 {
+            let mut cpu = Cpu::default();
+            // Only the low nibble selects the glyph.
+            cpu.register[0] = 0xA0 | digit;
+            cpu.execute(0xF029).unwrap();
+            cpu.execute(0xD125).unwrap();
+
+            let glyph = &Font::Chip48.glyphs()[digit as usize * 5..digit as usize * 5 + 5];
+            for (y, row) in glyph.iter().enumerate() {
+                for x in 0..8 {
+                    assert_eq!(
+                        row & (0x80 >> x) != 0,
+                        cpu.screen.get(x, y),
+                        "glyph {:X} at {}, {}",
+                        digit,
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ldhf_draws_every_big_font_glyph() {
+        for digit in 0..10u8 {
+            let mut cpu = super_chip_cpu(&[]);
+            cpu.screen.set_hires(true);
+            cpu.register[0] = digit;
+            cpu.execute(0xF030).unwrap();
+            cpu.execute(0xD12A).unwrap();
+
+            let glyph = &Font::SuperChipBig.glyphs()[digit as usize * 10..digit as usize * 10 + 10];
+            for (y, row) in glyph.iter().enumerate() {
+                for x in 0..8 {
+                    assert_eq!(
+                        row & (0x80 >> x) != 0,
+                        cpu.screen.get(x, y),
+                        "glyph {} at {}, {}",
+                        digit,
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn load_store_cover_v0_through_vx() {
+        let mut cpu = Cpu::new(Quirks::chip48());
+        cpu.register[..4].copy_from_slice(&[1, 2, 3, 4]);
+        cpu.i = 0x300;
+
+        cpu.execute(0xF255).unwrap();
+        assert_eq!(&[1, 2, 3, 0], &cpu.memory[0x300..0x304]);
+
+        cpu.register = [0; 16];
+        cpu.execute(0xF065).unwrap();
+        assert_eq!(&[1, 0, 0], &cpu.register[..3]);
+        cpu.execute(0xF265).unwrap();
+        assert_eq!(&[1, 2, 3, 0], &cpu.register[..4]);
+
+        cpu.register = [0xAA; 16];
+        cpu.execute(0xFF55).unwrap();
+        assert_eq!(&[0xAA; 16], &cpu.memory[0x300..0x310]);
+    }
+
+    #[test]
+    fn memory_instructions_past_the_end_of_memory_fault() {
+        let mut cpu = Cpu::default();
+        let end = cpu.memory.len();
+        cpu.register[0] = 123;
+
+        for &(opcode, i) in &[(0xF033, end - 2), (0xF155, end - 1), (0xF165, end - 1)] {
+            cpu.i = i as u16;
+            assert_eq!(
+                Err(FaultKind::MemoryOutOfBounds(end)),
+                cpu.execute(opcode),
+                "{:04X}",
+                opcode
+            );
+            assert_eq!(i as u16, cpu.i);
+        }
+        assert_eq!(&[0, 0], &cpu.memory[end - 2..]);
+        assert_eq!(123, cpu.register[0]);
+
+        // A fault in a running program halts at the instruction.
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&[0xAF, 0xFE, 0xF0, 0x33]).unwrap();
+        cpu.step();
+        assert_eq!(
+            StepOutcome::Fault(CpuFault {
+                pc: 0x202,
+                opcode: 0xF033,
+                kind: FaultKind::MemoryOutOfBounds(0x1000)
+            }),
+            cpu.step()
+        );
+    }
+
+    #[test]
+    fn load_store_increment_i_only_with_memory_quirk() {
+        let mut cpu = Cpu::new(Quirks::cosmac_vip());
+        cpu.i = 0x300;
+        cpu.execute(0xF355).unwrap();
+        assert_eq!(0x304, cpu.i);
+        cpu.execute(0xF265).unwrap();
+        assert_eq!(0x307, cpu.i);
+
+        let mut cpu = Cpu::new(Quirks::chip48());
+        cpu.i = 0x300;
+        cpu.execute(0xF355).unwrap();
+        cpu.execute(0xF265).unwrap();
+        assert_eq!(0x300, cpu.i);
+    }
+
+    #[test]
+    fn draw_clips_or_wraps_depending_on_quirk() {
+        let mut cpu = Cpu::new(Quirks::cosmac_vip());
+        cpu.memory[0] = 0xFF;
+        cpu.register[0] = 60;
+        cpu.execute(0xD011).unwrap();
+        assert_eq!(0, cpu.screen.get_screen_data()[0]);
+
+        let mut cpu = Cpu::new(Quirks::xo_chip());
+        cpu.memory[0] = 0xFF;
+        cpu.register[0] = 60;
+        cpu.execute(0xD011).unwrap();
+        assert_eq!(255, cpu.screen.get_screen_data()[0]);
+    }
+
+    #[test]
+    fn draw_waits_for_next_frame_with_display_wait_quirk() {
+        let mut cpu = Cpu::new(Quirks::cosmac_vip());
+        cpu.load_rom(&[0xD0, 0x01, 0x60, 0x01]).unwrap();
+
+        assert_eq!(StepOutcome::Executed, cpu.step());
+        assert_eq!(StepOutcome::Waiting, cpu.step());
+        assert_eq!(0x202, cpu.pc);
+
+        cpu.tick_timers();
+        assert_eq!(StepOutcome::Executed, cpu.step());
+        assert_eq!(1, cpu.register[0]);
+    }
+
+    #[test]
+    fn draw_does_not_wait_without_display_wait_quirk() {
+        let mut cpu = Cpu::new(Quirks::chip48());
+        cpu.load_rom(&[0xD0, 0x01, 0x60, 0x01]).unwrap();
+
+        assert_eq!(StepOutcome::Executed, cpu.step());
+        assert_eq!(StepOutcome::Executed, cpu.step());
+        assert_eq!(1, cpu.register[0]);
+    }
+
+    fn super_chip_cpu(rom: &[u8]) -> Cpu {
+        let mut cpu = Cpu::for_platform(Platform::SuperChip);
+        cpu.load_rom(rom).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn super_chip_instructions_are_unknown_on_chip8() {
+        let mut cpu = Cpu::new(Quirks::default());
+
+        assert_eq!(Err(FaultKind::UnknownOpcode), cpu.execute(0x00FF));
+        assert_eq!(Err(FaultKind::UnknownOpcode), cpu.execute(0xF030));
+        assert!(!cpu.screen.is_hires());
+    }
+
+    #[test]
+    fn high_and_low_switch_resolution() {
+        let mut cpu = super_chip_cpu(&[0x00, 0xFF, 0x00, 0xFE]);
+
+        cpu.step();
+        assert!(cpu.screen.is_hires());
+
+        cpu.step();
+        assert!(!cpu.screen.is_hires());
+    }
+
+    #[test]
+    fn scroll_instructions_move_the_screen() {
+        let mut cpu = super_chip_cpu(&[]);
+        cpu.screen.draw_sprite(8, 0, &[0x80]);
+
+        cpu.execute(0x00C2).unwrap();
+        cpu.execute(0x00FB).unwrap();
+        cpu.execute(0x00FB).unwrap();
+        cpu.execute(0x00FC).unwrap();
+
+        let pixels = cpu.screen.get_screen_data();
+        assert_eq!(255, pixels[2 * 64 + 12]);
+        assert_eq!(1, pixels.iter().filter(|&&pixel| pixel == 255).count());
+    }
+
+    #[test]
+    fn draw_with_zero_height_draws_large_sprite_on_super_chip() {
+        let mut cpu = super_chip_cpu(&[]);
+        cpu.screen.set_hires(true);
+        cpu.i = 0x300;
+        for byte in 0..32 {
+            cpu.memory[0x300 + byte] = 0xFF;
+        }
+
+        cpu.execute(0xD000).unwrap();
+
+        let pixels = cpu.screen.get_screen_data();
+        assert_eq!(
+            16 * 16,
+            pixels.iter().filter(|&&pixel| pixel == 255).count()
+        );
+        assert_eq!(255, pixels[15 * 128 + 15]);
+        assert_eq!(0, cpu.register[0xF]);
+    }
+
+    #[test]
+    fn draw_with_zero_height_draws_nothing_on_chip8() {
+        let mut cpu = Cpu::default();
+        cpu.memory[0] = 0xFF;
+
+        cpu.execute(0xD000).unwrap();
+
+        assert!(cpu.screen.get_screen_data().iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn ldhf_points_i_at_big_font_digit() {
+        let mut cpu = super_chip_cpu(&[]);
+        cpu.register[4] = 7;
+
+        cpu.execute(0xF430).unwrap();
+
+        assert_eq!(BIG_FONT_ADDRESS + 70, cpu.i);
+        assert_eq!(
+            &Font::SuperChipBig.glyphs()[70..80],
+            &cpu.memory[cpu.i as usize..cpu.i as usize + 10]
+        );
+    }
+
+    #[test]
+    fn load_font_moves_what_ldf_points_at() {
+        let mut cpu = Cpu::default();
+        cpu.load_font(Font::Eti660, 0x100).unwrap();
+        cpu.register[0] = 0xD;
+
+        cpu.execute(0xF029).unwrap();
+
+        assert_eq!(0x141, cpu.i);
+        assert_eq!(
+            &Font::Eti660.glyphs()[65..70],
+            &cpu.memory[cpu.i as usize..cpu.i as usize + 5]
+        );
+    }
+
+    #[test]
+    fn load_custom_font_accepts_a_sixteen_glyph_big_font() {
+        let glyphs: Vec<u8> = (0..160).map(|byte| byte as u8).collect();
+        let mut cpu = super_chip_cpu(&[]);
+        cpu.load_custom_font(&glyphs, FontSize::Big, 0x0).unwrap();
+        cpu.register[0] = 0xF;
+
+        cpu.execute(0xF030).unwrap();
+
+        assert_eq!(150, cpu.i);
+        assert_eq!(&glyphs[150..], &cpu.memory[150..160]);
+    }
+
+    #[test]
+    fn load_custom_font_rejects_fonts_that_do_not_fit() {
+        let mut cpu = Cpu::default();
+        let before = cpu.save_state();
+
+        assert_eq!(
+            Err(FontError::OutOfMemory(0xFC0)),
+            cpu.load_font(Font::CosmacVip, 0xFC0)
+        );
+        assert_eq!(
+            Err(FontError::BadLength(FontSize::Small, 3)),
+            cpu.load_custom_font(&[1, 2, 3], FontSize::Small, 0x50)
+        );
+        assert_eq!(before, cpu.save_state());
+    }
+
+    #[test]
+    fn load_state_restores_font_addresses() {
+        let mut cpu = Cpu::default();
+        cpu.load_font(Font::Dream6800, 0x180).unwrap();
+        let state = cpu.save_state();
+
+        let mut restored = Cpu::default();
+        restored.load_state(&state).unwrap();
+        restored.register[0] = 1;
+        restored.execute(0xF029).unwrap();
+
+        assert_eq!(0x185, restored.i);
+    }
+
+    #[test]
+    fn load_rom_rejects_roms_that_do_not_fit() {
+        let mut cpu = Cpu::default();
+        let before = cpu.save_state();
+
+        assert_eq!(
+            Err(LoadError::TooLarge {
+                len: 3585,
+                max: 3584
+            }),
+            cpu.load_rom(&[0; 3585])
+        );
+        assert_eq!(
+            Err(LoadError::AddressOutOfRange(0x1000)),
+            cpu.load_rom_at(&[0x12, 0x00], 0x1000)
+        );
+        assert_eq!(before, cpu.save_state());
+
+        cpu.load_rom(&[0xFF; 3584]).unwrap();
+        assert_eq!(0xFF, cpu.memory[0xFFF]);
+    }
+
+    #[test]
+    fn load_rom_at_starts_at_the_load_address() {
+        let mut cpu = Cpu::default();
+        cpu.load_rom_at(&[0x60, 0x2A, 0x16, 0x02], 0x600).unwrap();
+
+        cpu.step();
+
+        assert_eq!(0x2A, cpu.register[0]);
+        assert_eq!(0x602, cpu.pc);
+        assert!(cpu.memory[0x200..0x600].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn load_rom_resets_the_machine() {
+        // v0 := 0x22, delay := v0, i := 0x280, sprite 0 0 5, call 0x20C, 0x20C: jump 0x20C
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&[
+            0x60, 0x22, 0xF0, 0x15, 0xA2, 0x80, 0xD0, 0x05, 0x22, 0x0C, 0x00, 0x00, 0x12, 0x0C,
+        ])
+        .unwrap();
+        cpu.quirks.display_wait = false;
+        cpu.load_font(Font::Dream6800, 0x180).unwrap();
+        for _ in 0..6 {
+            cpu.step();
+        }
+
+        cpu.load_rom(&[0x12, 0x00]).unwrap();
+
+        assert_eq!(0x200, cpu.pc);
+        assert_eq!([0; 16], cpu.register);
+        assert_eq!(0, cpu.i);
+        assert_eq!(0, cpu.sp);
+        assert_eq!(0, cpu.timers.delay);
+        assert!(!cpu.screen.get(0, 0));
+        assert!(cpu.memory[0x202..0x20E].iter().all(|&byte| byte == 0));
+        assert!(!cpu.quirks.display_wait);
+        assert_eq!(Font::Dream6800.glyphs(), &cpu.memory[0x180..0x1D0]);
+        assert_eq!(
+            Font::SuperChipBig.glyphs(),
+            &cpu.memory[BIG_FONT_ADDRESS as usize..BIG_FONT_ADDRESS as usize + 100]
+        );
+    }
+
+    #[test]
+    fn rpl_flags_round_trip_registers() {
+        let mut cpu = super_chip_cpu(&[]);
+        for register in 0..8 {
+            cpu.register[register] = register as u8 + 1;
+        }
+
+        cpu.execute(0xF775).unwrap();
+        cpu.register = [0; 16];
+        cpu.execute(0xF385).unwrap();
+
+        assert_eq!(&[1, 2, 3, 4, 0, 0, 0, 0], &cpu.register[..8]);
+    }
+
+    #[test]
+    fn exit_stops_the_cpu() {
+        let mut cpu = super_chip_cpu(&[0x00, 0xFD, 0x60, 0x01]);
+
+        assert_eq!(StepOutcome::Exited, cpu.step());
+        assert_eq!(StepOutcome::Exited, cpu.step());
+        assert_eq!(0, cpu.register[0]);
+    }
+
+    fn xo_chip_cpu(rom: &[u8]) -> Cpu {
+        let mut cpu = Cpu::for_platform(Platform::XoChip);
+        cpu.load_rom(rom).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn xo_chip_has_64k_of_memory() {
+        let mut cpu = xo_chip_cpu(&[]);
+        cpu.i = 0xFFF0;
+        cpu.register[0] = 0x12;
+
+        cpu.execute(0xF033).unwrap();
+
+        assert_eq!(65536, cpu.memory.len());
+        assert_eq!(&[0, 1, 8], &cpu.memory[0xFFF0..0xFFF3]);
+    }
+
+    #[test]
+    fn xo_chip_instructions_are_unknown_on_super_chip() {
+        let mut cpu = super_chip_cpu(&[]);
+
+        assert_eq!(Err(FaultKind::UnknownOpcode), cpu.execute(0xF000));
+        assert_eq!(Err(FaultKind::UnknownOpcode), cpu.execute(0x5012));
+        assert_eq!(Err(FaultKind::UnknownOpcode), cpu.execute(0xF101));
+    }
+
+    #[test]
+    fn long_load_reads_following_word() {
+        let mut cpu = xo_chip_cpu(&[0xF0, 0x00, 0xAB, 0xCD, 0x60, 0x01]);
+
+        cpu.step();
+        assert_eq!(0xABCD, cpu.i);
+        assert_eq!(0x204, cpu.pc);
+
+        cpu.step();
+        assert_eq!(1, cpu.register[0]);
+    }
+
+    #[test]
+    fn skip_jumps_over_long_load() {
+        let mut cpu = xo_chip_cpu(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x61, 0x01]);
+
+        cpu.step();
+        assert_eq!(0x206, cpu.pc);
+
+        cpu.step();
+        assert_eq!(1, cpu.register[1]);
+        assert_eq!(0, cpu.i);
+    }
+
+    #[test]
+    fn save_and_load_register_ranges() {
+        let mut cpu = xo_chip_cpu(&[]);
+        cpu.i = 0x400;
+        cpu.register[2] = 0xA;
+        cpu.register[3] = 0xB;
+        cpu.register[4] = 0xC;
+
+        cpu.execute(0x5242).unwrap();
+        assert_eq!(&[0xA, 0xB, 0xC], &cpu.memory[0x400..0x403]);
+        assert_eq!(0x400, cpu.i);
+
+        cpu.execute(0x5422).unwrap();
+        assert_eq!(&[0xC, 0xB, 0xA], &cpu.memory[0x400..0x403]);
+
+        cpu.execute(0x5793).unwrap();
+        assert_eq!(&[0xC, 0xB, 0xA], &cpu.register[7..10]);
+    }
+
+    #[test]
+    fn plane_selects_bitplanes_for_drawing() {
+        let mut cpu = xo_chip_cpu(&[]);
+        cpu.i = 0x300;
+        cpu.memory[0x300] = 0x80;
+        cpu.memory[0x301] = 0xC0;
+
+        cpu.execute(0xF301).unwrap();
+        cpu.execute(0xD001).unwrap();
+
+        let colors = cpu.screen.get_plane_data();
+        assert_eq!(3, colors[0]);
+        assert_eq!(2, colors[1]);
+    }
+
+    #[test]
+    fn audio_and_pitch_load_audio_state() {
+        let mut cpu = xo_chip_cpu(&[]);
+        cpu.i = 0x300;
+        for byte in 0..16 {
+            cpu.memory[0x300 + byte] = byte as u8;
+        }
+        cpu.register[5] = 100;
+
+        cpu.execute(0xF002).unwrap();
+        cpu.execute(0xF53A).unwrap();
+
+        assert_eq!(15, cpu.audio.pattern[15]);
+        assert_eq!(100, cpu.audio.pitch);
+    }
+
+    #[test]
+    fn scroll_up_on_xo_chip() {
+        let mut cpu = xo_chip_cpu(&[]);
+        cpu.screen.draw_sprite(0, 3, &[0x80]);
+
+        cpu.execute(0x00D3).unwrap();
+
+        assert_eq!(255, cpu.screen.get_screen_data()[0]);
+    }
+
+    #[test]
+    fn runs_with_same_seed_are_identical() {
+        // A050: I = glyph 0, C0FF: V0 = random, D015: draw it at V0, V1,
+        // 7101: V1 += 1, 1202: loop back to C0FF
+        let rom = [0xA0, 0x50, 0xC0, 0xFF, 0xD0, 0x15, 0x71, 0x01, 0x12, 0x02];
+        let run = |seed| {
+            let mut cpu = Cpu::new(Quirks::chip48());
+            cpu.seed_rng(seed);
+            cpu.load_rom(&rom).unwrap();
+            for _ in 0..200 {
+                cpu.step();
+            }
+            cpu.screen.get_screen_data()
+        };
+
+        assert_eq!(run(99), run(99));
+        assert_ne!(run(99), run(100));
+    }
+
+    /// Runs a program that touches most of the machine: random numbers,
+    /// timers, memory, the stack and the screen.
+    fn busy_cpu() -> Cpu {
+        // 2206: call 0x206, 1202: loop, C0FF: V0 = random, F015: DT = V0,
+        // F018: ST = V0, A300: I = 0x300, F033: BCD of V0, D015: draw,
+        // 00EE: return
+        let rom = [
+            0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0xC0, 0xFF, 0xF0, 0x15, 0xF0, 0x18, 0xA3, 0x00,
+            0xF0, 0x33, 0xD0, 0x15, 0x00, 0xEE,
+        ];
+        let mut cpu = Cpu::new(Quirks::chip48());
+        cpu.seed_rng(5);
+        cpu.load_rom(&rom).unwrap();
+        cpu.keypad.key_down(0xB);
+        for _ in 0..5 {
+            cpu.step();
+        }
+        cpu.update_timers(0.5 / 60.0);
+        cpu
+    }
+
+    #[test]
+    fn save_and_load_state_round_trips_the_machine() {
+        let mut cpu = busy_cpu();
+        let state = cpu.save_state();
+
+        let mut restored = Cpu::default();
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(state, restored.save_state());
+        for _ in 0..50 {
+            assert_eq!(cpu.step(), restored.step());
+            cpu.update_timers(1.0 / 120.0);
+            restored.update_timers(1.0 / 120.0);
+        }
+        assert_eq!(cpu.save_state(), restored.save_state());
+        assert!(restored.keypad.is_pressed(0xB));
+    }
+
+    #[test]
+    fn load_state_restores_platform_and_screen_mode() {
+        let mut cpu = xo_chip_cpu(&[0x00, 0xFF, 0xF3, 0x01]);
+        cpu.step();
+        cpu.step();
+        let state = cpu.save_state();
+
+        let mut restored = Cpu::default();
+        restored.load_state(&state).unwrap();
+
+        assert_eq!(Platform::XoChip, restored.platform);
+        assert_eq!(Quirks::xo_chip(), restored.quirks);
+        assert!(restored.screen.is_hires());
+        assert_eq!(3, restored.screen.selected_planes());
+        assert_eq!(65536, restored.memory.len());
+    }
+
+    #[test]
+    fn load_state_rejects_corrupt_data_and_keeps_cpu() {
+        let mut state = busy_cpu().save_state();
+        state[40] ^= 0x01;
+        let mut cpu = Cpu::default();
+        let before = cpu.save_state();
+
+        assert_eq!(Err(StateError::ChecksumMismatch), cpu.load_state(&state));
+        assert_eq!(Err(StateError::BadMagic), cpu.load_state(b"not a state"));
+        assert_eq!(before, cpu.save_state());
+    }
+
+    #[test]
+    fn arithmetic_wraps_and_sets_flags_for_every_operand_pair() {
+        let mut cpu = Cpu::default();
+        for a in 0..=255u8 {
+            for b in 0..=255u8 {
+                cpu.register[2] = a;
+                cpu.register[0xF] = 0x42;
+                cpu.execute(0x7200 | b as u16).unwrap();
+                assert_eq!(
+                    (a.wrapping_add(b), 0x42),
+                    (cpu.register[2], cpu.register[0xF]),
+                    "72{:02X} with {:#04x}",
+                    b,
+                    a
+                );
+
+                let carry = (a as u16 + b as u16 > 0xFF) as u8;
+                for &(opcode, result, flag) in &[
+                    (0x8234, a.wrapping_add(b), carry),
+                    (0x8235, a.wrapping_sub(b), (a >= b) as u8),
+                    (0x8237, b.wrapping_sub(a), (b >= a) as u8),
+                ] {
+                    cpu.register[2] = a;
+                    cpu.register[3] = b;
+                    cpu.execute(opcode).unwrap();
+                    assert_eq!(
+                        (result, flag),
+                        (cpu.register[2], cpu.register[0xF]),
+                        "{:04X} with {:#04x}, {:#04x}",
+                        opcode,
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arithmetic_writes_vf_last() {
+        let mut cpu = Cpu::default();
+        for a in 0..=255u8 {
+            for b in 0..=255u8 {
+                let carry = (a as u16 + b as u16 > 0xFF) as u8;
+
+                // With X = F the flag replaces the result.
+                for &(opcode, flag) in &[
+                    (0x8F34, carry),
+                    (0x8F35, (a >= b) as u8),
+                    (0x8F37, (b >= a) as u8),
+                ] {
+                    cpu.register[0xF] = a;
+                    cpu.register[3] = b;
+                    cpu.execute(opcode).unwrap();
+                    assert_eq!(
+                        flag, cpu.register[0xF],
+                        "{:04X} with {:#04x}, {:#04x}",
+                        opcode, a, b
+                    );
+                }
+
+                // With Y = F the operand is read before the flag is written.
+                for &(opcode, result, flag) in &[
+                    (0x82F4, a.wrapping_add(b), carry),
+                    (0x82F5, a.wrapping_sub(b), (a >= b) as u8),
+                    (0x82F7, b.wrapping_sub(a), (b >= a) as u8),
+                ] {
+                    cpu.register[2] = a;
+                    cpu.register[0xF] = b;
+                    cpu.execute(opcode).unwrap();
+                    assert_eq!(
+                        (result, flag),
+                        (cpu.register[2], cpu.register[0xF]),
+                        "{:04X} with {:#04x}, {:#04x}",
+                        opcode,
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tetris_runs_without_overflowing() {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(include_bytes!("../../roms/tetris.rom"))
+            .unwrap();
+        for _ in 0..600 {
+            let outcome = cpu.run_frame(10);
+            assert!(
+                outcome == StepOutcome::Executed || outcome == StepOutcome::Waiting,
+                "{:?}",
+                outcome
+            );
+        }
+    }
 }
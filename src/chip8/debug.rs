@@ -0,0 +1,825 @@
+use crate::chip8::cpu::Cpu;
+use crate::chip8::fault::{CpuFault, StepOutcome};
+use crate::chip8::opcode::{self, Opcode};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+
+/// How many frames step-over and step-out run looking for the return before
+/// giving up, so a subroutine that never returns doesn't hang the caller.
+pub const STEP_LIMIT_FRAMES: u64 = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A range of memory an instruction read or wrote through I.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub start: u16,
+    pub len: u16,
+}
+
+impl MemoryAccess {
+    fn overlaps(&self, range: &Range<u16>) -> bool {
+        let end = self.start as u32 + self.len as u32;
+        self.start < range.end && end > range.start as u32
+    }
+}
+
+/// Which accesses a watchpoint stops on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    Access,
+}
+
+impl Watch {
+    /// Looks a watch kind up by name: `read`, `write` or `access`.
+    pub fn from_name(name: &str) -> Option<Watch> {
+        match name {
+            "read" => Some(Watch::Read),
+            "write" => Some(Watch::Write),
+            "access" => Some(Watch::Access),
+            _ => None,
+        }
+    }
+
+    fn matches(self, kind: AccessKind) -> bool {
+        match self {
+            Watch::Read => kind == AccessKind::Read,
+            Watch::Write => kind == AccessKind::Write,
+            Watch::Access => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Watchpoint {
+    range: Range<u16>,
+    watch: Watch,
+}
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The cpu reached a breakpoint at this address, whose condition held.
+    Breakpoint(u16),
+    /// The instruction at `pc` touched watched memory.
+    Watchpoint {
+        pc: u16,
+        access: MemoryAccess,
+    },
+    /// A step finished.
+    Stepped,
+    /// `run_until_frame` reached its frame.
+    Frame,
+    /// Step-over or step-out ran for `STEP_LIMIT_FRAMES` without returning.
+    StepLimit,
+    Exited,
+    Fault(CpuFault),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Breakpoint(address) => write!(f, "breakpoint at {:#05x}", address),
+            Stop::Watchpoint { pc, access } => write!(
+                f,
+                "{} of {:#05x}..{:#05x} at {:#05x}",
+                match access.kind {
+                    AccessKind::Read => "read",
+                    AccessKind::Write => "write",
+                },
+                access.start,
+                access.start as u32 + access.len as u32,
+                pc
+            ),
+            Stop::Stepped => write!(f, "stepped"),
+            Stop::Frame => write!(f, "reached frame"),
+            Stop::StepLimit => write!(f, "gave up waiting for the subroutine to return"),
+            Stop::Exited => write!(f, "program exited"),
+            Stop::Fault(fault) => write!(f, "cpu halted: {}", fault),
+        }
+    }
+}
+
+/// The `Debugger` type. Drives a `Cpu` a frame or an instruction at a time,
+/// stopping at breakpoints and watchpoints.
+///
+/// The debugger owns the frame timing: it runs `cycles_per_frame`
+/// instructions per 60 Hz frame and ticks the timers in between, so stepping
+/// through a program keeps the timers in step with the instructions.
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    watchpoints: Vec<Watchpoint>,
+    cycles_per_frame: u32,
+    cycles_left: u32,
+    frame: u64,
+    /// The breakpoint just stopped at, which doesn't stop the cpu again
+    /// until it moves on.
+    stopped_at: Option<u16>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new(10)
+    }
+}
+
+impl Debugger {
+    pub fn new(cycles_per_frame: u32) -> Debugger {
+        let cycles_per_frame = cycles_per_frame.max(1);
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            cycles_per_frame,
+            cycles_left: cycles_per_frame,
+            frame: 0,
+            stopped_at: None,
+        }
+    }
+
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame
+    }
+
+    /// Changes the instructions run per frame, from the next frame on.
+    pub fn set_cycles_per_frame(&mut self, cycles_per_frame: u32) {
+        self.cycles_per_frame = cycles_per_frame.max(1);
+        self.cycles_left = self.cycles_left.min(self.cycles_per_frame);
+    }
+
+    /// Frames completed since the debugger was created or reset.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Starts counting frames from zero again, keeping breakpoints and
+    /// watchpoints. Call this after loading a program.
+    pub fn reset(&mut self) {
+        self.cycles_left = self.cycles_per_frame;
+        self.frame = 0;
+        self.stopped_at = None;
+    }
+
+    /// Stops at `address`, only when `condition` holds if one is given.
+    /// Replaces any breakpoint already at `address`.
+    pub fn set_breakpoint(&mut self, address: u16, condition: Option<Condition>) {
+        self.breakpoints.insert(address, condition);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    /// The breakpoints by address, with their conditions.
+    pub fn breakpoints(&self) -> impl Iterator<Item = (u16, Option<&Condition>)> {
+        self.breakpoints
+            .iter()
+            .map(|(&address, condition)| (address, condition.as_ref()))
+    }
+
+    /// Stops after any instruction that reads or writes memory in `range`,
+    /// as selected by `watch`.
+    pub fn add_watchpoint(&mut self, range: Range<u16>, watch: Watch) {
+        let watchpoint = Watchpoint { range, watch };
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, range: Range<u16>, watch: Watch) -> bool {
+        let removed = Watchpoint { range, watch };
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| *watchpoint != removed);
+        self.watchpoints.len() != len
+    }
+
+    /// Removes every breakpoint and watchpoint.
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.stopped_at = None;
+    }
+
+    /// Runs the rest of the current frame. Returns `None` when the frame
+    /// completes without stopping.
+    pub fn run_frame(&mut self, cpu: &mut Cpu) -> Option<Stop> {
+        let frame = self.frame;
+        while self.frame == frame {
+            if let Err(stop) = self.cycle(cpu, true) {
+                return Some(stop);
+            }
+        }
+        None
+    }
+
+    /// Runs frames until `frame` frames have completed.
+    pub fn run_until_frame(&mut self, cpu: &mut Cpu, frame: u64) -> Stop {
+        while self.frame < frame {
+            if let Some(stop) = self.run_frame(cpu) {
+                return stop;
+            }
+        }
+        Stop::Frame
+    }
+
+    /// Executes the instruction at the program counter, even when there is
+    /// a breakpoint on it.
+    pub fn step_into(&mut self, cpu: &mut Cpu) -> Stop {
+        loop {
+            match self.cycle(cpu, false) {
+                Ok(StepOutcome::Waiting) => continue,
+                Ok(_) => return Stop::Stepped,
+                Err(stop) => return stop,
+            }
+        }
+    }
+
+    /// Like `step_into`, but runs a called subroutine until it returns.
+    pub fn step_over(&mut self, cpu: &mut Cpu) -> Stop {
+        let pc = cpu.pc();
+        let is_call = matches!(
+            cpu.word_at(pc).map(opcode::decode),
+            Some(Ok(Opcode::CALL(_)))
+        );
+        if !is_call {
+            return self.step_into(cpu);
+        }
+
+        let sp = cpu.sp();
+        let return_address = pc.wrapping_add(2);
+        self.run_until(cpu, |cpu| cpu.sp() == sp && cpu.pc() == return_address)
+    }
+
+    /// Runs until the current subroutine returns. Outside of a subroutine
+    /// this is a single step.
+    pub fn step_out(&mut self, cpu: &mut Cpu) -> Stop {
+        let sp = cpu.sp();
+        if sp == 0 {
+            return self.step_into(cpu);
+        }
+
+        self.run_until(cpu, |cpu| cpu.sp() < sp)
+    }
+
+    fn run_until(&mut self, cpu: &mut Cpu, done: impl Fn(&Cpu) -> bool) -> Stop {
+        match self.step_into(cpu) {
+            Stop::Stepped => (),
+            stop => return stop,
+        }
+
+        let start = self.frame;
+        while !done(cpu) {
+            if self.frame - start >= STEP_LIMIT_FRAMES {
+                return Stop::StepLimit;
+            }
+            if let Err(stop) = self.cycle(cpu, true) {
+                return stop;
+            }
+        }
+        Stop::Stepped
+    }
+
+    /// Executes one instruction, checking breakpoints beforehand if
+    /// `check_breakpoints` and watchpoints afterwards, and ends the frame
+    /// when its cycles are used up or the cpu waits for the display.
+    fn cycle(&mut self, cpu: &mut Cpu, check_breakpoints: bool) -> Result<StepOutcome, Stop> {
+        let pc = cpu.pc();
+        if self.stopped_at.is_some_and(|address| address != pc) {
+            self.stopped_at = None;
+        }
+        if check_breakpoints && self.stopped_at.is_none() && self.breakpoint_hit(cpu) {
+            self.stopped_at = Some(pc);
+            return Err(Stop::Breakpoint(pc));
+        }
+
+        let outcome = cpu.step();
+        match outcome {
+            StepOutcome::Executed => {
+                self.cycles_left -= 1;
+                if self.cycles_left == 0 {
+                    self.end_frame(cpu);
+                }
+            }
+            StepOutcome::Waiting => self.end_frame(cpu),
+            StepOutcome::Exited => return Err(Stop::Exited),
+            StepOutcome::Fault(fault) => return Err(Stop::Fault(fault)),
+        }
+
+        if let Some(access) = cpu.last_access() {
+            let watched = self.watchpoints.iter().any(|watchpoint| {
+                watchpoint.watch.matches(access.kind) && access.overlaps(&watchpoint.range)
+            });
+            if watched {
+                return Err(Stop::Watchpoint { pc, access });
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    fn end_frame(&mut self, cpu: &mut Cpu) {
+        cpu.tick_timers();
+        self.cycles_left = self.cycles_per_frame;
+        self.frame += 1;
+    }
+
+    fn breakpoint_hit(&self, cpu: &Cpu) -> bool {
+        match self.breakpoints.get(&cpu.pc()) {
+            Some(Some(condition)) => condition.holds(cpu),
+            Some(None) => true,
+            None => false,
+        }
+    }
+}
+
+/// An error in a breakpoint condition, with the 1 based column it was
+/// found at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConditionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+/// A breakpoint condition over the machine state, such as
+/// `v3 == 0x10 && [i + 1] != 0`.
+///
+/// Values are numbers, the registers `v0` to `vf`, `i`, `pc`, `sp`, `dt`
+/// and `st`, and `[address]` for the byte in memory at an address. They can
+/// be added and subtracted and compared with `== != < <= > >=`; a value on
+/// its own holds when it isn't zero. Comparisons combine with `&&`, which
+/// binds tighter, and `||`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    text: String,
+    /// Holds when all comparisons of any group hold.
+    any: Vec<Vec<Comparison>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Comparison {
+    left: Value,
+    compare: Option<(Compare, Value)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compare {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Number(i64),
+    Register(u8),
+    I,
+    Pc,
+    Sp,
+    Delay,
+    Sound,
+    Memory(Box<Value>),
+    Add(Box<Value>, Box<Value>),
+    Sub(Box<Value>, Box<Value>),
+}
+
+impl Value {
+    fn evaluate(&self, cpu: &Cpu) -> i64 {
+        match self {
+            Value::Number(value) => *value,
+            Value::Register(x) => cpu.registers()[*x as usize] as i64,
+            Value::I => cpu.i() as i64,
+            Value::Pc => cpu.pc() as i64,
+            Value::Sp => cpu.sp() as i64,
+            Value::Delay => cpu.timers.delay as i64,
+            Value::Sound => cpu.timers.sound as i64,
+            Value::Memory(address) => usize::try_from(address.evaluate(cpu))
+                .ok()
+                .and_then(|address| cpu.memory().get(address))
+                .map_or(0, |&byte| byte as i64),
+            Value::Add(left, right) => left.evaluate(cpu).wrapping_add(right.evaluate(cpu)),
+            Value::Sub(left, right) => left.evaluate(cpu).wrapping_sub(right.evaluate(cpu)),
+        }
+    }
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, ConditionError> {
+        let tokens = lex(text)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            end: text.chars().count() + 1,
+        };
+
+        let mut any = vec![vec![parser.comparison()?]];
+        while let Some((column, token)) = parser.next() {
+            match token.as_str() {
+                "&&" => any.last_mut().unwrap().push(parser.comparison()?),
+                "||" => any.push(vec![parser.comparison()?]),
+                _ => return Err(error(column, format!("unexpected `{}`", token))),
+            }
+        }
+
+        Ok(Condition {
+            text: text.trim().to_string(),
+            any,
+        })
+    }
+
+    /// Whether the condition holds for the current state of `cpu`.
+    pub fn holds(&self, cpu: &Cpu) -> bool {
+        self.any.iter().any(|all| {
+            all.iter().all(|comparison| {
+                let left = comparison.left.evaluate(cpu);
+                match &comparison.compare {
+                    None => left != 0,
+                    Some((compare, right)) => {
+                        let right = right.evaluate(cpu);
+                        match compare {
+                            Compare::Equal => left == right,
+                            Compare::NotEqual => left != right,
+                            Compare::Less => left < right,
+                            Compare::LessOrEqual => left <= right,
+                            Compare::Greater => left > right,
+                            Compare::GreaterOrEqual => left >= right,
+                        }
+                    }
+                }
+            })
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+fn error(column: usize, message: impl Into<String>) -> ConditionError {
+    ConditionError {
+        column,
+        message: message.into(),
+    }
+}
+
+/// Splits a condition into words and operators, with their columns.
+fn lex(text: &str) -> Result<Vec<(usize, String)>, ConditionError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    while position < chars.len() {
+        let c = chars[position];
+        let column = position + 1;
+        if c.is_whitespace() {
+            position += 1;
+        } else if c.is_ascii_alphanumeric() {
+            let start = position;
+            while position < chars.len() && chars[position].is_ascii_alphanumeric() {
+                position += 1;
+            }
+            tokens.push((column, chars[start..position].iter().collect()));
+        } else {
+            let pair: String = chars[position..chars.len().min(position + 2)]
+                .iter()
+                .collect();
+            let operator = ["==", "!=", "<=", ">=", "&&", "||"]
+                .iter()
+                .find(|operator| pair == **operator)
+                .map(|operator| operator.to_string())
+                .or_else(|| "<>[]+-".contains(c).then(|| c.to_string()))
+                .ok_or_else(|| error(column, format!("unexpected `{}`", c)))?;
+            position += operator.len();
+            tokens.push((column, operator));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(usize, String)],
+    position: usize,
+    /// The column just past the text, for errors at its end.
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<(usize, String)> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens
+            .get(self.position)
+            .map(|(_, text)| text.as_str())
+    }
+
+    fn comparison(&mut self) -> Result<Comparison, ConditionError> {
+        let left = self.value()?;
+        let compare = match self.peek() {
+            Some("==") => Compare::Equal,
+            Some("!=") => Compare::NotEqual,
+            Some("<") => Compare::Less,
+            Some("<=") => Compare::LessOrEqual,
+            Some(">") => Compare::Greater,
+            Some(">=") => Compare::GreaterOrEqual,
+            _ => {
+                return Ok(Comparison {
+                    left,
+                    compare: None,
+                })
+            }
+        };
+        self.position += 1;
+        let right = self.value()?;
+        Ok(Comparison {
+            left,
+            compare: Some((compare, right)),
+        })
+    }
+
+    fn value(&mut self) -> Result<Value, ConditionError> {
+        let mut value = self.term()?;
+        loop {
+            match self.peek() {
+                Some("+") => {
+                    self.position += 1;
+                    value = Value::Add(Box::new(value), Box::new(self.term()?));
+                }
+                Some("-") => {
+                    self.position += 1;
+                    value = Value::Sub(Box::new(value), Box::new(self.term()?));
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Value, ConditionError> {
+        let (column, token) = self
+            .next()
+            .ok_or_else(|| error(self.end, "expected a value"))?;
+        let lower = token.to_ascii_lowercase();
+
+        let value = match lower.as_str() {
+            "[" => {
+                let address = self.value()?;
+                match self.next() {
+                    Some((_, close)) if close == "]" => Value::Memory(Box::new(address)),
+                    _ => return Err(error(column, "missing `]`")),
+                }
+            }
+            "i" => Value::I,
+            "pc" => Value::Pc,
+            "sp" => Value::Sp,
+            "dt" => Value::Delay,
+            "st" => Value::Sound,
+            _ => {
+                let register = lower
+                    .strip_prefix('v')
+                    .filter(|index| index.len() == 1)
+                    .and_then(|index| u8::from_str_radix(index, 16).ok());
+                let number = match lower.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16).ok(),
+                    None => lower.parse().ok(),
+                };
+                match (register, number) {
+                    (Some(x), _) => Value::Register(x),
+                    (None, Some(number)) => Value::Number(number),
+                    _ => {
+                        return Err(error(
+                            column,
+                            format!("expected a value, found `{}`", token),
+                        ))
+                    }
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cpu running `program` from 0x200.
+    fn cpu(program: &[u16]) -> Cpu {
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&rom).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn stops_at_breakpoints_and_continues_past_them() {
+        // 0x200: v0 += 1, jump 0x200
+        let mut cpu = cpu(&[0x7001, 0x1200]);
+        let mut debugger = Debugger::new(10);
+        debugger.set_breakpoint(0x202, None);
+
+        assert_eq!(Some(Stop::Breakpoint(0x202)), debugger.run_frame(&mut cpu));
+        assert_eq!(1, cpu.registers()[0]);
+        assert_eq!(Some(Stop::Breakpoint(0x202)), debugger.run_frame(&mut cpu));
+        assert_eq!(2, cpu.registers()[0]);
+
+        debugger.remove_breakpoint(0x202);
+        assert_eq!(None, debugger.run_frame(&mut cpu));
+        assert_eq!(1, debugger.frame());
+    }
+
+    #[test]
+    fn conditional_breakpoints_stop_only_when_the_condition_holds() {
+        let mut cpu = cpu(&[0x7001, 0x1200]);
+        let mut debugger = Debugger::new(100);
+        let condition = Condition::parse("v0 == 5 || v0 >= 0x20").unwrap();
+        debugger.set_breakpoint(0x202, Some(condition));
+
+        assert_eq!(Some(Stop::Breakpoint(0x202)), debugger.run_frame(&mut cpu));
+        assert_eq!(5, cpu.registers()[0]);
+        assert_eq!(Some(Stop::Breakpoint(0x202)), debugger.run_frame(&mut cpu));
+        assert_eq!(0x20, cpu.registers()[0]);
+    }
+
+    #[test]
+    fn conditions_read_registers_and_memory() {
+        // i := 0x200
+        let mut cpu = cpu(&[0xA200]);
+        cpu.step();
+
+        let holds = |text: &str| Condition::parse(text).unwrap().holds(&cpu);
+
+        assert!(holds("i == 0x200 && pc == 0x202"));
+        assert!(holds("[i] == 0xA2 && [i + 1] == 0"));
+        assert!(holds("[pc - 2] == 0xa2"));
+        assert!(holds("sp == 0 && dt == 0 && st == 0 && VF == 0"));
+        assert!(holds("i - 0x1FF"));
+        assert!(!holds("v0"));
+        assert!(!holds("i < 0x200 || i > 0x200"));
+        assert!(holds("i <= 0x200 && i >= 512 && i != 0"));
+        assert!(holds("0x7FFFFFFFFFFFFFFF + 1 < 0"));
+        assert!(holds("0 - 0x7FFFFFFFFFFFFFFF - 2 > 0"));
+    }
+
+    #[test]
+    fn condition_errors_point_at_the_column() {
+        let parse = |text: &str| Condition::parse(text).unwrap_err().to_string();
+
+        assert_eq!("column 7: unexpected `=`", parse("v0 == =5"));
+        assert_eq!("column 7: expected a value, found `vg`", parse("v0 == vg"));
+        assert_eq!("column 1: missing `]`", parse("[i == 3"));
+        assert_eq!("column 7: expected a value", parse("v0 == "));
+        assert_eq!("column 4: unexpected `v1`", parse("v0 v1"));
+    }
+
+    #[test]
+    fn watchpoints_stop_after_the_access() {
+        // i := 0x300, save v2, i := 0x300, load v1, jump 0x208
+        let mut cpu = cpu(&[0xA300, 0xF255, 0xA300, 0xF165, 0x1208]);
+        let mut debugger = Debugger::new(10);
+        debugger.add_watchpoint(0x301..0x302, Watch::Write);
+        debugger.add_watchpoint(0x300..0x301, Watch::Read);
+
+        let stop = debugger.run_frame(&mut cpu).unwrap();
+        let access = cpu.last_access().unwrap();
+        assert_eq!(Stop::Watchpoint { pc: 0x202, access }, stop);
+        assert_eq!(AccessKind::Write, access.kind);
+        assert_eq!(0x300, access.start);
+
+        let stop = debugger.run_frame(&mut cpu).unwrap();
+        let access = cpu.last_access().unwrap();
+        assert_eq!(Stop::Watchpoint { pc: 0x206, access }, stop);
+        assert_eq!(AccessKind::Read, access.kind);
+
+        assert!(debugger.remove_watchpoint(0x300..0x301, Watch::Read));
+        assert!(!debugger.remove_watchpoint(0x300..0x301, Watch::Read));
+        assert_eq!(None, debugger.run_frame(&mut cpu));
+    }
+
+    #[test]
+    fn watchpoints_fire_once_when_the_draw_waits_for_the_display() {
+        // i := 0x300, sprite v0 v0 5, v1 := 1, jump 0x206
+        let mut cpu = cpu(&[0xA300, 0xD005, 0x6101, 0x1206]);
+        assert!(cpu.quirks.display_wait);
+        let mut debugger = Debugger::new(10);
+        debugger.add_watchpoint(0x300..0x305, Watch::Read);
+
+        let stop = debugger.run_frame(&mut cpu).unwrap();
+        let access = cpu.last_access().unwrap();
+        assert_eq!(Stop::Watchpoint { pc: 0x202, access }, stop);
+
+        assert_eq!(None, debugger.run_frame(&mut cpu));
+        assert_eq!(None, debugger.run_frame(&mut cpu));
+        assert_eq!(1, cpu.registers()[1]);
+    }
+
+    #[test]
+    fn accesses_outside_the_watched_range_do_not_stop() {
+        // i := 0x300, save v3, jump 0x204
+        let mut cpu = cpu(&[0xA300, 0xF355, 0x1204]);
+        let mut debugger = Debugger::new(10);
+        debugger.add_watchpoint(0x2F0..0x300, Watch::Access);
+        debugger.add_watchpoint(0x304..0x310, Watch::Access);
+
+        assert_eq!(None, debugger.run_frame(&mut cpu));
+    }
+
+    #[test]
+    fn step_over_runs_the_whole_subroutine() {
+        // 0x200: call 0x206, v1 := 1, jump 0x204; 0x206: v2 := 2, return
+        let mut cpu = cpu(&[0x2206, 0x6101, 0x1204, 0x6202, 0x00EE]);
+        let mut debugger = Debugger::new(10);
+
+        assert_eq!(Stop::Stepped, debugger.step_over(&mut cpu));
+        assert_eq!(0x202, cpu.pc());
+        assert_eq!(2, cpu.registers()[2]);
+        assert_eq!(Stop::Stepped, debugger.step_over(&mut cpu));
+        assert_eq!(0x204, cpu.pc());
+        assert_eq!(1, cpu.registers()[1]);
+    }
+
+    #[test]
+    fn step_over_stops_at_breakpoints_inside_the_subroutine() {
+        let mut cpu = cpu(&[0x2206, 0x6101, 0x1204, 0x6202, 0x00EE]);
+        let mut debugger = Debugger::new(10);
+        debugger.set_breakpoint(0x208, None);
+
+        assert_eq!(Stop::Breakpoint(0x208), debugger.step_over(&mut cpu));
+        assert_eq!(Stop::Stepped, debugger.step_out(&mut cpu));
+        assert_eq!(0x202, cpu.pc());
+        assert_eq!(0, cpu.sp());
+    }
+
+    #[test]
+    fn step_into_enters_subroutines_and_step_out_leaves_them() {
+        let mut cpu = cpu(&[0x2206, 0x6101, 0x1204, 0x6202, 0x00EE]);
+        let mut debugger = Debugger::new(10);
+
+        assert_eq!(Stop::Stepped, debugger.step_into(&mut cpu));
+        assert_eq!(0x206, cpu.pc());
+        assert_eq!(&[0x202], cpu.stack());
+        assert_eq!(Stop::Stepped, debugger.step_out(&mut cpu));
+        assert_eq!(0x202, cpu.pc());
+        assert_eq!(Stop::Stepped, debugger.step_out(&mut cpu));
+        assert_eq!(0x204, cpu.pc());
+    }
+
+    #[test]
+    fn step_over_gives_up_on_subroutines_that_never_return() {
+        // call 0x202, which loops forever
+        let mut cpu = cpu(&[0x2202, 0x1202]);
+        let mut debugger = Debugger::new(10);
+
+        assert_eq!(Stop::StepLimit, debugger.step_over(&mut cpu));
+        assert_eq!(STEP_LIMIT_FRAMES, debugger.frame());
+    }
+
+    #[test]
+    fn frames_tick_the_timers() {
+        // delay := v0 with v0 = 10, then loop
+        let mut cpu = cpu(&[0x600A, 0xF015, 0x1204]);
+        let mut debugger = Debugger::new(4);
+
+        assert_eq!(Stop::Frame, debugger.run_until_frame(&mut cpu, 3));
+        assert_eq!(3, debugger.frame());
+        assert_eq!(7, cpu.timers.delay);
+
+        for _ in 0..4 {
+            debugger.step_into(&mut cpu);
+        }
+        assert_eq!(4, debugger.frame());
+        assert_eq!(6, cpu.timers.delay);
+    }
+
+    #[test]
+    fn faults_and_exits_stop_the_debugger() {
+        let mut cpu = cpu(&[0x00EE]);
+        let mut debugger = Debugger::default();
+
+        match debugger.run_frame(&mut cpu) {
+            Some(Stop::Fault(fault)) => assert_eq!(0x200, fault.pc),
+            stop => panic!("expected a fault, got {:?}", stop),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use crate::chip8::opcode::{decode, Opcode};
+use std::ops::Range;
+
+/// A fetched instruction: the raw opcode, and what it decodes to or `None`
+/// when it's unknown.
+pub(crate) type Entry = (u16, Option<Opcode>);
+
+/// The `DecodeCache` type. Remembers the decoded instruction at every
+/// address it has fetched from, so loops skip straight to dispatch instead
+/// of decoding the same opcodes over and over. Whatever writes to memory
+/// must call `invalidate` for the bytes it changed.
+pub(crate) struct DecodeCache {
+    entries: Vec<Option<Entry>>,
+}
+
+impl DecodeCache {
+    pub fn new(memory_size: usize) -> DecodeCache {
+        DecodeCache {
+            entries: vec![None; memory_size],
+        }
+    }
+
+    /// Forgets every entry, sizing the cache for `memory_size` bytes.
+    pub fn reset(&mut self, memory_size: usize) {
+        self.entries.clear();
+        self.entries.resize(memory_size, None);
+    }
+
+    /// The instruction at `address`, decoding it on first use. `None` when
+    /// it runs past the end of `memory`.
+    #[inline]
+    pub fn fetch(&mut self, memory: &[u8], address: u16) -> Option<Entry> {
+        let address = address as usize;
+        if address + 1 >= memory.len() {
+            return None;
+        }
+        if let Some(entry) = self.entries[address] {
+            return Some(entry);
+        }
+
+        let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+        let entry = (opcode, decode(opcode).ok());
+        self.entries[address] = Some(entry);
+        Some(entry)
+    }
+
+    /// Forgets the instructions overlapping the bytes in `range`, including
+    /// one starting on the byte before it.
+    pub fn invalidate(&mut self, range: Range<usize>) {
+        let start = range.start.saturating_sub(1);
+        let end = range.end.min(self.entries.len());
+        for entry in self.entries[start.min(end)..end].iter_mut() {
+            *entry = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::cpu::Cpu;
+
+    #[test]
+    fn decodes_once_until_invalidated() {
+        let mut memory = vec![0x60, 0x05, 0x12, 0x00];
+        let mut cache = DecodeCache::new(memory.len());
+        assert_eq!(
+            Some((0x6005, Some(Opcode::LD(0, 5)))),
+            cache.fetch(&memory, 0)
+        );
+
+        memory[1] = 0x07;
+        assert_eq!(
+            Some((0x6005, Some(Opcode::LD(0, 5)))),
+            cache.fetch(&memory, 0)
+        );
+        cache.invalidate(1..2);
+        assert_eq!(
+            Some((0x6007, Some(Opcode::LD(0, 7)))),
+            cache.fetch(&memory, 0)
+        );
+
+        assert_eq!(None, cache.fetch(&memory, 3));
+    }
+
+    #[test]
+    fn invalidates_an_instruction_starting_before_the_write() {
+        let memory = vec![0x60, 0x05, 0x12, 0x00];
+        let mut cache = DecodeCache::new(memory.len());
+        cache.fetch(&memory, 1);
+        cache.fetch(&memory, 2);
+
+        cache.invalidate(2..3);
+        assert_eq!(None, cache.entries[1]);
+        assert_eq!(None, cache.entries[2]);
+    }
+
+    #[test]
+    fn cpu_sees_self_modifying_code() {
+        // i := 0x20C, call 0x20C, v0 := 0x72, v1 := 5, save v1, call 0x20C
+        // 0x20C: v1 += 5, return
+        let rom = [
+            0xA2, 0x0C, 0x22, 0x0C, 0x60, 0x72, 0x61, 0x05, 0xF1, 0x55, 0x22, 0x0C, 0x71, 0x05,
+            0x00, 0xEE,
+        ];
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&rom).unwrap();
+
+        // The first call caches 7105, then FX55 rewrites it to 7205.
+        for _ in 0..9 {
+            cpu.step();
+        }
+        assert_eq!(5, cpu.registers()[1]);
+        assert_eq!(5, cpu.registers()[2]);
+    }
+
+    #[test]
+    fn cached_and_uncached_cpus_agree() {
+        // Counts v2 from 0 to 249, writing its digits into the following
+        // code with BCD, and draws the hundreds digit it loaded from there.
+        let program: [u16; 13] = [
+            0x6200, 0x7201, 0x42FA, 0x6200, 0xA211, 0xF233, 0x00E0, 0x6400, 0x6300, 0x0000, 0xF329,
+            0xD445, 0x1202,
+        ];
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        let mut cached = Cpu::default();
+        let mut uncached = Cpu::default();
+        uncached.set_decode_cache(false);
+        for cpu in [&mut cached, &mut uncached].iter_mut() {
+            cpu.load_rom(&rom).unwrap();
+        }
+
+        let mut digits = [false; 3];
+        for _ in 0..600 {
+            cached.run_frame(10);
+            uncached.run_frame(10);
+            assert_eq!(uncached.save_state(), cached.save_state());
+            digits[cached.registers()[3] as usize] = true;
+        }
+        assert_eq!([true; 3], digits);
+    }
+}
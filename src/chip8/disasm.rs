@@ -0,0 +1,530 @@
+use crate::chip8::opcode::{decode, Opcode};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// The address programs are loaded at unless the platform says otherwise.
+pub const DEFAULT_ORIGIN: u16 = 0x200;
+
+/// The assembly dialect a listing is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Upper case mnemonics from Cowgod's technical reference, `LD V1, 0x20`.
+    Cowgod,
+    /// Octo's structured syntax, `v1 := 0x20`.
+    Octo,
+}
+
+impl Syntax {
+    /// Looks a syntax up by name: `cowgod` or `octo`.
+    pub fn from_name(name: &str) -> Option<Syntax> {
+        match name {
+            "cowgod" => Some(Syntax::Cowgod),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        }
+    }
+}
+
+/// What a label marks, which decides its name. A subroutine beats a jump
+/// target, which beats data, when an address is all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Jump,
+    Subroutine,
+}
+
+/// The result of tracing the control flow of a ROM.
+struct Analysis {
+    /// The length of the instruction starting at each offset, if any.
+    instructions: Vec<Option<usize>>,
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+/// Disassembles `rom`, loaded at `origin`, into a listing in `syntax`.
+///
+/// Code is found by recursive descent from the origin, following jumps,
+/// calls and both sides of skips. Whatever isn't reached is emitted as data
+/// bytes, so the listing reassembles to exactly the same bytes.
+pub fn disassemble(rom: &[u8], origin: u16, syntax: Syntax) -> String {
+    let analysis = analyze(rom, origin);
+    let mut listing = String::new();
+
+    if origin != DEFAULT_ORIGIN {
+        match syntax {
+            Syntax::Cowgod => writeln!(listing, "ORG {}", address(origin)),
+            Syntax::Octo => writeln!(listing, ":org {}", address(origin)),
+        }
+        .unwrap();
+    }
+
+    let is_boundary = |offset: usize| {
+        analysis.instructions[offset].is_some()
+            || analysis.labels.contains_key(&address_of(origin, offset))
+    };
+
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = address_of(origin, offset);
+        if let Some(&kind) = analysis.labels.get(&address) {
+            match syntax {
+                Syntax::Cowgod => writeln!(listing, "{}:", label_name(address, kind)),
+                Syntax::Octo => writeln!(listing, ": {}", label_name(address, kind)),
+            }
+            .unwrap();
+        }
+
+        // An instruction that overlaps another instruction or a label can't
+        // be written as one without losing the other, so it becomes data.
+        if let Some(len) = analysis.instructions[offset] {
+            if !(offset + 1..offset + len).any(is_boundary) {
+                let text = format_instruction(&rom[offset..offset + len], &analysis, syntax);
+                writeln!(listing, "    {}", text).unwrap();
+                offset += len;
+                continue;
+            }
+        }
+
+        let mut end = offset + 1;
+        while end < rom.len() && end - offset < 8 && !is_boundary(end) {
+            end += 1;
+        }
+        let bytes: Vec<String> = rom[offset..end].iter().map(|&value| byte(value)).collect();
+        match syntax {
+            Syntax::Cowgod => writeln!(listing, "    DB {}", bytes.join(", ")),
+            Syntax::Octo => writeln!(listing, "    {}", bytes.join(" ")),
+        }
+        .unwrap();
+        offset = end;
+    }
+
+    listing
+}
+
+fn address_of(origin: u16, offset: usize) -> u16 {
+    origin.wrapping_add(offset as u16)
+}
+
+/// The addresses of the instructions in `rom`, loaded at `origin`, that
+/// recursive descent reaches; see `disassemble`.
+pub fn code_addresses(rom: &[u8], origin: u16) -> Vec<u16> {
+    analyze(rom, origin)
+        .instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, len)| len.is_some())
+        .map(|(offset, _)| address_of(origin, offset))
+        .collect()
+}
+
+fn word(bytes: &[u8], offset: usize) -> Option<u16> {
+    if offset + 1 >= bytes.len() {
+        return None;
+    }
+    Some((bytes[offset] as u16) << 8 | bytes[offset + 1] as u16)
+}
+
+fn analyze(rom: &[u8], origin: u16) -> Analysis {
+    let mut analysis = Analysis {
+        instructions: vec![None; rom.len()],
+        labels: BTreeMap::new(),
+    };
+    let offset_of = |address: u16| {
+        let offset = address.wrapping_sub(origin) as usize;
+        if address >= origin && offset < rom.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    };
+    let add_label = |labels: &mut BTreeMap<u16, LabelKind>, address: u16, kind| {
+        if offset_of(address).is_some() {
+            let entry = labels.entry(address).or_insert(kind);
+            *entry = (*entry).max(kind);
+        }
+    };
+
+    let mut pending = vec![origin];
+    while let Some(mut address) = pending.pop() {
+        while let Some(offset) = offset_of(address) {
+            if analysis.instructions[offset].is_some() {
+                break;
+            }
+            let opcode = match word(rom, offset).map(decode) {
+                Some(Ok(opcode)) => opcode,
+                _ => break,
+            };
+            let len = if opcode == Opcode::LDIL { 4 } else { 2 };
+            if offset + len > rom.len() {
+                break;
+            }
+
+            analysis.instructions[offset] = Some(len);
+            let next = address.wrapping_add(len as u16);
+            match opcode {
+                Opcode::JP(target) => {
+                    add_label(&mut analysis.labels, target, LabelKind::Jump);
+                    pending.push(target);
+                    break;
+                }
+                Opcode::CALL(target) => {
+                    add_label(&mut analysis.labels, target, LabelKind::Subroutine);
+                    pending.push(target);
+                }
+                Opcode::RET | Opcode::EXIT | Opcode::JPR(_) => break,
+                Opcode::SE(..)
+                | Opcode::SNE(..)
+                | Opcode::SER(..)
+                | Opcode::SNER(..)
+                | Opcode::SKP(_)
+                | Opcode::SKNP(_) => {
+                    // The skipped instruction may be the four byte F000 NNNN.
+                    let skipped = match offset_of(next).and_then(|next| word(rom, next)) {
+                        Some(0xF000) => 4,
+                        _ => 2,
+                    };
+                    pending.push(next.wrapping_add(skipped));
+                }
+                Opcode::LDI(target) => add_label(&mut analysis.labels, target, LabelKind::Data),
+                Opcode::LDIL => {
+                    let target = word(rom, offset + 2).unwrap();
+                    add_label(&mut analysis.labels, target, LabelKind::Data);
+                }
+                _ => (),
+            }
+            address = next;
+        }
+    }
+
+    analysis
+}
+
+fn label_name(address: u16, kind: LabelKind) -> String {
+    let prefix = match kind {
+        LabelKind::Data => "data",
+        LabelKind::Jump => "label",
+        LabelKind::Subroutine => "sub",
+    };
+    format!("{}_{:03x}", prefix, address)
+}
+
+/// Formats an address operand, by label when it has one.
+fn target(value: u16, analysis: &Analysis) -> String {
+    match analysis.labels.get(&value) {
+        Some(&kind) => label_name(value, kind),
+        None => address(value),
+    }
+}
+
+fn address(value: u16) -> String {
+    format!("0x{:03X}", value)
+}
+
+fn byte(value: u8) -> String {
+    format!("0x{:02X}", value)
+}
+
+/// Formats a single instruction in `syntax`, with numeric operands. `long`
+/// is the word following F000, used by `LD I, LONG`.
+pub fn format_opcode(opcode: Opcode, long: u16, syntax: Syntax) -> String {
+    let analysis = Analysis {
+        instructions: Vec::new(),
+        labels: BTreeMap::new(),
+    };
+    let mut bytes = [0u8; 4];
+    bytes[..2].copy_from_slice(&opcode.encode().to_be_bytes());
+    bytes[2..].copy_from_slice(&long.to_be_bytes());
+    match syntax {
+        Syntax::Cowgod => format_cowgod(opcode, &bytes, &analysis),
+        Syntax::Octo => format_octo(opcode, &bytes, &analysis),
+    }
+}
+
+fn format_instruction(bytes: &[u8], analysis: &Analysis, syntax: Syntax) -> String {
+    let opcode = decode(word(bytes, 0).unwrap()).unwrap();
+    match syntax {
+        Syntax::Cowgod => format_cowgod(opcode, bytes, analysis),
+        Syntax::Octo => format_octo(opcode, bytes, analysis),
+    }
+}
+
+fn format_cowgod(opcode: Opcode, bytes: &[u8], analysis: &Analysis) -> String {
+    match opcode {
+        Opcode::SYS(address) => format!("SYS {}", target(address, analysis)),
+        Opcode::CLS => "CLS".to_string(),
+        Opcode::RET => "RET".to_string(),
+        Opcode::SCD(n) => format!("SCD {}", n),
+        Opcode::SCU(n) => format!("SCU {}", n),
+        Opcode::SCR => "SCR".to_string(),
+        Opcode::SCL => "SCL".to_string(),
+        Opcode::EXIT => "EXIT".to_string(),
+        Opcode::LOW => "LOW".to_string(),
+        Opcode::HIGH => "HIGH".to_string(),
+        Opcode::JP(address) => format!("JP {}", target(address, analysis)),
+        Opcode::CALL(address) => format!("CALL {}", target(address, analysis)),
+        Opcode::SE(x, kk) => format!("SE V{:X}, {}", x, byte(kk)),
+        Opcode::SNE(x, kk) => format!("SNE V{:X}, {}", x, byte(kk)),
+        Opcode::SER(x, y) => format!("SE V{:X}, V{:X}", x, y),
+        Opcode::SAVE(x, y) => format!("SAVE V{:X}, V{:X}", x, y),
+        Opcode::LOAD(x, y) => format!("LOAD V{:X}, V{:X}", x, y),
+        Opcode::LD(x, kk) => format!("LD V{:X}, {}", x, byte(kk)),
+        Opcode::ADD(x, kk) => format!("ADD V{:X}, {}", x, byte(kk)),
+        Opcode::LDR(x, y) => format!("LD V{:X}, V{:X}", x, y),
+        Opcode::OR(x, y) => format!("OR V{:X}, V{:X}", x, y),
+        Opcode::AND(x, y) => format!("AND V{:X}, V{:X}", x, y),
+        Opcode::XOR(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+        Opcode::ADDR(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+        Opcode::SUBR(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+        Opcode::SHR(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+        Opcode::SUBN(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+        Opcode::SHL(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+        Opcode::SNER(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+        Opcode::LDI(address) => format!("LD I, {}", target(address, analysis)),
+        Opcode::JPR(address) => format!("JP V0, {}", target(address, analysis)),
+        Opcode::RND(x, kk) => format!("RND V{:X}, {}", x, byte(kk)),
+        Opcode::DRW(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Opcode::SKP(x) => format!("SKP V{:X}", x),
+        Opcode::SKNP(x) => format!("SKNP V{:X}", x),
+        Opcode::LDDT(x) => format!("LD V{:X}, DT", x),
+        Opcode::LDK(x) => format!("LD V{:X}, K", x),
+        Opcode::DTLD(x) => format!("LD DT, V{:X}", x),
+        Opcode::STLD(x) => format!("LD ST, V{:X}", x),
+        Opcode::ADDI(x) => format!("ADD I, V{:X}", x),
+        Opcode::LDF(x) => format!("LD F, V{:X}", x),
+        Opcode::LDB(x) => format!("LD B, V{:X}", x),
+        Opcode::LDIR(x) => format!("LD [I], V{:X}", x),
+        Opcode::LDRI(x) => format!("LD V{:X}, [I]", x),
+        Opcode::LDHF(x) => format!("LD HF, V{:X}", x),
+        Opcode::STRPL(x) => format!("LD R, V{:X}", x),
+        Opcode::LDRPL(x) => format!("LD V{:X}, R", x),
+        Opcode::LDIL => format!("LD I, LONG {}", target(word(bytes, 2).unwrap(), analysis)),
+        Opcode::PLANE(n) => format!("PLANE {}", n),
+        Opcode::AUDIO => "AUDIO".to_string(),
+        Opcode::PITCH(x) => format!("LD PITCH, V{:X}", x),
+    }
+}
+
+fn format_octo(opcode: Opcode, bytes: &[u8], analysis: &Analysis) -> String {
+    match opcode {
+        // Octo has no mnemonic for machine code calls.
+        Opcode::SYS(_) => format!("{} {}", byte(bytes[0]), byte(bytes[1])),
+        Opcode::CLS => "clear".to_string(),
+        Opcode::RET => "return".to_string(),
+        Opcode::SCD(n) => format!("scroll-down {}", n),
+        Opcode::SCU(n) => format!("scroll-up {}", n),
+        Opcode::SCR => "scroll-right".to_string(),
+        Opcode::SCL => "scroll-left".to_string(),
+        Opcode::EXIT => "exit".to_string(),
+        Opcode::LOW => "lores".to_string(),
+        Opcode::HIGH => "hires".to_string(),
+        Opcode::JP(address) => format!("jump {}", target(address, analysis)),
+        Opcode::CALL(address) => match analysis.labels.get(&address) {
+            Some(&kind) => label_name(address, kind),
+            None => format!(":call {}", target(address, analysis)),
+        },
+        // Octo's conditions say when the next instruction runs, which is
+        // the opposite of when it is skipped.
+        Opcode::SE(x, kk) => format!("if v{:x} != {} then", x, byte(kk)),
+        Opcode::SNE(x, kk) => format!("if v{:x} == {} then", x, byte(kk)),
+        Opcode::SER(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        Opcode::SAVE(x, y) => format!("save v{:x} - v{:x}", x, y),
+        Opcode::LOAD(x, y) => format!("load v{:x} - v{:x}", x, y),
+        Opcode::LD(x, kk) => format!("v{:x} := {}", x, byte(kk)),
+        Opcode::ADD(x, kk) => format!("v{:x} += {}", x, byte(kk)),
+        Opcode::LDR(x, y) => format!("v{:x} := v{:x}", x, y),
+        Opcode::OR(x, y) => format!("v{:x} |= v{:x}", x, y),
+        Opcode::AND(x, y) => format!("v{:x} &= v{:x}", x, y),
+        Opcode::XOR(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        Opcode::ADDR(x, y) => format!("v{:x} += v{:x}", x, y),
+        Opcode::SUBR(x, y) => format!("v{:x} -= v{:x}", x, y),
+        Opcode::SHR(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        Opcode::SUBN(x, y) => format!("v{:x} =- v{:x}", x, y),
+        Opcode::SHL(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        Opcode::SNER(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        Opcode::LDI(address) => format!("i := {}", target(address, analysis)),
+        Opcode::JPR(address) => format!("jump0 {}", target(address, analysis)),
+        Opcode::RND(x, kk) => format!("v{:x} := random {}", x, byte(kk)),
+        Opcode::DRW(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Opcode::SKP(x) => format!("if v{:x} -key then", x),
+        Opcode::SKNP(x) => format!("if v{:x} key then", x),
+        Opcode::LDDT(x) => format!("v{:x} := delay", x),
+        Opcode::LDK(x) => format!("v{:x} := key", x),
+        Opcode::DTLD(x) => format!("delay := v{:x}", x),
+        Opcode::STLD(x) => format!("buzzer := v{:x}", x),
+        Opcode::ADDI(x) => format!("i += v{:x}", x),
+        Opcode::LDF(x) => format!("i := hex v{:x}", x),
+        Opcode::LDB(x) => format!("bcd v{:x}", x),
+        Opcode::LDIR(x) => format!("save v{:x}", x),
+        Opcode::LDRI(x) => format!("load v{:x}", x),
+        Opcode::LDHF(x) => format!("i := bighex v{:x}", x),
+        Opcode::STRPL(x) => format!("saveflags v{:x}", x),
+        Opcode::LDRPL(x) => format!("loadflags v{:x}", x),
+        Opcode::LDIL => format!("i := long {}", target(word(bytes, 2).unwrap(), analysis)),
+        Opcode::PLANE(n) => format!("plane {}", n),
+        Opcode::AUDIO => "audio".to_string(),
+        Opcode::PITCH(x) => format!("pitch := v{:x}", x),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cowgod(rom: &[u8]) -> String {
+        disassemble(rom, DEFAULT_ORIGIN, Syntax::Cowgod)
+    }
+
+    fn octo(rom: &[u8]) -> String {
+        disassemble(rom, DEFAULT_ORIGIN, Syntax::Octo)
+    }
+
+    #[test]
+    fn separates_code_from_sprite_data() {
+        // 6120: V1 = 0x20, A208: I = sprite, D115: draw, 1206: loop,
+        // then a sprite
+        let rom = [
+            0x61, 0x20, 0xA2, 0x08, 0xD1, 0x15, 0x12, 0x06, 0xF0, 0x90, 0xF0, 0x90, 0xF0,
+        ];
+
+        assert_eq!(
+            "    LD V1, 0x20\n\
+             \x20   LD I, data_208\n\
+             \x20   DRW V1, V1, 5\n\
+             label_206:\n\
+             \x20   JP label_206\n\
+             data_208:\n\
+             \x20   DB 0xF0, 0x90, 0xF0, 0x90, 0xF0\n",
+            cowgod(&rom)
+        );
+    }
+
+    #[test]
+    fn follows_calls_and_both_sides_of_skips() {
+        // 2206: call, 1204: loop, 00EE: hidden by the jump, sub: 3100: skip
+        // if V1 = 0, 00E0: clear, 00EE: return
+        let rom = [
+            0x22, 0x08, 0x12, 0x02, 0x00, 0xEE, 0xAB, 0xCD, 0x31, 0x00, 0x00, 0xE0, 0x00, 0xEE,
+        ];
+
+        assert_eq!(
+            "    CALL sub_208\n\
+             label_202:\n\
+             \x20   JP label_202\n\
+             \x20   DB 0x00, 0xEE, 0xAB, 0xCD\n\
+             sub_208:\n\
+             \x20   SE V1, 0x00\n\
+             \x20   CLS\n\
+             \x20   RET\n",
+            cowgod(&rom)
+        );
+    }
+
+    #[test]
+    fn skips_over_long_load_on_both_paths() {
+        // 3100: skip, F000 0208: I = long, 00FD: exit, then data
+        let rom = [
+            0x31, 0x00, 0xF0, 0x00, 0x02, 0x0A, 0x00, 0xFD, 0x00, 0x00, 0x55,
+        ];
+
+        let listing = octo(&rom);
+
+        assert_eq!(
+            "    if v1 != 0x00 then\n\
+             \x20   i := long data_20a\n\
+             \x20   exit\n\
+             \x20   0x00 0x00\n\
+             : data_20a\n\
+             \x20   0x55\n",
+            listing
+        );
+    }
+
+    #[test]
+    fn octo_syntax_covers_the_instruction_set() {
+        let rom = [
+            0x00, 0xE0, 0x6A, 0x12, 0x8A, 0xB4, 0x8A, 0xB7, 0x8A, 0x06, 0xCA, 0x0F, 0xEA, 0x9E,
+            0xFA, 0x0A, 0xFA, 0x29, 0xFA, 0x33, 0xFA, 0x55, 0x51, 0x32, 0xF2, 0x01, 0xFA, 0x3A,
+            0x00, 0xFF, 0x02, 0x34, 0x00, 0xEE,
+        ];
+
+        assert_eq!(
+            "    clear\n\
+             \x20   va := 0x12\n\
+             \x20   va += vb\n\
+             \x20   va =- vb\n\
+             \x20   va >>= v0\n\
+             \x20   va := random 0x0F\n\
+             \x20   if va -key then\n\
+             \x20   va := key\n\
+             \x20   i := hex va\n\
+             \x20   bcd va\n\
+             \x20   save va\n\
+             \x20   save v1 - v3\n\
+             \x20   plane 2\n\
+             \x20   pitch := va\n\
+             \x20   hires\n\
+             \x20   0x02 0x34\n\
+             \x20   return\n",
+            octo(&rom)
+        );
+    }
+
+    #[test]
+    fn instructions_overlapping_labels_become_data() {
+        // 1201: jump into its own second byte, which reads as 0100: sys
+        let rom = [0x12, 0x01, 0x00, 0xE0];
+
+        assert_eq!(
+            "    DB 0x12\n\
+             label_201:\n\
+             \x20   SYS 0x100\n\
+             \x20   DB 0xE0\n",
+            cowgod(&rom)
+        );
+    }
+
+    #[test]
+    fn unknown_opcodes_and_odd_tails_are_data() {
+        let rom = [0x80, 0x08, 0x00];
+
+        assert_eq!("    DB 0x80, 0x08, 0x00\n", cowgod(&rom));
+    }
+
+    #[test]
+    fn single_instructions_format_without_labels() {
+        assert_eq!(
+            "CALL 0x300",
+            format_opcode(Opcode::CALL(0x300), 0, Syntax::Cowgod)
+        );
+        assert_eq!(
+            ":call 0x300",
+            format_opcode(Opcode::CALL(0x300), 0, Syntax::Octo)
+        );
+        assert_eq!(
+            "i := long 0x1234",
+            format_opcode(Opcode::LDIL, 0x1234, Syntax::Octo)
+        );
+    }
+
+    #[test]
+    fn targets_outside_the_rom_stay_numeric() {
+        // A050: I = font, 2300: call outside, 00FD: exit
+        let rom = [0xA0, 0x50, 0x23, 0x00, 0x00, 0xFD];
+
+        assert_eq!("    i := 0x050\n    :call 0x300\n    exit\n", octo(&rom));
+    }
+
+    #[test]
+    fn other_origins_are_declared() {
+        let rom = [0x16, 0x00];
+
+        assert_eq!(
+            "ORG 0x600\nlabel_600:\n    JP label_600\n",
+            disassemble(&rom, 0x600, Syntax::Cowgod)
+        );
+        assert_eq!(
+            ":org 0x600\n: label_600\n    jump label_600\n",
+            disassemble(&rom, 0x600, Syntax::Octo)
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use std::fmt;
+
+/// The `CpuFault` type. Describes why the cpu had to stop executing, along
+/// with the program counter and the raw opcode of the offending instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuFault {
+    pub pc: u16,
+    pub opcode: u16,
+    pub kind: FaultKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    UnknownOpcode,
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds(usize),
+    PcOutOfBounds,
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaultKind::UnknownOpcode => write!(f, "unknown opcode"),
+            FaultKind::StackOverflow => write!(f, "stack overflow"),
+            FaultKind::StackUnderflow => write!(f, "stack underflow"),
+            FaultKind::MemoryOutOfBounds(address) => {
+                write!(f, "memory access out of range at {:#06x}", address)
+            }
+            FaultKind::PcOutOfBounds => write!(f, "program counter outside of memory"),
+        }
+    }
+}
+
+impl fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (pc: {:#06x}, opcode: {:#06x})",
+            self.kind, self.pc, self.opcode
+        )
+    }
+}
+
+/// The result of a single `Cpu::step`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    /// The cpu is blocked until the next 60 Hz frame and executed nothing.
+    Waiting,
+    /// The program ran 00FD and the cpu has stopped.
+    Exited,
+    Fault(CpuFault),
+}
@@ -0,0 +1,294 @@
+use std::fmt;
+
+/// Where FX29 finds the small font unless told otherwise.
+pub const FONT_ADDRESS: u16 = 0x50;
+/// Where FX30 finds the big font unless told otherwise.
+pub const BIG_FONT_ADDRESS: u16 = 0xA0;
+
+/// The `FontSize` type. FX29 points at 4x5 hex digit glyphs, five bytes
+/// each; the SUPER-CHIP FX30 at 8x10 ones, ten bytes each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontSize {
+    Small,
+    Big,
+}
+
+impl FontSize {
+    /// The number of bytes in one glyph.
+    pub fn glyph_len(self) -> usize {
+        match self {
+            FontSize::Small => 5,
+            FontSize::Big => 10,
+        }
+    }
+
+    /// Whether a font of `len` bytes has a glyph for every digit: all
+    /// sixteen for small fonts, 0 to 9 or all sixteen for big ones.
+    fn accepts(self, len: usize) -> bool {
+        match self {
+            FontSize::Small => len == 16 * 5,
+            FontSize::Big => len == 10 * 10 || len == 16 * 10,
+        }
+    }
+}
+
+/// Returned when a font can't be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    /// The font has the wrong number of bytes for its size.
+    BadLength(FontSize, usize),
+    /// The font doesn't fit in memory at this address.
+    OutOfMemory(u16),
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FontError::BadLength(FontSize::Small, len) => {
+                write!(f, "small font must be 80 bytes, not {}", len)
+            }
+            FontError::BadLength(FontSize::Big, len) => {
+                write!(f, "big font must be 100 or 160 bytes, not {}", len)
+            }
+            FontError::OutOfMemory(address) => {
+                write!(f, "font doesn't fit in memory at {:#06x}", address)
+            }
+        }
+    }
+}
+
+/// Checks that `glyphs` is a whole font of `size`.
+pub(crate) fn check_font(glyphs: &[u8], size: FontSize) -> Result<(), FontError> {
+    if size.accepts(glyphs.len()) {
+        Ok(())
+    } else {
+        Err(FontError::BadLength(size, glyphs.len()))
+    }
+}
+
+/// The `Font` type. The hex digit fonts built into the interpreters of the
+/// machines CHIP-8 ran on, so programs can be shown the way they looked
+/// there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Font {
+    /// CHIP-48 on the HP-48, the font most modern interpreters use.
+    #[default]
+    Chip48,
+    /// The original interpreter on the RCA COSMAC VIP.
+    CosmacVip,
+    /// CHIPOS on the DREAM 6800.
+    Dream6800,
+    /// The ETI-660 learner's computer.
+    Eti660,
+    /// FISH'N'CHIPS, the font Octo calls `fish`.
+    FishNChips,
+    /// The SUPER-CHIP 1.1 big font, digits 0 to 9.
+    SuperChipBig,
+}
+
+impl Font {
+    /// The glyphs, one after another from digit 0.
+    pub fn glyphs(self) -> &'static [u8] {
+        match self {
+            Font::Chip48 => CHIP48,
+            Font::CosmacVip => COSMAC_VIP,
+            Font::Dream6800 => DREAM_6800,
+            Font::Eti660 => ETI_660,
+            Font::FishNChips => FISH_N_CHIPS,
+            Font::SuperChipBig => SUPER_CHIP_BIG,
+        }
+    }
+
+    /// Whether FX29 or FX30 points at this font.
+    pub fn size(self) -> FontSize {
+        match self {
+            Font::SuperChipBig => FontSize::Big,
+            _ => FontSize::Small,
+        }
+    }
+
+    /// Looks up a font by name: `chip48`, `vip`, `dream6800`, `eti660`,
+    /// `fish` or `schip`.
+    pub fn from_name(name: &str) -> Option<Font> {
+        match name {
+            "chip48" => Some(Font::Chip48),
+            "vip" => Some(Font::CosmacVip),
+            "dream6800" => Some(Font::Dream6800),
+            "eti660" => Some(Font::Eti660),
+            "fish" => Some(Font::FishNChips),
+            "schip" => Some(Font::SuperChipBig),
+            _ => None,
+        }
+    }
+}
+
+#[rustfmt::skip]
+static CHIP48: &[u8] =
+&[
+  0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+  0x20, 0x60, 0x20, 0x20, 0x70, // 1
+  0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+  0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+  0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+  0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+  0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+  0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+  0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+  0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+  0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+  0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+  0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+  0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+  0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+  0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+];
+
+#[rustfmt::skip]
+static COSMAC_VIP: &[u8] =
+&[
+  0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+  0x60, 0x20, 0x20, 0x20, 0x70, // 1
+  0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+  0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+  0xA0, 0xA0, 0xF0, 0x20, 0x20, // 4
+  0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+  0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+  0xF0, 0x10, 0x10, 0x10, 0x10, // 7
+  0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+  0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+  0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+  0xF0, 0x50, 0x70, 0x50, 0xF0, // B
+  0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+  0xF0, 0x50, 0x50, 0x50, 0xF0, // D
+  0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+  0xF0, 0x80, 0xF0, 0x80, 0x80  // F
+];
+
+#[rustfmt::skip]
+static DREAM_6800: &[u8] =
+&[
+  0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+  0x40, 0x40, 0x40, 0x40, 0x40, // 1
+  0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+  0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+  0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+  0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+  0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+  0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+  0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+  0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+  0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+  0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+  0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+  0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+  0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+  0xE0, 0x80, 0xC0, 0x80, 0x80  // F
+];
+
+#[rustfmt::skip]
+static ETI_660: &[u8] =
+&[
+  0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+  0x20, 0x20, 0x20, 0x20, 0x20, // 1
+  0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+  0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+  0xA0, 0xA0, 0xE0, 0x20, 0x20, // 4
+  0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+  0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+  0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+  0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+  0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+  0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+  0x80, 0x80, 0xE0, 0xA0, 0xE0, // B
+  0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+  0x20, 0x20, 0xE0, 0xA0, 0xE0, // D
+  0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+  0xE0, 0x80, 0xC0, 0x80, 0x80  // F
+];
+
+#[rustfmt::skip]
+static FISH_N_CHIPS: &[u8] =
+&[
+  0x60, 0xA0, 0xA0, 0xA0, 0xC0, // 0
+  0x40, 0xC0, 0x40, 0x40, 0xE0, // 1
+  0xC0, 0x20, 0x40, 0x80, 0xE0, // 2
+  0xC0, 0x20, 0x40, 0x20, 0xC0, // 3
+  0x20, 0xA0, 0xE0, 0x20, 0x20, // 4
+  0xE0, 0x80, 0xC0, 0x20, 0xC0, // 5
+  0x40, 0x80, 0xC0, 0xA0, 0x40, // 6
+  0xE0, 0x20, 0x60, 0x40, 0x40, // 7
+  0x40, 0xA0, 0x40, 0xA0, 0x40, // 8
+  0x40, 0xA0, 0x60, 0x20, 0x40, // 9
+  0x40, 0xA0, 0xE0, 0xA0, 0xA0, // A
+  0xC0, 0xA0, 0xC0, 0xA0, 0xC0, // B
+  0x60, 0x80, 0x80, 0x80, 0x60, // C
+  0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+  0xE0, 0x80, 0xC0, 0x80, 0xE0, // E
+  0xE0, 0x80, 0xC0, 0x80, 0x80  // F
+];
+
+#[rustfmt::skip]
+static SUPER_CHIP_BIG: &[u8] =
+&[
+  0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+  0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+  0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+  0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+  0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+  0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+  0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+  0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+  0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+  0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C  // 9
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONTS: [Font; 6] = [
+        Font::Chip48,
+        Font::CosmacVip,
+        Font::Dream6800,
+        Font::Eti660,
+        Font::FishNChips,
+        Font::SuperChipBig,
+    ];
+
+    #[test]
+    fn built_in_fonts_are_whole() {
+        for font in FONTS.iter() {
+            assert_eq!(Ok(()), check_font(font.glyphs(), font.size()), "{:?}", font);
+        }
+    }
+
+    #[test]
+    fn fonts_differ() {
+        for (index, font) in FONTS.iter().enumerate() {
+            for other in FONTS[index + 1..].iter() {
+                assert_ne!(font.glyphs(), other.glyphs(), "{:?} {:?}", font, other);
+            }
+        }
+    }
+
+    #[test]
+    fn check_font_rejects_partial_fonts() {
+        assert_eq!(
+            Err(FontError::BadLength(FontSize::Small, 75)),
+            check_font(&[0; 75], FontSize::Small)
+        );
+        assert_eq!(
+            Err(FontError::BadLength(FontSize::Big, 80)),
+            check_font(&[0; 80], FontSize::Big)
+        );
+        assert_eq!(Ok(()), check_font(&[0; 160], FontSize::Big));
+    }
+
+    #[test]
+    fn from_name_finds_fonts() {
+        assert_eq!(Some(Font::CosmacVip), Font::from_name("vip"));
+        assert_eq!(Some(Font::FishNChips), Font::from_name("fish"));
+        assert_eq!(Some(Font::SuperChipBig), Font::from_name("schip"));
+        assert_eq!(None, Font::from_name("comic"));
+    }
+}
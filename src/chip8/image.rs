@@ -0,0 +1,161 @@
+use crate::chip8::screen::Screen;
+use crate::chip8::state::crc32;
+
+/// Encodes the screen as a binary (P4) PBM image, with lit pixels in any
+/// plane as black.
+pub fn encode_pbm(screen: &Screen) -> Vec<u8> {
+    let (width, height) = (screen.width(), screen.height());
+    let mut image = format!("P4\n{} {}\n", width, height).into_bytes();
+
+    for y in 0..height {
+        for row_byte in 0..width.div_ceil(8) {
+            let mut byte = 0u8;
+            for bit in 0..8 {
+                let x = row_byte * 8 + bit;
+                if x < width && screen.get(x, y) {
+                    byte |= 0x80 >> bit;
+                }
+            }
+            image.push(byte);
+        }
+    }
+
+    image
+}
+
+/// Encodes the screen as an RGB PNG image, coloring each pixel with
+/// `palette` the same way the renderer does. The image data is stored
+/// uncompressed, which keeps the encoder small; the screens are tiny.
+pub fn encode_png(screen: &Screen, palette: &[u32; 4]) -> Vec<u8> {
+    let (width, height) = (screen.width(), screen.height());
+    let rgb = screen.get_rgb_data(palette);
+
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3) {
+        // Filter type 0: the row is stored as is.
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, deflate, adaptive filtering, no interlace.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut image = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut image, b"IHDR", &header);
+    write_chunk(&mut image, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut image, b"IEND", &[]);
+    image
+}
+
+fn write_chunk(image: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    image.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = image.len();
+    image.extend_from_slice(kind);
+    image.extend_from_slice(data);
+    let crc = crc32(&image[start..]);
+    image.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps `data` in a zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xFFFF;
+
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        stream.push(last as u8);
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::screen::DEFAULT_PALETTE;
+
+    #[test]
+    fn pbm_packs_rows_msb_first() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 0, &[0xA5]);
+        screen.draw_sprite(63, 31, &[0x80]);
+
+        let image = encode_pbm(&screen);
+
+        let header = b"P4\n64 32\n";
+        assert_eq!(header, &image[..header.len()]);
+        assert_eq!(header.len() + 8 * 32, image.len());
+        assert_eq!(0xA5, image[header.len()]);
+        assert_eq!(0x01, image[image.len() - 1]);
+    }
+
+    #[test]
+    fn png_has_valid_structure() {
+        let mut screen = Screen::new();
+        screen.set_hires(true);
+        screen.draw_sprite(0, 0, &[0x80]);
+
+        let image = encode_png(&screen, &DEFAULT_PALETTE);
+
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &image[..8]);
+        assert_eq!(b"IHDR", &image[12..16]);
+        assert_eq!(&128u32.to_be_bytes(), &image[16..20]);
+        assert_eq!(&64u32.to_be_bytes(), &image[20..24]);
+        assert_eq!(crc32(&image[12..29]).to_be_bytes(), image[29..33]);
+        assert_eq!(b"IEND", &image[image.len() - 8..image.len() - 4]);
+    }
+
+    #[test]
+    fn png_image_data_holds_filtered_rgb_rows() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 0, &[0x80]);
+
+        let image = encode_png(&screen, &DEFAULT_PALETTE);
+
+        let idat_len = u32::from_be_bytes([image[33], image[34], image[35], image[36]]) as usize;
+        assert_eq!(b"IDAT", &image[37..41]);
+        let stream = &image[41..41 + idat_len];
+        // zlib header, then a single final stored block
+        assert_eq!(&[0x78, 0x01, 0x01], &stream[..3]);
+        let raw = &stream[7..stream.len() - 4];
+        assert_eq!(32 * (64 * 3 + 1), raw.len());
+        assert_eq!(&[0, 0xFF, 0xFF, 0xFF, 0, 0, 0], &raw[..7]);
+        assert_eq!(&adler32(raw).to_be_bytes(), &stream[stream.len() - 4..]);
+    }
+
+    #[test]
+    fn stored_blocks_split_large_data() {
+        let data = vec![7u8; 0x1_0000 + 10];
+
+        let stream = zlib_stored(&data);
+
+        assert_eq!(2 + 5 + 0xFFFF + 5 + 11 + 4, stream.len());
+        assert_eq!(0, stream[2]);
+        assert_eq!(1, stream[2 + 5 + 0xFFFF]);
+    }
+
+    #[test]
+    fn adler32_matches_known_value() {
+        assert_eq!(0x11E6_0398, adler32(b"Wikipedia"));
+    }
+}
@@ -0,0 +1,713 @@
+//! Compiles runs of CHIP-8 instructions to WebAssembly.
+//!
+//! A block starts at some address and runs straight-line instructions that
+//! only touch V0 to VF and I (6XNN, 7XNN, 8XYN, ANNN and FX1E), optionally
+//! ending in a jump (1NNN) or skip (3XNN, 4XNN, 5XY0, 9XY0). Everything else
+//! ends the block and is left to the interpreter, which is also what keeps
+//! timers, the screen, the keypad and memory writes out of compiled code.
+//!
+//! Each block becomes a module of its own that imports a memory named
+//! `env.state` and exports `run: () -> i32`. The state holds V0 to VF at
+//! offsets 0 to 15 and I as a little endian word at 16; `run` updates it and
+//! returns the address to continue at.
+
+use crate::chip8::cpu::Cpu;
+use crate::chip8::fault::StepOutcome;
+use crate::chip8::opcode::{decode, Opcode};
+use crate::chip8::platform::Platform;
+use crate::chip8::quirks::Quirks;
+use std::collections::HashMap;
+
+/// Size of the state a block operates on: V0 to VF, then I.
+pub const JIT_STATE_SIZE: usize = 18;
+
+/// Offset of I in the state.
+const I_OFFSET: u32 = 16;
+
+/// Blocks are cut off after this many instructions.
+const MAX_BLOCK_INSTRUCTIONS: u32 = 64;
+
+/// Instantiates and runs the WebAssembly modules the `Jit` compiles. The
+/// browser frontend uses the browser's own engine; anything else can plug
+/// in an embedded one.
+pub trait Runtime {
+    /// A module ready to run.
+    type Instance;
+
+    fn instantiate(&mut self, module: &[u8]) -> Result<Self::Instance, String>;
+
+    /// Runs `instance` on `state` and returns the address to continue at.
+    fn run(&mut self, instance: &Self::Instance, state: &mut [u8; JIT_STATE_SIZE]) -> u16;
+}
+
+/// A block compiled to a WebAssembly module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledBlock {
+    pub module: Vec<u8>,
+    /// How many instructions one run of the block executes.
+    pub instructions: u32,
+    /// How many bytes of memory from the start address the code depends on.
+    pub source_len: usize,
+}
+
+/// Compiles the block at `start` for `platform` and `quirks`, of at most
+/// `max_instructions`, or returns `None` when the instruction there has to
+/// be interpreted.
+pub fn compile_block(
+    memory: &[u8],
+    start: u16,
+    platform: Platform,
+    quirks: Quirks,
+    max_instructions: u32,
+) -> Option<CompiledBlock> {
+    let word = |address: u16| {
+        let address = address as usize;
+        let bytes = memory.get(address..address + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    };
+
+    let mut body = Body::default();
+    let mut instructions = 0;
+    let mut pc = start;
+    let mut source_end = start as usize;
+    let mut ended = false;
+    while instructions < max_instructions.min(MAX_BLOCK_INSTRUCTIONS) {
+        let opcode = match word(pc).map(decode) {
+            Some(Ok(opcode)) => opcode,
+            _ => break,
+        };
+        let next = match pc.checked_add(2) {
+            Some(next) => next,
+            None => break,
+        };
+        let skip = || {
+            if platform.has_xo_chip() && word(next) == Some(0xF000) {
+                next.wrapping_add(4)
+            } else {
+                next.wrapping_add(2)
+            }
+        };
+
+        let compiled = match opcode {
+            Opcode::JP(address) => {
+                body.i32_const(address as i32);
+                ended = true;
+                true
+            }
+            Opcode::SE(x, kk) | Opcode::SNE(x, kk) => {
+                body.i32_const(skip() as i32);
+                body.i32_const(next as i32);
+                body.register(x);
+                body.i32_const(kk as i32);
+                body.op(if let Opcode::SE(..) = opcode { EQ } else { NE });
+                body.op(SELECT);
+                ended = true;
+                true
+            }
+            Opcode::SER(x, y) | Opcode::SNER(x, y) => {
+                body.i32_const(skip() as i32);
+                body.i32_const(next as i32);
+                body.register(x);
+                body.register(y);
+                body.op(if let Opcode::SER(..) = opcode { EQ } else { NE });
+                body.op(SELECT);
+                ended = true;
+                true
+            }
+            _ => body.instruction(opcode, quirks),
+        };
+        if !compiled {
+            break;
+        }
+
+        instructions += 1;
+        source_end = next as usize;
+        if ended {
+            if let Opcode::SE(..) | Opcode::SNE(..) | Opcode::SER(..) | Opcode::SNER(..) = opcode {
+                // Whether a skip covers two bytes or four depends on the
+                // next instruction.
+                source_end = (source_end + 2).min(memory.len());
+            }
+            break;
+        }
+        pc = next;
+    }
+
+    if instructions == 0 {
+        return None;
+    }
+    if !ended {
+        body.i32_const(source_end as i32);
+    }
+    Some(CompiledBlock {
+        module: body.into_module(),
+        instructions,
+        source_len: source_end - start as usize,
+    })
+}
+
+const SELECT: u8 = 0x1B;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const I32_LOAD8_U: u8 = 0x2D;
+const I32_LOAD16_U: u8 = 0x2F;
+const I32_STORE8: u8 = 0x3A;
+const I32_STORE16: u8 = 0x3B;
+const I32_CONST: u8 = 0x41;
+const EQ: u8 = 0x46;
+const NE: u8 = 0x47;
+const GE_U: u8 = 0x4F;
+const ADD: u8 = 0x6A;
+const SUB: u8 = 0x6B;
+const AND: u8 = 0x71;
+const OR: u8 = 0x72;
+const XOR: u8 = 0x73;
+const SHL: u8 = 0x74;
+const SHR_U: u8 = 0x76;
+const END: u8 = 0x0B;
+
+/// The body of a block's `run` function, which has one i32 local.
+#[derive(Default)]
+struct Body {
+    code: Vec<u8>,
+}
+
+impl Body {
+    /// Emits the code for a straight-line instruction, mirroring what the
+    /// interpreter does for it. Returns false for anything else.
+    fn instruction(&mut self, opcode: Opcode, quirks: Quirks) -> bool {
+        match opcode {
+            Opcode::LD(x, kk) => self.set_register(x, |body| body.i32_const(kk as i32)),
+            Opcode::ADD(x, kk) => self.set_register(x, |body| {
+                body.register(x);
+                body.i32_const(kk as i32);
+                body.op(ADD);
+            }),
+            Opcode::LDR(x, y) => self.set_register(x, |body| body.register(y)),
+            Opcode::OR(x, y) | Opcode::AND(x, y) | Opcode::XOR(x, y) => {
+                let op = match opcode {
+                    Opcode::OR(..) => OR,
+                    Opcode::AND(..) => AND,
+                    _ => XOR,
+                };
+                self.set_register(x, |body| {
+                    body.register(x);
+                    body.register(y);
+                    body.op(op);
+                });
+                if quirks.vf_reset {
+                    self.set_register(0xF, |body| body.i32_const(0));
+                }
+            }
+            Opcode::ADDR(x, y) => {
+                // VF is written last, after VX, with the carry out of bit 7.
+                self.register(x);
+                self.register(y);
+                self.op(ADD);
+                self.code.extend_from_slice(&[LOCAL_SET, 0]);
+                self.set_register(x, |body| body.code.extend_from_slice(&[LOCAL_GET, 0]));
+                self.set_register(0xF, |body| {
+                    body.code.extend_from_slice(&[LOCAL_GET, 0]);
+                    body.i32_const(8);
+                    body.op(SHR_U);
+                });
+            }
+            Opcode::SUBR(x, y) | Opcode::SUBN(x, y) => {
+                // NOT borrow, computed before VX changes.
+                let (minuend, subtrahend) = if let Opcode::SUBR(..) = opcode {
+                    (x, y)
+                } else {
+                    (y, x)
+                };
+                self.register(minuend);
+                self.register(subtrahend);
+                self.op(GE_U);
+                self.code.extend_from_slice(&[LOCAL_SET, 0]);
+                self.set_register(x, |body| {
+                    body.register(minuend);
+                    body.register(subtrahend);
+                    body.op(SUB);
+                });
+                self.set_register(0xF, |body| body.code.extend_from_slice(&[LOCAL_GET, 0]));
+            }
+            Opcode::SHR(x, y) | Opcode::SHL(x, y) => {
+                let source = if quirks.shift_uses_vy { y } else { x };
+                self.register(source);
+                self.code.extend_from_slice(&[LOCAL_SET, 0]);
+                let (shift, flag_shift) = if let Opcode::SHR(..) = opcode {
+                    (SHR_U, None)
+                } else {
+                    (SHL, Some(7))
+                };
+                self.set_register(x, |body| {
+                    body.code.extend_from_slice(&[LOCAL_GET, 0]);
+                    body.i32_const(1);
+                    body.op(shift);
+                });
+                self.set_register(0xF, |body| {
+                    body.code.extend_from_slice(&[LOCAL_GET, 0]);
+                    match flag_shift {
+                        Some(bits) => {
+                            body.i32_const(bits);
+                            body.op(SHR_U);
+                        }
+                        None => {
+                            body.i32_const(1);
+                            body.op(AND);
+                        }
+                    }
+                });
+            }
+            Opcode::LDI(nnn) => self.set_i(|body| body.i32_const(nnn as i32)),
+            Opcode::ADDI(x) => self.set_i(|body| {
+                body.i();
+                body.register(x);
+                body.op(ADD);
+            }),
+            _ => return false,
+        }
+        true
+    }
+
+    fn op(&mut self, op: u8) {
+        self.code.push(op);
+    }
+
+    fn i32_const(&mut self, value: i32) {
+        self.code.push(I32_CONST);
+        write_i32(&mut self.code, value);
+    }
+
+    fn register(&mut self, x: u8) {
+        self.i32_const(0);
+        self.code.extend_from_slice(&[I32_LOAD8_U, 0, x]);
+    }
+
+    /// Stores the value `value` pushes in VX, keeping its low byte.
+    fn set_register(&mut self, x: u8, value: impl FnOnce(&mut Body)) {
+        self.i32_const(0);
+        value(self);
+        self.code.extend_from_slice(&[I32_STORE8, 0, x]);
+    }
+
+    fn i(&mut self) {
+        self.i32_const(0);
+        self.code
+            .extend_from_slice(&[I32_LOAD16_U, 1, I_OFFSET as u8]);
+    }
+
+    fn set_i(&mut self, value: impl FnOnce(&mut Body)) {
+        self.i32_const(0);
+        value(self);
+        self.code
+            .extend_from_slice(&[I32_STORE16, 1, I_OFFSET as u8]);
+    }
+
+    /// Wraps the body, which leaves the next address on the stack, in a
+    /// module exporting it as `run`.
+    fn into_module(self) -> Vec<u8> {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        // () -> i32
+        section(&mut module, 1, &[1, 0x60, 0, 1, 0x7F]);
+        // env.state: a memory of at least one page
+        section(
+            &mut module,
+            2,
+            &[
+                1, 3, b'e', b'n', b'v', 5, b's', b't', b'a', b't', b'e', 2, 0, 1,
+            ],
+        );
+        section(&mut module, 3, &[1, 0]);
+        section(&mut module, 7, &[1, 3, b'r', b'u', b'n', 0, 0]);
+
+        let mut function = vec![1, 1, 0x7F];
+        function.extend_from_slice(&self.code);
+        function.push(END);
+        let mut code = vec![1];
+        write_u32(&mut code, function.len() as u32);
+        code.extend_from_slice(&function);
+        section(&mut module, 10, &code);
+        module
+    }
+}
+
+fn section(module: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    module.push(id);
+    write_u32(module, contents.len() as u32);
+    module.extend_from_slice(contents);
+}
+
+/// Unsigned LEB128.
+fn write_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Signed LEB128.
+fn write_i32(out: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// What the `Jit` knows about the code at one address.
+struct Block<I> {
+    platform: Platform,
+    quirks: Quirks,
+    /// The memory the block was compiled from.
+    source: Vec<u8>,
+    /// The instance and its instruction count, or `None` when the code
+    /// there has to be interpreted.
+    compiled: Option<(I, u32)>,
+}
+
+impl<I> Block<I> {
+    /// Whether the block still describes the code in `cpu`. Self-modifying
+    /// code, loading a program or changing quirks all make it stale.
+    fn is_current(&self, cpu: &Cpu, start: u16) -> bool {
+        let start = start as usize;
+        self.platform == cpu.platform
+            && self.quirks == cpu.quirks
+            && cpu.memory().get(start..start + self.source.len()) == Some(&self.source[..])
+    }
+}
+
+/// The `Jit` type. Runs frames like `Cpu::run_frame`, but executes the
+/// blocks `compile_block` can handle as WebAssembly and interprets only the
+/// rest. Blocks are compiled the first time they run and recompiled when the
+/// memory they came from changes.
+///
+/// Compiled blocks skip the trace, the profiler and the debugger, so this is
+/// for fast-forwarding and batch runs rather than debugging.
+pub struct Jit<R: Runtime> {
+    runtime: R,
+    blocks: HashMap<u16, Block<R::Instance>>,
+    compiled: u64,
+    executed: u64,
+}
+
+impl<R: Runtime> Jit<R> {
+    pub fn new(runtime: R) -> Jit<R> {
+        Jit {
+            runtime,
+            blocks: HashMap::new(),
+            compiled: 0,
+            executed: 0,
+        }
+    }
+
+    /// Modules compiled so far, counting recompiles.
+    pub fn compiled(&self) -> u64 {
+        self.compiled
+    }
+
+    /// Instructions executed by compiled code so far.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Forgets every compiled block.
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// Runs one 60 Hz frame of `cycles` instructions, with the same results
+    /// as `Cpu::run_frame`.
+    pub fn run_frame(&mut self, cpu: &mut Cpu, cycles: u32) -> StepOutcome {
+        let mut outcome = StepOutcome::Executed;
+        let mut left = cycles;
+        while left > 0 {
+            if let Some(instructions) = self.run_block(cpu, left, cycles) {
+                left -= instructions;
+                continue;
+            }
+
+            outcome = cpu.step();
+            left -= 1;
+            if outcome != StepOutcome::Executed {
+                break;
+            }
+        }
+
+        match outcome {
+            StepOutcome::Executed | StepOutcome::Waiting => cpu.tick_timers(),
+            StepOutcome::Exited | StepOutcome::Fault(_) => (),
+        }
+        outcome
+    }
+
+    /// Runs the block at the program counter if there is one of at most
+    /// `cycles` instructions, and returns how many it executed. New blocks
+    /// are kept to `cycles_per_frame` so that they fit in a frame.
+    fn run_block(&mut self, cpu: &mut Cpu, cycles: u32, cycles_per_frame: u32) -> Option<u32> {
+        if !cpu.is_running() {
+            return None;
+        }
+
+        let pc = cpu.pc();
+        if !self
+            .blocks
+            .get(&pc)
+            .is_some_and(|block| block.is_current(cpu, pc))
+        {
+            let block = self.compile(cpu, pc, cycles_per_frame);
+            self.blocks.insert(pc, block);
+        }
+
+        let (instance, instructions) = self.blocks[&pc].compiled.as_ref()?;
+        if *instructions > cycles {
+            return None;
+        }
+
+        let mut state = [0u8; JIT_STATE_SIZE];
+        state[..16].copy_from_slice(cpu.registers());
+        state[16..].copy_from_slice(&cpu.i().to_le_bytes());
+        let next = self.runtime.run(instance, &mut state);
+
+        let mut registers = [0u8; 16];
+        registers.copy_from_slice(&state[..16]);
+        cpu.finish_block(registers, u16::from_le_bytes([state[16], state[17]]), next);
+        self.executed += *instructions as u64;
+        Some(*instructions)
+    }
+
+    fn compile(&mut self, cpu: &Cpu, start: u16, max_instructions: u32) -> Block<R::Instance> {
+        let compiled = compile_block(
+            cpu.memory(),
+            start,
+            cpu.platform,
+            cpu.quirks,
+            max_instructions,
+        );
+        let source_len = compiled.as_ref().map_or(2, |block| block.source_len);
+        let end = (start as usize + source_len).min(cpu.memory().len());
+        let source = cpu
+            .memory()
+            .get(start as usize..end)
+            .unwrap_or_default()
+            .to_vec();
+
+        let compiled = compiled.and_then(|block| {
+            self.compiled += 1;
+            let instance = self.runtime.instantiate(&block.module).ok()?;
+            Some((instance, block.instructions))
+        });
+        Block {
+            platform: cpu.platform,
+            quirks: cpu.quirks,
+            source,
+            compiled,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmi::{Engine, Linker, Memory, MemoryType, Module, Store, TypedFunc};
+
+    /// Runs blocks with wasmi, standing in for the browser.
+    struct Wasmi {
+        engine: Engine,
+        store: Store<()>,
+        linker: Linker<()>,
+        memory: Memory,
+    }
+
+    impl Wasmi {
+        fn new() -> Wasmi {
+            let engine = Engine::default();
+            let mut store = Store::new(&engine, ());
+            let memory = Memory::new(&mut store, MemoryType::new(1, None).unwrap()).unwrap();
+            let mut linker = Linker::new(&engine);
+            linker.define("env", "state", memory).unwrap();
+            Wasmi {
+                engine,
+                store,
+                linker,
+                memory,
+            }
+        }
+    }
+
+    impl Runtime for Wasmi {
+        type Instance = TypedFunc<(), i32>;
+
+        fn instantiate(&mut self, module: &[u8]) -> Result<Self::Instance, String> {
+            let module = Module::new(&self.engine, module).map_err(|err| err.to_string())?;
+            let instance = self
+                .linker
+                .instantiate(&mut self.store, &module)
+                .and_then(|instance| instance.start(&mut self.store))
+                .map_err(|err| err.to_string())?;
+            instance
+                .get_typed_func(&self.store, "run")
+                .map_err(|err| err.to_string())
+        }
+
+        fn run(&mut self, instance: &Self::Instance, state: &mut [u8; JIT_STATE_SIZE]) -> u16 {
+            self.memory.write(&mut self.store, 0, state).unwrap();
+            let next = instance.call(&mut self.store, ()).unwrap();
+            self.memory.read(&self.store, 0, state).unwrap();
+            next as u16
+        }
+    }
+
+    fn rom(program: &[u16]) -> Vec<u8> {
+        program.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    /// Runs `rom` for `frames` frames both interpreted and with the JIT,
+    /// comparing the whole machine after every frame, and returns the JIT.
+    fn run_both(rom: &[u8], platform: Platform, frames: u32, cycles: u32) -> Jit<Wasmi> {
+        let mut interpreted = Cpu::for_platform(platform);
+        let mut jitted = Cpu::for_platform(platform);
+        interpreted.load_rom(rom).unwrap();
+        jitted.load_rom(rom).unwrap();
+
+        let mut jit = Jit::new(Wasmi::new());
+        for frame in 0..frames {
+            let expected = interpreted.run_frame(cycles);
+            let actual = jit.run_frame(&mut jitted, cycles);
+            assert_eq!(expected, actual, "outcome of frame {}", frame);
+            assert!(
+                interpreted.save_state() == jitted.save_state(),
+                "state after frame {}: pc 0x{:03X} vs 0x{:03X}, registers {:?} vs {:?}",
+                frame,
+                interpreted.pc(),
+                jitted.pc(),
+                interpreted.registers(),
+                jitted.registers()
+            );
+        }
+        jit
+    }
+
+    #[test]
+    fn encodes_leb128() {
+        let mut out = Vec::new();
+        write_u32(&mut out, 624_485);
+        write_i32(&mut out, -123_456);
+        write_i32(&mut out, 0x200);
+        assert_eq!(vec![0xE5, 0x8E, 0x26, 0xC0, 0xBB, 0x78, 0x80, 0x04], out);
+    }
+
+    #[test]
+    fn blocks_end_at_jumps_and_uninterpretable_instructions() {
+        // v0 := 1, v1 += 2, jump 0x200, cls
+        let memory = rom(&[0x6001, 0x7102, 0x1200, 0x00E0]);
+        let block = compile_block(&memory, 0, Platform::Chip8, Quirks::default(), 64).unwrap();
+        assert_eq!((3, 6), (block.instructions, block.source_len));
+        assert!(block.module.starts_with(b"\0asm"));
+
+        // v0 := 1, cls
+        let memory = rom(&[0x6001, 0x00E0]);
+        let block = compile_block(&memory, 0, Platform::Chip8, Quirks::default(), 64).unwrap();
+        assert_eq!((1, 2), (block.instructions, block.source_len));
+        assert_eq!(
+            None,
+            compile_block(&memory, 2, Platform::Chip8, Quirks::default(), 64)
+        );
+    }
+
+    #[test]
+    fn skips_depend_on_the_next_instruction() {
+        // if v0 != 0 then i := long 0x1234
+        let memory = rom(&[0x3000, 0xF000, 0x1234]);
+        let block = compile_block(&memory, 0, Platform::XoChip, Quirks::xo_chip(), 64).unwrap();
+        assert_eq!((1, 4), (block.instructions, block.source_len));
+
+        let mut jit = Jit::new(Wasmi::new());
+        let mut cpu = Cpu::for_platform(Platform::XoChip);
+        cpu.load_rom(&memory).unwrap();
+        jit.run_frame(&mut cpu, 1);
+        assert_eq!(0x206, cpu.pc());
+    }
+
+    #[test]
+    fn matches_the_interpreter_on_every_alu_instruction() {
+        let mut program = vec![0x6A10, 0x6B20, 0xA123];
+        for y in 0..16u16 {
+            for op in [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE].iter() {
+                // vX := 0x10 + y, vY += 0x3B, vX op= vY, i += vX
+                let x = (y + 5) % 16;
+                program.push(0x6000 | x << 8 | (0x10 + y));
+                program.push(0x7000 | y << 8 | 0x3B);
+                program.push(0x8000 | x << 8 | y << 4 | op);
+                program.push(0xF01E | x << 8);
+            }
+        }
+        program.push(0x1200);
+
+        for &platform in [Platform::Chip8, Platform::SuperChip, Platform::XoChip].iter() {
+            let jit = run_both(&rom(&program), platform, 100, 40);
+            assert!(jit.executed() > 3000);
+        }
+    }
+
+    #[test]
+    fn matches_the_interpreter_on_loops_and_skips() {
+        // Counts v2 from 0 to 249 and draws its hundreds digit, with every
+        // kind of skip along the way.
+        let program = [
+            0x6200, 0x7201, 0x42FA, 0x6200, 0x6300, 0x32C8, 0x7301, 0x3264, 0x1214, 0x7301, 0x5230,
+            0x6400, 0x9240, 0x6401, 0xF329, 0x00E0, 0xD445, 0x1202,
+        ];
+        run_both(&rom(&program), Platform::Chip8, 600, 10);
+        run_both(&rom(&program), Platform::SuperChip, 300, 50);
+    }
+
+    #[test]
+    fn recompiles_self_modifying_code() {
+        // Counts v2 from 0 to 249, writing its digits with BCD over the
+        // immediate of `v3 := 0`, and draws the digit it loaded from there.
+        let program = [
+            0x6200, 0x7201, 0x42FA, 0x6200, 0xA211, 0xF233, 0x00E0, 0x6400, 0x6300, 0x0000, 0xF329,
+            0xD445, 0x1202,
+        ];
+        let jit = run_both(&rom(&program), Platform::Chip8, 600, 10);
+        // Every change of the hundreds digit recompiled the block loading it.
+        let blocks = jit
+            .blocks
+            .values()
+            .filter(|block| block.compiled.is_some())
+            .count();
+        assert!(jit.compiled() > blocks as u64);
+    }
+
+    #[test]
+    fn matches_the_interpreter_on_tetris() {
+        let rom = include_bytes!("../../roms/tetris.rom");
+        let jit = run_both(rom, Platform::Chip8, 600, 10);
+        assert!(jit.executed() > 0);
+    }
+
+    #[test]
+    fn quirk_changes_recompile() {
+        // v0 := 3, v1 := 0x80, v0 >>= v1, jump 0x200
+        let memory = rom(&[0x6003, 0x6180, 0x8016, 0x1200]);
+        let mut jit = Jit::new(Wasmi::new());
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&memory).unwrap();
+
+        jit.run_frame(&mut cpu, 4);
+        assert_eq!(0x40, cpu.registers()[0]);
+        cpu.quirks.shift_uses_vy = false;
+        jit.run_frame(&mut cpu, 4);
+        assert_eq!(1, cpu.registers()[0]);
+        assert_eq!(2, jit.compiled());
+    }
+}
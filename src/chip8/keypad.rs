@@ -0,0 +1,146 @@
+use crate::chip8::state::{StateError, StateReader, StateWriter};
+
+/// Number of keys on the chip8 hex keypad.
+pub const KEY_COUNT: u8 = 16;
+
+/// The `Keypad` type. Tracks which of the 16 hex keys are held down, plus the
+/// press and release edges seen since `clear_events`, which FX0A needs in
+/// order to wait for a full key press.
+#[derive(Default)]
+pub struct Keypad {
+    state: u16,
+    pressed: u16,
+    released: u16,
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad::default()
+    }
+
+    /// Marks `key` as held down. Keys above 0xF are ignored.
+    pub fn key_down(&mut self, key: u8) {
+        if key >= KEY_COUNT || self.is_pressed(key) {
+            return;
+        }
+
+        self.state |= 1 << key;
+        self.pressed |= 1 << key;
+    }
+
+    /// Marks `key` as released. Keys above 0xF are ignored.
+    pub fn key_up(&mut self, key: u8) {
+        if key >= KEY_COUNT || !self.is_pressed(key) {
+            return;
+        }
+
+        self.state &= !(1 << key);
+        self.released |= 1 << key;
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        key < KEY_COUNT && self.state & (1 << key) != 0
+    }
+
+    /// Forgets all press and release edges seen so far. Keys that are held
+    /// down stay held down.
+    pub fn clear_events(&mut self) {
+        self.pressed = 0;
+        self.released = 0;
+    }
+
+    /// Returns the lowest key that was both pressed and released since the
+    /// last `clear_events`, and clears the events when one is found.
+    pub fn take_released_key(&mut self) -> Option<u8> {
+        let completed = self.pressed & self.released;
+        if completed == 0 {
+            return None;
+        }
+
+        self.clear_events();
+        Some(completed.trailing_zeros() as u8)
+    }
+
+    pub(crate) fn save(&self, writer: &mut StateWriter) {
+        writer.u16(self.state);
+        writer.u16(self.pressed);
+        writer.u16(self.released);
+    }
+
+    pub(crate) fn load(reader: &mut StateReader) -> Result<Keypad, StateError> {
+        Ok(Keypad {
+            state: reader.u16()?,
+            pressed: reader.u16()?,
+            released: reader.u16()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_sixteen_keys_can_be_pressed() {
+        let mut keypad = Keypad::new();
+
+        for key in 0..KEY_COUNT {
+            keypad.key_down(key);
+        }
+
+        assert!((0..KEY_COUNT).all(|key| keypad.is_pressed(key)));
+    }
+
+    #[test]
+    fn key_up_releases_key() {
+        let mut keypad = Keypad::new();
+
+        keypad.key_down(0xF);
+        keypad.key_up(0xF);
+
+        assert!(!keypad.is_pressed(0xF));
+    }
+
+    #[test]
+    fn key_up_without_key_down_is_ignored() {
+        let mut keypad = Keypad::new();
+
+        keypad.key_up(3);
+
+        assert!(!keypad.is_pressed(3));
+        assert_eq!(None, keypad.take_released_key());
+    }
+
+    #[test]
+    fn out_of_range_keys_are_ignored() {
+        let mut keypad = Keypad::new();
+
+        keypad.key_down(16);
+
+        assert!(!keypad.is_pressed(16));
+        assert!((0..KEY_COUNT).all(|key| !keypad.is_pressed(key)));
+    }
+
+    #[test]
+    fn take_released_key_needs_press_and_release() {
+        let mut keypad = Keypad::new();
+
+        keypad.key_down(0xA);
+        assert_eq!(None, keypad.take_released_key());
+
+        keypad.key_up(0xA);
+        assert_eq!(Some(0xA), keypad.take_released_key());
+        assert_eq!(None, keypad.take_released_key());
+    }
+
+    #[test]
+    fn key_held_before_clear_events_does_not_count() {
+        let mut keypad = Keypad::new();
+
+        keypad.key_down(2);
+        keypad.clear_events();
+        keypad.key_up(2);
+
+        assert_eq!(None, keypad.take_released_key());
+    }
+}
@@ -0,0 +1,395 @@
+//! Runs the cpu in lockstep with an execution trace recorded by another
+//! emulator, stopping at the first instruction where the two disagree.
+//!
+//! A reference trace is plain text with one line per instruction, giving
+//! the machine state right before the instruction at PC executes:
+//!
+//! ```text
+//! # pc  opcode v0 v1 v2 v3 v4 v5 v6 v7 v8 v9 va vb vc vd ve vf i
+//! 0200  A2B4   00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 0000
+//! 0202  23E6   00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 02B4
+//! ```
+//!
+//! That is PC, the opcode at PC, V0 to VF and I, all in hex with an
+//! optional `0x` prefix and separated by whitespace or commas. Blank lines
+//! and everything after a `#` are ignored.
+
+use crate::chip8::cpu::Cpu;
+use crate::chip8::disasm::{format_opcode, Syntax};
+use crate::chip8::fault::{CpuFault, StepOutcome};
+use crate::chip8::opcode::decode;
+use std::fmt;
+use std::fmt::Write;
+
+/// The machine state before one instruction, as a reference trace records
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceState {
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: [u8; 16],
+    pub i: u16,
+}
+
+impl TraceState {
+    /// The state of `cpu` about to execute its next instruction.
+    pub fn of(cpu: &Cpu) -> TraceState {
+        TraceState {
+            pc: cpu.pc(),
+            opcode: cpu.word_at(cpu.pc()).unwrap_or(0),
+            registers: *cpu.registers(),
+            i: cpu.i(),
+        }
+    }
+}
+
+/// Formats the state as a reference trace line.
+impl fmt::Display for TraceState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X} {:04X}", self.pc, self.opcode)?;
+        for register in self.registers.iter() {
+            write!(f, " {:02X}", register)?;
+        }
+        write!(f, " {:04X}", self.i)
+    }
+}
+
+/// A malformed line in a reference trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TraceParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// A reference trace: the states it lists, with their line numbers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReferenceTrace {
+    states: Vec<(usize, TraceState)>,
+}
+
+impl ReferenceTrace {
+    pub fn parse(text: &str) -> Result<ReferenceTrace, TraceParseError> {
+        let mut states = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: String| TraceParseError {
+                line: line_number,
+                message,
+            };
+            let content = line.split('#').next().unwrap();
+            let fields: Vec<&str> = content
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|field| !field.is_empty())
+                .collect();
+            if fields.is_empty() {
+                continue;
+            }
+            if fields.len() != 19 {
+                return Err(error(format!(
+                    "expected 19 fields (pc, opcode, v0 to vf, i), found {}",
+                    fields.len()
+                )));
+            }
+
+            let hex = |field: &str, max: u32| {
+                let digits = field
+                    .strip_prefix("0x")
+                    .or_else(|| field.strip_prefix("0X"))
+                    .unwrap_or(field);
+                u32::from_str_radix(digits, 16)
+                    .ok()
+                    .filter(|&value| value <= max)
+                    .ok_or_else(|| error(format!("invalid value: {}", field)))
+            };
+            let mut registers = [0u8; 16];
+            for (register, field) in registers.iter_mut().zip(&fields[2..18]) {
+                *register = hex(field, 0xFF)? as u8;
+            }
+            states.push((
+                line_number,
+                TraceState {
+                    pc: hex(fields[0], 0xFFFF)? as u16,
+                    opcode: hex(fields[1], 0xFFFF)? as u16,
+                    registers,
+                    i: hex(fields[18], 0xFFFF)? as u16,
+                },
+            ));
+        }
+
+        Ok(ReferenceTrace { states })
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub fn states(&self) -> impl Iterator<Item = &TraceState> {
+        self.states.iter().map(|(_, state)| state)
+    }
+
+    /// Runs `cpu` one instruction per line of the trace, ticking the timers
+    /// every `cycles_per_frame` instructions, until its state differs from
+    /// the trace or the trace ends.
+    pub fn run_lockstep(&self, cpu: &mut Cpu, cycles_per_frame: u32) -> Lockstep {
+        let cycles_per_frame = cycles_per_frame.max(1);
+        let mut cycles = 0;
+        let mut previous = None;
+
+        for (index, &(line, expected)) in self.states.iter().enumerate() {
+            let actual = TraceState::of(cpu);
+            if actual != expected {
+                return Lockstep {
+                    matched: index,
+                    divergence: Some(Divergence {
+                        line,
+                        previous,
+                        expected,
+                        actual,
+                        fault: None,
+                    }),
+                };
+            }
+
+            loop {
+                match cpu.step() {
+                    StepOutcome::Executed => break,
+                    StepOutcome::Waiting => {
+                        cpu.tick_timers();
+                        cycles = 0;
+                    }
+                    StepOutcome::Exited => {
+                        return Lockstep {
+                            matched: index + 1,
+                            divergence: self.states.get(index + 1).map(|&(line, expected)| {
+                                Divergence {
+                                    line,
+                                    previous: Some(actual),
+                                    expected,
+                                    actual: TraceState::of(cpu),
+                                    fault: None,
+                                }
+                            }),
+                        };
+                    }
+                    StepOutcome::Fault(fault) => {
+                        return Lockstep {
+                            matched: index,
+                            divergence: Some(Divergence {
+                                line,
+                                previous,
+                                expected,
+                                actual,
+                                fault: Some(fault),
+                            }),
+                        };
+                    }
+                }
+            }
+            cycles += 1;
+            if cycles == cycles_per_frame {
+                cpu.tick_timers();
+                cycles = 0;
+            }
+            previous = Some(actual);
+        }
+
+        Lockstep {
+            matched: self.states.len(),
+            divergence: None,
+        }
+    }
+}
+
+/// The result of `ReferenceTrace::run_lockstep`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lockstep {
+    /// The number of trace lines the cpu agreed with.
+    pub matched: usize,
+    pub divergence: Option<Divergence>,
+}
+
+/// Where the cpu first disagreed with the reference trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The line of the trace that disagrees.
+    pub line: usize,
+    /// The state before the last instruction both agreed on, if any.
+    pub previous: Option<TraceState>,
+    pub expected: TraceState,
+    pub actual: TraceState,
+    /// Set when the cpu faulted on an instruction the reference executed.
+    pub fault: Option<CpuFault>,
+}
+
+/// A report with the expected and actual states side by side and the
+/// differing rows marked.
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.fault {
+            Some(fault) => writeln!(f, "cpu faulted at trace line {}: {}", self.line, fault)?,
+            None => writeln!(f, "diverged at trace line {}", self.line)?,
+        }
+        if let Some(previous) = self.previous {
+            let instruction = match decode(previous.opcode) {
+                Ok(opcode) => format_opcode(opcode, 0, Syntax::Cowgod),
+                Err(_) => "???".to_string(),
+            };
+            writeln!(
+                f,
+                "after 0x{:03X}  {:04X}  {}",
+                previous.pc, previous.opcode, instruction
+            )?;
+        }
+
+        let mut rows = vec![
+            (
+                "pc".to_string(),
+                format!("0x{:03X}", self.expected.pc),
+                format!("0x{:03X}", self.actual.pc),
+            ),
+            (
+                "opcode".to_string(),
+                format!("{:04X}", self.expected.opcode),
+                format!("{:04X}", self.actual.opcode),
+            ),
+        ];
+        for index in 0..16 {
+            rows.push((
+                format!("v{:x}", index),
+                format!("0x{:02X}", self.expected.registers[index]),
+                format!("0x{:02X}", self.actual.registers[index]),
+            ));
+        }
+        rows.push((
+            "i".to_string(),
+            format!("0x{:03X}", self.expected.i),
+            format!("0x{:03X}", self.actual.i),
+        ));
+
+        writeln!(f, "{:<8}{:<10}actual", "", "expected")?;
+        for (name, expected, actual) in rows {
+            let mut row = String::new();
+            write!(row, "{:<8}{:<10}{:<10}", name, expected, actual).unwrap();
+            if expected != actual {
+                row.push('<');
+            }
+            writeln!(f, "{}", row.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // v0 := 5, v1 := 3, v0 += v1, i := 0x300, jump 0x208
+    const PROGRAM: [u8; 10] = [0x60, 0x05, 0x61, 0x03, 0x80, 0x14, 0xA3, 0x00, 0x12, 0x08];
+
+    fn cpu() -> Cpu {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&PROGRAM).unwrap();
+        cpu
+    }
+
+    /// Our own trace of `steps` instructions, as another emulator would
+    /// write it.
+    fn record(steps: usize) -> String {
+        let mut cpu = cpu();
+        let mut text = String::from("# pc opcode v0-vf i\n");
+        for _ in 0..steps {
+            writeln!(text, "{}", TraceState::of(&cpu)).unwrap();
+            cpu.step();
+        }
+        text
+    }
+
+    #[test]
+    fn parses_the_documented_format() {
+        let trace = ReferenceTrace::parse(
+            "# comment\n\n\
+             0x0200, 0x6005, 0,0,0,0, 0,0,0,0, 0,0,0,0, 0,0,0,ff, 0x2b4  # trailing\n",
+        )
+        .unwrap();
+
+        let state = trace.states().next().unwrap();
+        assert_eq!(1, trace.len());
+        assert_eq!(0x200, state.pc);
+        assert_eq!(0x6005, state.opcode);
+        assert_eq!(0xFF, state.registers[15]);
+        assert_eq!(0x2B4, state.i);
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        let error = |text: &str| ReferenceTrace::parse(text).unwrap_err().to_string();
+
+        assert_eq!(
+            "line 2: expected 19 fields (pc, opcode, v0 to vf, i), found 2",
+            error("\n0200 6005\n")
+        );
+        assert_eq!(
+            "line 1: invalid value: 100",
+            error("0200 6005 100 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0")
+        );
+    }
+
+    #[test]
+    fn matching_traces_run_to_the_end() {
+        let trace = ReferenceTrace::parse(&record(8)).unwrap();
+
+        let lockstep = trace.run_lockstep(&mut cpu(), 10);
+
+        assert_eq!(8, lockstep.matched);
+        assert_eq!(None, lockstep.divergence);
+    }
+
+    #[test]
+    fn stops_at_the_first_difference() {
+        // The reference thinks v0 += v1 overflowed into a different value.
+        let text = record(6).replace("0206 A300 08 03", "0206 A300 09 03");
+        let trace = ReferenceTrace::parse(&text).unwrap();
+
+        let lockstep = trace.run_lockstep(&mut cpu(), 10);
+        let divergence = lockstep.divergence.unwrap();
+
+        assert_eq!(3, lockstep.matched);
+        assert_eq!(5, divergence.line);
+        assert_eq!(0x09, divergence.expected.registers[0]);
+        assert_eq!(0x08, divergence.actual.registers[0]);
+        assert_eq!(0x204, divergence.previous.unwrap().pc);
+
+        let report = divergence.to_string();
+        assert!(report.starts_with("diverged at trace line 5\nafter 0x204  8014  ADD V0, V1\n"));
+        assert!(report.contains("\nv0      0x09      0x08      <\n"));
+        assert!(report.contains("\nv1      0x03      0x03\n"));
+    }
+
+    #[test]
+    fn reports_faults() {
+        let trace = ReferenceTrace::parse(
+            "0200 00EE 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 0000\n",
+        )
+        .unwrap();
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&[0x00, 0xEE]).unwrap();
+
+        let divergence = trace.run_lockstep(&mut cpu, 10).divergence.unwrap();
+
+        assert!(divergence.fault.is_some());
+        assert!(divergence
+            .to_string()
+            .starts_with("cpu faulted at trace line 1: stack underflow"));
+    }
+}
@@ -1,8 +1,51 @@
+mod asm;
+mod audio;
 mod cpu;
+mod debug;
+mod decode_cache;
+mod disasm;
+mod fault;
+mod font;
+mod image;
+mod jit;
+mod json;
+mod keypad;
+mod lockstep;
+mod octo;
 mod opcode;
-mod render;
+mod platform;
+mod profile;
+mod quirks;
+mod rewind;
+mod rng;
+mod romdb;
 mod screen;
+mod state;
+mod timer;
+mod trace;
 
-pub use cpu::Cpu;
-pub use render::Renderer;
-pub use screen::Screen;
+pub use asm::{assemble, assemble_with_includes, AsmError, Program};
+pub use audio::Audio;
+pub use cpu::{Cpu, LoadError};
+pub use debug::{
+    AccessKind, Condition, ConditionError, Debugger, MemoryAccess, Stop, Watch, STEP_LIMIT_FRAMES,
+};
+pub use disasm::{disassemble, format_opcode, Syntax, DEFAULT_ORIGIN};
+pub use fault::{CpuFault, FaultKind, StepOutcome};
+pub use font::{Font, FontError, FontSize, BIG_FONT_ADDRESS, FONT_ADDRESS};
+pub use image::{encode_pbm, encode_png};
+pub use jit::{compile_block, CompiledBlock, Jit, Runtime, JIT_STATE_SIZE};
+pub use keypad::{Keypad, KEY_COUNT};
+pub use lockstep::{Divergence, Lockstep, ReferenceTrace, TraceParseError, TraceState};
+pub use octo::compile_octo;
+pub use opcode::{decode, DecodeError, Opcode};
+pub use platform::Platform;
+pub use profile::{HotLoop, Profiler, Stats};
+pub use quirks::Quirks;
+pub use rewind::Rewind;
+pub use rng::Rng;
+pub use romdb::{sha1_hex, DatabaseError, RomDatabase, RomInfo};
+pub use screen::{Screen, DEFAULT_PALETTE};
+pub use state::StateError;
+pub use timer::{Timers, TIMER_FREQUENCY};
+pub use trace::{Change, Trace, TraceEntry, DEFAULT_TRACE_CAPACITY};
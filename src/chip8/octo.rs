@@ -0,0 +1,1227 @@
+use crate::chip8::asm::{AsmError, Program};
+use crate::chip8::disasm::DEFAULT_ORIGIN;
+use crate::chip8::opcode::Opcode;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+
+/// Stops runaway macros that expand into themselves.
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+
+/// A whitespace separated word of source and where it was found.
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn is(&self, text: &str) -> bool {
+        self.text == text
+    }
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let line_text = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let mut start = None;
+        for (column, c) in line_text.char_indices().chain(Some((line_text.len(), ' '))) {
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(column),
+                (true, Some(from)) => {
+                    tokens.push_back(Token {
+                        text: line_text[from..column].to_string(),
+                        line: index + 1,
+                        column: line_text[..from].chars().count() + 1,
+                    });
+                    start = None;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.chars().all(|c| c.is_ascii_digit()) && !digits.is_empty() {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// An operand that names code or data: known right away, or a label
+/// defined further down that gets patched in at the end.
+enum Target {
+    Known(i64),
+    Forward(Token),
+}
+
+/// Where and how to write a forward reference once its label is known.
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    /// The low 12 bits of the instruction word.
+    Address,
+    /// A whole 16 bit word, as for `i := long`.
+    Long,
+    /// The byte written by `:unpack`'s first half, with the nibble on top.
+    UnpackHigh(u8),
+    /// The byte written by `:unpack`'s second half.
+    UnpackLow,
+}
+
+struct Fixup {
+    address: u16,
+    kind: FixupKind,
+    token: Token,
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// A condition of `if` and `while`, in the form it compiles to.
+enum Condition {
+    /// A single skip instruction; the first opcode skips when the
+    /// condition holds, the second when it doesn't.
+    Skip(Opcode, Opcode),
+    /// Compares through vf first, then skips on vf's value.
+    Compare {
+        setup: [Opcode; 2],
+        vf_when_true: u8,
+    },
+}
+
+enum Block {
+    Loop {
+        start: u16,
+        whiles: Vec<u16>,
+        token: Token,
+    },
+    If {
+        jump: u16,
+        token: Token,
+    },
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    file: String,
+    rom: Vec<u8>,
+    origin: Option<u16>,
+    here: u32,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    breakpoints: BTreeMap<u16, String>,
+    expansions: usize,
+}
+
+impl Compiler {
+    fn new(source: &str, file: &str) -> Compiler {
+        Compiler {
+            tokens: tokenize(source),
+            file: file.to_string(),
+            rom: Vec::new(),
+            origin: None,
+            here: DEFAULT_ORIGIN as u32,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            breakpoints: BTreeMap::new(),
+            expansions: 0,
+        }
+    }
+
+    fn error(&self, token: &Token, message: impl Into<String>) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: token.line,
+            column: token.column,
+            message: message.into(),
+        }
+    }
+
+    fn next(&mut self, after: &Token) -> Result<Token, AsmError> {
+        self.tokens.pop_front().ok_or_else(|| {
+            self.error(
+                after,
+                format!("unexpected end of file after `{}`", after.text),
+            )
+        })
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|next| next.is(text))
+    }
+
+    fn expect(&mut self, after: &Token, text: &str) -> Result<Token, AsmError> {
+        let token = self.next(after)?;
+        if !token.is(text) {
+            return Err(self.error(
+                &token,
+                format!("expected `{}`, found `{}`", text, token.text),
+            ));
+        }
+        Ok(token)
+    }
+
+    fn here(&self) -> u16 {
+        self.here as u16
+    }
+
+    fn emit_byte(&mut self, token: &Token, byte: u8) -> Result<(), AsmError> {
+        if self.here > 0xFFFF {
+            return Err(self.error(token, "program too large"));
+        }
+        let origin = *self.origin.get_or_insert(self.here as u16) as u32;
+        let offset = (self.here - origin) as usize;
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, token: &Token, opcode: Opcode) -> Result<(), AsmError> {
+        for byte in opcode.encode().to_be_bytes().iter() {
+            self.emit_byte(token, *byte)?;
+        }
+        Ok(())
+    }
+
+    fn patch(&mut self, address: u16, byte: u8) {
+        let offset = (address - self.origin.unwrap()) as usize;
+        self.rom[offset] = byte;
+    }
+
+    fn patch_address(&mut self, address: u16, target: u16) {
+        let offset = (address - self.origin.unwrap()) as usize;
+        let high = self.rom[offset] & 0xF0 | (target >> 8) as u8 & 0x0F;
+        self.patch(address, high);
+        self.patch(address + 1, target as u8);
+    }
+
+    /// Emits an instruction taking a 12 bit address, patching it later if the
+    /// target isn't defined yet.
+    fn emit_with_target(
+        &mut self,
+        token: &Token,
+        target: Target,
+        opcode: fn(u16) -> Opcode,
+    ) -> Result<(), AsmError> {
+        let address = self.here();
+        match target {
+            Target::Known(value) => {
+                let value = self.check_range(token, value, 0, 0xFFF, "address")?;
+                self.emit(token, opcode(value))
+            }
+            Target::Forward(name) => {
+                self.fixups.push(Fixup {
+                    address,
+                    kind: FixupKind::Address,
+                    token: name,
+                });
+                self.emit(token, opcode(0))
+            }
+        }
+    }
+
+    fn check_range(
+        &self,
+        token: &Token,
+        value: i64,
+        min: i64,
+        max: i64,
+        what: &str,
+    ) -> Result<u16, AsmError> {
+        if value < min || value > max {
+            return Err(self.error(token, format!("{} out of range: {}", what, value)));
+        }
+        Ok(value as u16)
+    }
+
+    fn register(&self, token: &Token) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(&token.text) {
+            return Some(register);
+        }
+        let index = token.text.strip_prefix('v')?;
+        if index.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(index, 16).ok()
+    }
+
+    fn expect_register(&mut self, after: &Token) -> Result<u8, AsmError> {
+        let token = self.next(after)?;
+        self.register(&token).ok_or_else(|| {
+            self.error(
+                &token,
+                format!("expected a register, found `{}`", token.text),
+            )
+        })
+    }
+
+    /// A number or constant.
+    fn value(&self, token: &Token) -> Result<i64, AsmError> {
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(value);
+        }
+        if let Some(&value) = self.constants.get(&token.text) {
+            return Ok(value);
+        }
+        if let Some(&address) = self.labels.get(&token.text) {
+            return Ok(address as i64);
+        }
+        Err(self.error(token, format!("expected a number, found `{}`", token.text)))
+    }
+
+    fn expect_value(
+        &mut self,
+        after: &Token,
+        min: i64,
+        max: i64,
+        what: &str,
+    ) -> Result<u16, AsmError> {
+        let token = self.next(after)?;
+        let value = self.value(&token)?;
+        self.check_range(&token, value, min, max, what)
+    }
+
+    fn expect_byte(&mut self, after: &Token) -> Result<u8, AsmError> {
+        Ok(self.expect_value(after, -128, 255, "byte")? as u8)
+    }
+
+    fn expect_nibble(&mut self, after: &Token) -> Result<u8, AsmError> {
+        Ok(self.expect_value(after, 0, 15, "nibble")? as u8)
+    }
+
+    /// A number, constant or label, which may be defined later on.
+    fn expect_target(&mut self, after: &Token) -> Result<(Token, Target), AsmError> {
+        let token = self.next(after)?;
+        let target = match self.value(&token) {
+            Ok(value) => Target::Known(value),
+            Err(_) if is_identifier(&token.text) && self.register(&token).is_none() => {
+                Target::Forward(token.clone())
+            }
+            Err(err) => return Err(err),
+        };
+        Ok((token, target))
+    }
+
+    fn define(&mut self, token: &Token) -> Result<String, AsmError> {
+        let name = token.text.clone();
+        let taken = self.labels.contains_key(&name)
+            || self.constants.contains_key(&name)
+            || self.aliases.contains_key(&name)
+            || self.macros.contains_key(&name);
+        if taken {
+            return Err(self.error(token, format!("`{}` is already defined", name)));
+        }
+        if !is_identifier(&name) || self.register(token).is_some() {
+            return Err(self.error(token, format!("`{}` can't be used as a name", name)));
+        }
+        Ok(name)
+    }
+
+    fn compile(&mut self) -> Result<(), AsmError> {
+        while let Some(token) = self.tokens.pop_front() {
+            self.statement(token)?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let (token, missing) = match block {
+                Block::Loop { token, .. } => (token, "again"),
+                Block::If { token, .. } => (token, "end"),
+            };
+            return Err(self.error(token, format!("missing `{}`", missing)));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = match self.labels.get(&fixup.token.text) {
+                Some(&address) => address,
+                None => {
+                    let message = format!("unknown label `{}`", fixup.token.text);
+                    return Err(self.error(&fixup.token, message));
+                }
+            };
+            match fixup.kind {
+                FixupKind::Address => {
+                    self.check_range(&fixup.token, address as i64, 0, 0xFFF, "address")?;
+                    self.patch_address(fixup.address, address);
+                }
+                FixupKind::Long => {
+                    self.patch(fixup.address, (address >> 8) as u8);
+                    self.patch(fixup.address + 1, address as u8);
+                }
+                FixupKind::UnpackHigh(nibble) => {
+                    self.patch(fixup.address, nibble << 4 | (address >> 8) as u8 & 0x0F);
+                }
+                FixupKind::UnpackLow => self.patch(fixup.address, address as u8),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        if let Some(register) = self.register(&token) {
+            return self.register_statement(&token, register);
+        }
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next(&token)?;
+                let name = self.define(&name)?;
+                self.labels.insert(name, self.here());
+            }
+            ":const" => {
+                let name = self.next(&token)?;
+                let name = self.define(&name)?;
+                let value = self.next(&token)?;
+                let value = self.value(&value)?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next(&token)?;
+                let name = self.define(&name)?;
+                let register = self.expect_register(&token)?;
+                self.aliases.insert(name, register);
+            }
+            ":macro" => {
+                let name = self.next(&token)?;
+                let name = self.define(&name)?;
+                let mut parameters = Vec::new();
+                loop {
+                    let parameter = self.next(&token)?;
+                    if parameter.is("{") {
+                        break;
+                    }
+                    parameters.push(parameter.text);
+                }
+                let body = self.braced(&token)?;
+                self.macros.insert(name, Macro { parameters, body });
+            }
+            ":calc" => {
+                let name = self.next(&token)?;
+                let name = self.define(&name)?;
+                self.expect(&token, "{")?;
+                let body = self.braced(&token)?;
+                let value = self.calculate(&token, &body)?;
+                self.constants.insert(name, value);
+            }
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    self.tokens.pop_front();
+                    let body = self.braced(&token)?;
+                    self.calculate(&token, &body)?
+                } else {
+                    let value = self.next(&token)?;
+                    self.value(&value)?
+                };
+                let byte = self.check_range(&token, value, -128, 255, "byte")?;
+                self.emit_byte(&token, byte as u8)?;
+            }
+            ":org" => {
+                let address = self.expect_value(&token, 0, 0xFFFF, "address")? as u32;
+                if let Some(origin) = self.origin {
+                    if address < origin as u32 {
+                        return Err(
+                            self.error(&token, "can't move before the start of the program")
+                        );
+                    }
+                }
+                self.here = address;
+            }
+            ":call" => {
+                let (target_token, target) = self.expect_target(&token)?;
+                self.emit_with_target(&target_token, target, Opcode::CALL)?;
+            }
+            ":breakpoint" => {
+                let name = self.next(&token)?;
+                self.breakpoints.insert(self.here(), name.text);
+            }
+            ":unpack" => {
+                let nibble = self.expect_nibble(&token)?;
+                let (target_token, target) = self.expect_target(&token)?;
+                let address = self.here();
+                let value = match target {
+                    Target::Known(value) => {
+                        self.check_range(&target_token, value, 0, 0xFFF, "address")?
+                    }
+                    Target::Forward(name) => {
+                        self.fixups.push(Fixup {
+                            address: address + 1,
+                            kind: FixupKind::UnpackHigh(nibble),
+                            token: name.clone(),
+                        });
+                        self.fixups.push(Fixup {
+                            address: address + 3,
+                            kind: FixupKind::UnpackLow,
+                            token: name,
+                        });
+                        0
+                    }
+                };
+                self.emit(&token, Opcode::LD(0, nibble << 4 | (value >> 8) as u8))?;
+                self.emit(&token, Opcode::LD(1, value as u8))?;
+            }
+            "clear" => self.emit(&token, Opcode::CLS)?,
+            "return" | ";" => self.emit(&token, Opcode::RET)?,
+            "exit" => self.emit(&token, Opcode::EXIT)?,
+            "hires" => self.emit(&token, Opcode::HIGH)?,
+            "lores" => self.emit(&token, Opcode::LOW)?,
+            "scroll-left" => self.emit(&token, Opcode::SCL)?,
+            "scroll-right" => self.emit(&token, Opcode::SCR)?,
+            "audio" => self.emit(&token, Opcode::AUDIO)?,
+            "scroll-down" => {
+                let rows = self.expect_nibble(&token)?;
+                self.emit(&token, Opcode::SCD(rows))?;
+            }
+            "scroll-up" => {
+                let rows = self.expect_nibble(&token)?;
+                self.emit(&token, Opcode::SCU(rows))?;
+            }
+            "plane" => {
+                let planes = self.expect_nibble(&token)?;
+                self.emit(&token, Opcode::PLANE(planes))?;
+            }
+            "bcd" => {
+                let x = self.expect_register(&token)?;
+                self.emit(&token, Opcode::LDB(x))?;
+            }
+            "save" | "load" => {
+                let x = self.expect_register(&token)?;
+                let range = self.peek_is("-");
+                let opcode = if range {
+                    self.tokens.pop_front();
+                    let y = self.expect_register(&token)?;
+                    if token.is("save") {
+                        Opcode::SAVE(x, y)
+                    } else {
+                        Opcode::LOAD(x, y)
+                    }
+                } else if token.is("save") {
+                    Opcode::LDIR(x)
+                } else {
+                    Opcode::LDRI(x)
+                };
+                self.emit(&token, opcode)?;
+            }
+            "saveflags" => {
+                let x = self.expect_register(&token)?;
+                self.emit(&token, Opcode::STRPL(x))?;
+            }
+            "loadflags" => {
+                let x = self.expect_register(&token)?;
+                self.emit(&token, Opcode::LDRPL(x))?;
+            }
+            "sprite" => {
+                let x = self.expect_register(&token)?;
+                let y = self.expect_register(&token)?;
+                let rows = self.expect_nibble(&token)?;
+                self.emit(&token, Opcode::DRW(x, y, rows))?;
+            }
+            "jump" => {
+                let (target_token, target) = self.expect_target(&token)?;
+                self.emit_with_target(&target_token, target, Opcode::JP)?;
+            }
+            "jump0" => {
+                let (target_token, target) = self.expect_target(&token)?;
+                self.emit_with_target(&target_token, target, Opcode::JPR)?;
+            }
+            "native" => {
+                let (target_token, target) = self.expect_target(&token)?;
+                self.emit_with_target(&target_token, target, Opcode::SYS)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(&token, ":=")?;
+                let x = self.expect_register(&token)?;
+                let opcode = match token.text.as_str() {
+                    "delay" => Opcode::DTLD(x),
+                    "buzzer" => Opcode::STLD(x),
+                    _ => Opcode::PITCH(x),
+                };
+                self.emit(&token, opcode)?;
+            }
+            "i" => self.i_statement(&token)?,
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here(),
+                whiles: Vec::new(),
+                token,
+            }),
+            "while" => {
+                let condition = self.condition(&token)?;
+                self.emit_skip(&token, &condition, true)?;
+                let jump = self.here();
+                self.emit(&token, Opcode::JP(0))?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { whiles, .. }) => whiles.push(jump),
+                    _ => return Err(self.error(&token, "`while` outside of a loop")),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, whiles, .. }) => {
+                    self.check_range(&token, start as i64, 0, 0xFFF, "address")?;
+                    self.emit(&token, Opcode::JP(start))?;
+                    for jump in whiles {
+                        let here = self.here();
+                        self.patch_address(jump, here);
+                    }
+                }
+                _ => return Err(self.error(&token, "`again` without `loop`")),
+            },
+            "if" => {
+                let condition = self.condition(&token)?;
+                let keyword = self.next(&token)?;
+                match keyword.text.as_str() {
+                    "then" => self.emit_skip(&token, &condition, false)?,
+                    "begin" => {
+                        self.emit_skip(&token, &condition, true)?;
+                        let jump = self.here();
+                        self.emit(&token, Opcode::JP(0))?;
+                        self.blocks.push(Block::If { jump, token });
+                    }
+                    _ => {
+                        let message =
+                            format!("expected `then` or `begin`, found `{}`", keyword.text);
+                        return Err(self.error(&keyword, message));
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let end_jump = self.here();
+                    self.emit(&token, Opcode::JP(0))?;
+                    let here = self.here();
+                    self.patch_address(jump, here);
+                    self.blocks.push(Block::If {
+                        jump: end_jump,
+                        token,
+                    });
+                }
+                _ => return Err(self.error(&token, "`else` without `if ... begin`")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let here = self.here();
+                    self.patch_address(jump, here);
+                }
+                _ => return Err(self.error(&token, "`end` without `if ... begin`")),
+            },
+            _ => self.word(token)?,
+        }
+
+        Ok(())
+    }
+
+    /// A number, constant, macro call or subroutine call on its own.
+    fn word(&mut self, token: Token) -> Result<(), AsmError> {
+        if self.macros.contains_key(&token.text) {
+            return self.expand(&token);
+        }
+        if let Some(value) =
+            parse_number(&token.text).or_else(|| self.constants.get(&token.text).copied())
+        {
+            let byte = self.check_range(&token, value, -128, 255, "byte")?;
+            return self.emit_byte(&token, byte as u8);
+        }
+        if is_identifier(&token.text) && !token.text.starts_with(':') {
+            let target = match self.labels.get(&token.text) {
+                Some(&address) => Target::Known(address as i64),
+                None => Target::Forward(token.clone()),
+            };
+            return self.emit_with_target(&token, target, Opcode::CALL);
+        }
+        Err(self.error(&token, format!("unknown word `{}`", token.text)))
+    }
+
+    fn register_statement(&mut self, token: &Token, x: u8) -> Result<(), AsmError> {
+        let operator = self.next(token)?;
+        let source = self.next(&operator)?;
+        let y = self.register(&source);
+
+        let opcode = match (operator.text.as_str(), y) {
+            (":=", Some(y)) => Opcode::LDR(x, y),
+            (":=", None) if source.is("random") => Opcode::RND(x, self.expect_byte(&source)?),
+            (":=", None) if source.is("key") => Opcode::LDK(x),
+            (":=", None) if source.is("delay") => Opcode::LDDT(x),
+            (":=", None) => Opcode::LD(x, self.byte(&source)?),
+            ("+=", Some(y)) => Opcode::ADDR(x, y),
+            ("+=", None) => Opcode::ADD(x, self.byte(&source)?),
+            ("-=", Some(y)) => Opcode::SUBR(x, y),
+            ("-=", None) => Opcode::ADD(x, (self.byte(&source)? as i8).wrapping_neg() as u8),
+            ("=-", Some(y)) => Opcode::SUBN(x, y),
+            ("|=", Some(y)) => Opcode::OR(x, y),
+            ("&=", Some(y)) => Opcode::AND(x, y),
+            ("^=", Some(y)) => Opcode::XOR(x, y),
+            (">>=", Some(y)) => Opcode::SHR(x, y),
+            ("<<=", Some(y)) => Opcode::SHL(x, y),
+            _ => {
+                let message = format!(
+                    "can't use `{} {}` on a register",
+                    operator.text, source.text
+                );
+                return Err(self.error(&operator, message));
+            }
+        };
+        self.emit(token, opcode)
+    }
+
+    fn byte(&self, token: &Token) -> Result<u8, AsmError> {
+        let value = self.value(token)?;
+        Ok(self.check_range(token, value, -128, 255, "byte")? as u8)
+    }
+
+    fn i_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let operator = self.next(token)?;
+        match operator.text.as_str() {
+            "+=" => {
+                let x = self.expect_register(&operator)?;
+                self.emit(token, Opcode::ADDI(x))
+            }
+            ":=" => {
+                let source = self.next(&operator)?;
+                match source.text.as_str() {
+                    "hex" => {
+                        let x = self.expect_register(&source)?;
+                        self.emit(token, Opcode::LDF(x))
+                    }
+                    "bighex" => {
+                        let x = self.expect_register(&source)?;
+                        self.emit(token, Opcode::LDHF(x))
+                    }
+                    "long" => {
+                        let (target_token, target) = self.expect_target(&source)?;
+                        self.emit(token, Opcode::LDIL)?;
+                        let address = self.here();
+                        let value = match target {
+                            Target::Known(value) => {
+                                self.check_range(&target_token, value, 0, 0xFFFF, "address")?
+                            }
+                            Target::Forward(name) => {
+                                self.fixups.push(Fixup {
+                                    address,
+                                    kind: FixupKind::Long,
+                                    token: name,
+                                });
+                                0
+                            }
+                        };
+                        self.emit_byte(token, (value >> 8) as u8)?;
+                        self.emit_byte(token, value as u8)
+                    }
+                    _ => {
+                        self.tokens.push_front(source);
+                        let (target_token, target) = self.expect_target(&operator)?;
+                        self.emit_with_target(&target_token, target, Opcode::LDI)
+                    }
+                }
+            }
+            _ => Err(self.error(&operator, format!("can't use `{}` on i", operator.text))),
+        }
+    }
+
+    fn condition(&mut self, token: &Token) -> Result<Condition, AsmError> {
+        let x = self.expect_register(token)?;
+        let operator = self.next(token)?;
+
+        match operator.text.as_str() {
+            "key" => return Ok(Condition::Skip(Opcode::SKP(x), Opcode::SKNP(x))),
+            "-key" => return Ok(Condition::Skip(Opcode::SKNP(x), Opcode::SKP(x))),
+            _ => (),
+        }
+
+        let operand = self.next(&operator)?;
+        let y = self.register(&operand);
+        let condition = match (operator.text.as_str(), y) {
+            ("==", Some(y)) => Condition::Skip(Opcode::SER(x, y), Opcode::SNER(x, y)),
+            ("==", None) => {
+                let kk = self.byte(&operand)?;
+                Condition::Skip(Opcode::SE(x, kk), Opcode::SNE(x, kk))
+            }
+            ("!=", Some(y)) => Condition::Skip(Opcode::SNER(x, y), Opcode::SER(x, y)),
+            ("!=", None) => {
+                let kk = self.byte(&operand)?;
+                Condition::Skip(Opcode::SNE(x, kk), Opcode::SE(x, kk))
+            }
+            (">=", _) | ("<", _) | ("<=", _) | (">", _) => {
+                // vf ends up 1 when no borrow happened: `>=` and `<` compute
+                // x >= operand, `<=` and `>` compute operand >= x.
+                let x_first = matches!(operator.text.as_str(), ">=" | "<");
+                let setup = match (x_first, y) {
+                    (true, Some(y)) => [Opcode::LDR(0xF, x), Opcode::SUBR(0xF, y)],
+                    (true, None) => [Opcode::LD(0xF, self.byte(&operand)?), Opcode::SUBN(0xF, x)],
+                    (false, Some(y)) => [Opcode::LDR(0xF, y), Opcode::SUBR(0xF, x)],
+                    (false, None) => [Opcode::LD(0xF, self.byte(&operand)?), Opcode::SUBR(0xF, x)],
+                };
+                let vf_when_true = matches!(operator.text.as_str(), ">=" | "<=") as u8;
+                Condition::Compare {
+                    setup,
+                    vf_when_true,
+                }
+            }
+            _ => {
+                let message = format!("unknown comparison `{}`", operator.text);
+                return Err(self.error(&operator, message));
+            }
+        };
+        Ok(condition)
+    }
+
+    /// Emits the skip for `condition`, skipping the next instruction when
+    /// the condition is `when`.
+    fn emit_skip(
+        &mut self,
+        token: &Token,
+        condition: &Condition,
+        when: bool,
+    ) -> Result<(), AsmError> {
+        match condition {
+            Condition::Skip(if_true, if_false) => {
+                self.emit(token, if when { *if_true } else { *if_false })
+            }
+            Condition::Compare {
+                setup,
+                vf_when_true,
+            } => {
+                for opcode in setup.iter() {
+                    self.emit(token, *opcode)?;
+                }
+                let value = if when {
+                    *vf_when_true
+                } else {
+                    1 - vf_when_true
+                };
+                self.emit(token, Opcode::SE(0xF, value))
+            }
+        }
+    }
+
+    /// Collects the tokens up to the matching `}`, the `{` already read.
+    fn braced(&mut self, token: &Token) -> Result<Vec<Token>, AsmError> {
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let next = self
+                .tokens
+                .pop_front()
+                .ok_or_else(|| self.error(token, "missing `}`"))?;
+            match next.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(next);
+        }
+    }
+
+    fn expand(&mut self, token: &Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(self.error(token, "too many macro expansions"));
+        }
+
+        let parameters = self.macros[&token.text].parameters.clone();
+        let mut arguments = HashMap::new();
+        for parameter in parameters {
+            let argument = self.next(token)?;
+            arguments.insert(parameter, argument.text);
+        }
+
+        let body = &self.macros[&token.text].body;
+        let expanded: Vec<Token> = body
+            .iter()
+            .map(|body_token| Token {
+                text: arguments
+                    .get(&body_token.text)
+                    .cloned()
+                    .unwrap_or_else(|| body_token.text.clone()),
+                line: token.line,
+                column: token.column,
+            })
+            .collect();
+        for expanded_token in expanded.into_iter().rev() {
+            self.tokens.push_front(expanded_token);
+        }
+        Ok(())
+    }
+
+    /// Evaluates a `:calc` expression. Like Octo, operators have no
+    /// precedence and group from the right: `2 * 3 + 1` is 8.
+    fn calculate(&self, token: &Token, body: &[Token]) -> Result<i64, AsmError> {
+        let mut position = 0;
+        let value = self.calc_expression(token, body, &mut position)?;
+        if let Some(extra) = body.get(position) {
+            return Err(self.error(extra, format!("unexpected `{}`", extra.text)));
+        }
+        Ok(value)
+    }
+
+    fn calc_expression(
+        &self,
+        token: &Token,
+        body: &[Token],
+        position: &mut usize,
+    ) -> Result<i64, AsmError> {
+        let left = self.calc_term(token, body, position)?;
+        let operator = match body.get(*position) {
+            Some(operator) if operator.text != ")" => operator,
+            _ => return Ok(left),
+        };
+        *position += 1;
+        let right = self.calc_expression(token, body, position)?;
+
+        let value = match operator.text.as_str() {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => return Err(self.error(operator, "division by zero")),
+            "/" => left / right,
+            "%" => left % right,
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<<" => left.checked_shl(right as u32).unwrap_or(0),
+            ">>" => left.checked_shr(right as u32).unwrap_or(0),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64,
+            ">" => (left > right) as i64,
+            "<=" => (left <= right) as i64,
+            ">=" => (left >= right) as i64,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            _ => return Err(self.error(operator, format!("unknown operator `{}`", operator.text))),
+        };
+        Ok(value)
+    }
+
+    fn calc_term(
+        &self,
+        token: &Token,
+        body: &[Token],
+        position: &mut usize,
+    ) -> Result<i64, AsmError> {
+        let term = body
+            .get(*position)
+            .ok_or_else(|| self.error(token, "expected a value"))?;
+        *position += 1;
+
+        match term.text.as_str() {
+            "(" => {
+                let value = self.calc_expression(token, body, position)?;
+                match body.get(*position) {
+                    Some(close) if close.is(")") => *position += 1,
+                    _ => return Err(self.error(term, "missing `)`")),
+                }
+                Ok(value)
+            }
+            "-" => Ok(self.calc_term(token, body, position)?.wrapping_neg()),
+            "~" => Ok(!self.calc_term(token, body, position)?),
+            "!" => Ok((self.calc_term(token, body, position)? == 0) as i64),
+            "@" => {
+                let address = self.calc_term(token, body, position)?;
+                let origin = self.origin.unwrap_or(DEFAULT_ORIGIN) as i64;
+                let byte = usize::try_from(address - origin)
+                    .ok()
+                    .and_then(|offset| self.rom.get(offset))
+                    .ok_or_else(|| {
+                        self.error(term, format!("nothing assembled at {:#x}", address))
+                    })?;
+                Ok(*byte as i64)
+            }
+            "HERE" => Ok(self.here as i64),
+            _ => self
+                .value(term)
+                .map_err(|_| self.error(term, format!("unknown value `{}`", term.text))),
+        }
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+}
+
+/// Compiles Octo source into a program. `file` names the source in errors.
+///
+/// When the source defines `main` anywhere but at the start of the program,
+/// a `jump main` is placed at 0x200 the way Octo does it.
+pub fn compile_octo(source: &str, file: &str) -> Result<Program, AsmError> {
+    let mut compiler = Compiler::new(source, file);
+    compiler.compile()?;
+
+    let origin = compiler.origin.unwrap_or(DEFAULT_ORIGIN);
+    if let Some(&main) = compiler.labels.get("main") {
+        if main != origin {
+            let eof = Token {
+                text: String::new(),
+                line: 1,
+                column: 1,
+            };
+            compiler = Compiler::new(source, file);
+            compiler.emit(&eof, Opcode::JP(0))?;
+            compiler.compile()?;
+            let main = compiler.labels["main"];
+            compiler.check_range(&eof, main as i64, 0, 0xFFF, "address of main")?;
+            let start = compiler.origin.unwrap();
+            compiler.patch_address(start, main);
+        }
+    }
+
+    Ok(Program {
+        origin: compiler.origin.unwrap_or(DEFAULT_ORIGIN),
+        rom: compiler.rom,
+        symbols: compiler.labels.into_iter().collect(),
+        breakpoints: compiler.breakpoints,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::disasm::{disassemble, Syntax};
+    use crate::chip8::rng::Rng;
+
+    fn rom(source: &str) -> Vec<u8> {
+        match compile_octo(source, "test.8o") {
+            Ok(program) => program.rom,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn words(source: &str) -> Vec<u16> {
+        rom(source)
+            .chunks(2)
+            .map(|pair| (pair[0] as u16) << 8 | pair[1] as u16)
+            .collect()
+    }
+
+    fn error(source: &str) -> String {
+        compile_octo(source, "test.8o").unwrap_err().to_string()
+    }
+
+    #[test]
+    fn compiles_statements() {
+        let source = "
+            clear  v1 := 0x20  v2 := v1  va += 5  va += vb  va -= vb  va -= 1
+            va =- vb  va |= vb  va &= vb  va ^= vb  va >>= vb  va <<= vb
+            v3 := random 0x0F  v4 := key  v5 := delay  delay := v6  buzzer := v7
+            i := 0x123  i += v8  i := hex v9  i := bighex va  i := long 0xABCD
+            bcd vb  save vc  load vd  save v1 - v3  load v3 - v1  saveflags ve  loadflags vf
+            sprite v1 v2 5  hires  lores  scroll-down 3  scroll-up 2  scroll-left  scroll-right
+            plane 3  audio  pitch := v2  exit  native 0x123  jump0 0x300  return  ;
+        ";
+
+        assert_eq!(
+            vec![
+                0x00E0, 0x6120, 0x8210, 0x7A05, 0x8AB4, 0x8AB5, 0x7AFF, 0x8AB7, 0x8AB1, 0x8AB2,
+                0x8AB3, 0x8AB6, 0x8ABE, 0xC30F, 0xF40A, 0xF507, 0xF615, 0xF718, 0xA123, 0xF81E,
+                0xF929, 0xFA30, 0xF000, 0xABCD, 0xFB33, 0xFC55, 0xFD65, 0x5132, 0x5313, 0xFE75,
+                0xFF85, 0xD125, 0x00FF, 0x00FE, 0x00C3, 0x00D2, 0x00FC, 0x00FB, 0xF301, 0xF002,
+                0xF23A, 0x00FD, 0x0123, 0xB300, 0x00EE, 0x00EE,
+            ],
+            words(source)
+        );
+    }
+
+    #[test]
+    fn labels_calls_and_jumps_resolve_forwards() {
+        let program = compile_octo(
+            ": main\n  draw  jump main\n: draw  i := ball  sprite v0 v0 1  return\n: ball  0x80",
+            "test.8o",
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![0x22, 0x04, 0x12, 0x00, 0xA2, 0x0A, 0xD0, 0x01, 0x00, 0xEE, 0x80],
+            program.rom
+        );
+        assert_eq!(Some(&0x204), program.symbols.get("draw"));
+        assert_eq!(Some(&0x20A), program.symbols.get("ball"));
+    }
+
+    #[test]
+    fn main_after_other_code_gets_a_jump() {
+        let program = compile_octo(": helper return\n: main helper", "test.8o").unwrap();
+
+        assert_eq!(vec![0x12, 0x04, 0x00, 0xEE, 0x22, 0x02], program.rom);
+        assert_eq!(Some(&0x204), program.symbols.get("main"));
+    }
+
+    #[test]
+    fn conditions_compile_to_skips() {
+        assert_eq!(
+            vec![0x4112, 0x00E0, 0x3112, 0x00E0, 0x9120, 0x00E0, 0x5120, 0x00E0, 0xE1A1, 0x00E0],
+            words(
+                "if v1 == 0x12 then clear  if v1 != 0x12 then clear  if v1 == v2 then clear
+                 if v1 != v2 then clear  if v1 key then clear"
+            )
+        );
+    }
+
+    #[test]
+    fn comparisons_go_through_vf() {
+        assert_eq!(
+            vec![0x8F10, 0x8F25, 0x3F01, 0x00E0, 0x6F05, 0x8F17, 0x3F00, 0x00E0],
+            words("if v1 < v2 then clear  if v1 >= 5 then clear")
+        );
+        assert_eq!(
+            vec![0x8F20, 0x8F15, 0x3F00, 0x00E0, 0x6F05, 0x8F15, 0x3F01, 0x00E0],
+            words("if v1 <= v2 then clear  if v1 > 5 then clear")
+        );
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        assert_eq!(
+            vec![0x3100, 0x120A, 0x6201, 0x6302, 0x120C, 0x6203],
+            words("if v1 == 0 begin v2 := 1 v3 := 2 else v2 := 3 end")
+        );
+        assert_eq!(
+            vec![0xE1A1, 0x1206, 0x00E0],
+            words("if v1 -key begin clear end")
+        );
+    }
+
+    #[test]
+    fn loops_with_while() {
+        assert_eq!(
+            vec![0x7101, 0x4105, 0x120A, 0x00E0, 0x1200, 0x00EE],
+            words("loop v1 += 1 while v1 != 5 clear again return")
+        );
+    }
+
+    #[test]
+    fn constants_aliases_and_calc() {
+        assert_eq!(
+            vec![0x6A03, 0x6A0F, 0x6A04, 0x6A08, 0xA20A],
+            words(
+                ":const SPEED 3  :alias ball-x va  ball-x := SPEED
+                 :calc BIG { SPEED * 3 + 2 }  ball-x := BIG
+                 :calc SMALL { ( SPEED * 3 ) % 5 }  ball-x := SMALL
+                 :calc SHIFTED { 1 << SPEED }  ball-x := SHIFTED
+                 :calc NEXT { HERE + 2 }  i := NEXT"
+            )
+        );
+    }
+
+    #[test]
+    fn macros_substitute_arguments() {
+        assert_eq!(
+            vec![0x6103, 0x7101, 0x6207, 0x7201],
+            words(":macro set-and-bump reg value { reg := value reg += 1 }  set-and-bump v1 3  set-and-bump v2 7")
+        );
+    }
+
+    #[test]
+    fn data_bytes_and_unpack() {
+        assert_eq!(
+            vec![0xF0, 0x90, 0xFF, 0x06, 0x60, 0xA2, 0x61, 0x08, 0x12, 0x34],
+            rom("0xF0 0x90 -1 :byte { 2 * 3 } :unpack 0xA data  : data  :byte 0x12 :byte 0x34")
+        );
+    }
+
+    #[test]
+    fn breakpoints_and_org() {
+        let program =
+            compile_octo(":org 0x600  clear  :breakpoint here  return", "test.8o").unwrap();
+
+        assert_eq!(0x600, program.origin);
+        assert_eq!(vec![0x00, 0xE0, 0x00, 0xEE], program.rom);
+        assert_eq!(Some(&"here".to_string()), program.breakpoints.get(&0x602));
+    }
+
+    #[test]
+    fn comments_are_ignored() {
+        assert_eq!(vec![0x00E0], words("# heading\nclear # comment return"));
+    }
+
+    #[test]
+    fn errors_point_at_the_source_line() {
+        assert_eq!(
+            "test.8o:2:9: unknown label `nowhere`",
+            error("clear\n   jump nowhere")
+        );
+        assert_eq!("test.8o:1:7: byte out of range: 256", error("v1 := 256"));
+        assert_eq!(
+            "test.8o:3:1: `end` without `if ... begin`",
+            error("clear\n\nend")
+        );
+        assert_eq!("test.8o:1:1: missing `again`", error("loop clear"));
+        assert_eq!("test.8o:1:7: `a` is already defined", error(": a : a"));
+        assert_eq!(
+            "test.8o:1:4: can't use `*= v2` on a register",
+            error("v1 *= v2")
+        );
+        assert_eq!(
+            "test.8o:1:11: expected `then` or `begin`, found `clear`",
+            error("if v1 key clear")
+        );
+        assert_eq!("test.8o:1:1: unknown word `:bogus`", error(":bogus"));
+        assert_eq!(
+            "test.8o:1:9: unexpected end of file after `sprite`",
+            error("v1 := 2 sprite")
+        );
+    }
+
+    #[test]
+    fn disassembled_roms_recompile_byte_for_byte() {
+        let tetris = include_bytes!("../../roms/tetris.rom");
+        let listing = disassemble(tetris, DEFAULT_ORIGIN, Syntax::Octo);
+
+        assert_eq!(&tetris[..], &rom(&listing)[..]);
+    }
+
+    #[test]
+    fn random_roms_recompile_byte_for_byte() {
+        let mut rng = Rng::new(8);
+
+        for size in 1..200 {
+            let bytes: Vec<u8> = (0..size).map(|_| rng.next_u8()).collect();
+            for &origin in &[0x200, 0x600] {
+                let listing = disassemble(&bytes, origin, Syntax::Octo);
+                let program = compile_octo(&listing, "listing.8o")
+                    .unwrap_or_else(|err| panic!("{}\n{}", err, listing));
+
+                assert_eq!(bytes, program.rom, "{}", listing);
+                assert_eq!(origin, program.origin);
+            }
+        }
+    }
+}
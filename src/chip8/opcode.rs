@@ -1,13 +1,25 @@
-#[derive(Debug)]
+use std::fmt;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
-    SYS,
+    SYS(u16),
     CLS,
     RET,
+    SCD(u8),
+    SCU(u8),
+    SCR,
+    SCL,
+    EXIT,
+    LOW,
+    HIGH,
     JP(u16),
     CALL(u16),
     SE(u8, u8),
     SNE(u8, u8),
     SER(u8, u8),
+    SAVE(u8, u8),
+    LOAD(u8, u8),
     LD(u8, u8),
     ADD(u8, u8),
     LDR(u8, u8),
@@ -16,9 +28,9 @@ pub enum Opcode {
     XOR(u8, u8),
     ADDR(u8, u8),
     SUBR(u8, u8),
-    SHR(u8),
+    SHR(u8, u8),
     SUBN(u8, u8),
-    SHL(u8),
+    SHL(u8, u8),
     SNER(u8, u8),
     LDI(u16),
     JPR(u16),
@@ -35,22 +47,178 @@ pub enum Opcode {
     LDB(u8),
     LDIR(u8),
     LDRI(u8),
+    LDHF(u8),
+    STRPL(u8),
+    LDRPL(u8),
+    /// F000 NNNN. The address is the word following the instruction.
+    LDIL,
+    PLANE(u8),
+    AUDIO,
+    PITCH(u8),
+}
+
+/// Returned by `decode` when a word doesn't map to any known instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    UnknownOpcode(u16),
 }
 
-pub fn decode(opcode: u16) -> Opcode {
-    match opcode & 0xF000 {
-        0x0000 => match opcode & 0x00FF {
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode(opcode) => write!(f, "unknown opcode {:#06x}", opcode),
+        }
+    }
+}
+
+impl Opcode {
+    /// The name of the instruction, such as `DRW` for `DRW(1, 2, 5)`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Opcode::SYS(..) => "SYS",
+            Opcode::CLS => "CLS",
+            Opcode::RET => "RET",
+            Opcode::SCD(..) => "SCD",
+            Opcode::SCU(..) => "SCU",
+            Opcode::SCR => "SCR",
+            Opcode::SCL => "SCL",
+            Opcode::EXIT => "EXIT",
+            Opcode::LOW => "LOW",
+            Opcode::HIGH => "HIGH",
+            Opcode::JP(..) => "JP",
+            Opcode::CALL(..) => "CALL",
+            Opcode::SE(..) => "SE",
+            Opcode::SNE(..) => "SNE",
+            Opcode::SER(..) => "SER",
+            Opcode::SAVE(..) => "SAVE",
+            Opcode::LOAD(..) => "LOAD",
+            Opcode::LD(..) => "LD",
+            Opcode::ADD(..) => "ADD",
+            Opcode::LDR(..) => "LDR",
+            Opcode::OR(..) => "OR",
+            Opcode::AND(..) => "AND",
+            Opcode::XOR(..) => "XOR",
+            Opcode::ADDR(..) => "ADDR",
+            Opcode::SUBR(..) => "SUBR",
+            Opcode::SHR(..) => "SHR",
+            Opcode::SUBN(..) => "SUBN",
+            Opcode::SHL(..) => "SHL",
+            Opcode::SNER(..) => "SNER",
+            Opcode::LDI(..) => "LDI",
+            Opcode::JPR(..) => "JPR",
+            Opcode::RND(..) => "RND",
+            Opcode::DRW(..) => "DRW",
+            Opcode::SKP(..) => "SKP",
+            Opcode::SKNP(..) => "SKNP",
+            Opcode::LDDT(..) => "LDDT",
+            Opcode::LDK(..) => "LDK",
+            Opcode::DTLD(..) => "DTLD",
+            Opcode::STLD(..) => "STLD",
+            Opcode::ADDI(..) => "ADDI",
+            Opcode::LDF(..) => "LDF",
+            Opcode::LDB(..) => "LDB",
+            Opcode::LDIR(..) => "LDIR",
+            Opcode::LDRI(..) => "LDRI",
+            Opcode::LDHF(..) => "LDHF",
+            Opcode::STRPL(..) => "STRPL",
+            Opcode::LDRPL(..) => "LDRPL",
+            Opcode::LDIL => "LDIL",
+            Opcode::PLANE(..) => "PLANE",
+            Opcode::AUDIO => "AUDIO",
+            Opcode::PITCH(..) => "PITCH",
+        }
+    }
+
+    /// Encodes the instruction back into its word, the inverse of `decode`.
+    /// For `LDIL` this is only the F000 prefix; the address follows it.
+    pub fn encode(self) -> u16 {
+        let xy = |x: u8, y: u8| (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let xkk = |x: u8, kk: u8| (x as u16 & 0xF) << 8 | kk as u16;
+        let x = |x: u8| (x as u16 & 0xF) << 8;
+        let n = |n: u8| n as u16 & 0xF;
+
+        match self {
+            Opcode::SYS(address) => address & 0xFFF,
+            Opcode::CLS => 0x00E0,
+            Opcode::RET => 0x00EE,
+            Opcode::SCD(rows) => 0x00C0 | n(rows),
+            Opcode::SCU(rows) => 0x00D0 | n(rows),
+            Opcode::SCR => 0x00FB,
+            Opcode::SCL => 0x00FC,
+            Opcode::EXIT => 0x00FD,
+            Opcode::LOW => 0x00FE,
+            Opcode::HIGH => 0x00FF,
+            Opcode::JP(address) => 0x1000 | address & 0xFFF,
+            Opcode::CALL(address) => 0x2000 | address & 0xFFF,
+            Opcode::SE(vx, kk) => 0x3000 | xkk(vx, kk),
+            Opcode::SNE(vx, kk) => 0x4000 | xkk(vx, kk),
+            Opcode::SER(vx, vy) => 0x5000 | xy(vx, vy),
+            Opcode::SAVE(vx, vy) => 0x5002 | xy(vx, vy),
+            Opcode::LOAD(vx, vy) => 0x5003 | xy(vx, vy),
+            Opcode::LD(vx, kk) => 0x6000 | xkk(vx, kk),
+            Opcode::ADD(vx, kk) => 0x7000 | xkk(vx, kk),
+            Opcode::LDR(vx, vy) => 0x8000 | xy(vx, vy),
+            Opcode::OR(vx, vy) => 0x8001 | xy(vx, vy),
+            Opcode::AND(vx, vy) => 0x8002 | xy(vx, vy),
+            Opcode::XOR(vx, vy) => 0x8003 | xy(vx, vy),
+            Opcode::ADDR(vx, vy) => 0x8004 | xy(vx, vy),
+            Opcode::SUBR(vx, vy) => 0x8005 | xy(vx, vy),
+            Opcode::SHR(vx, vy) => 0x8006 | xy(vx, vy),
+            Opcode::SUBN(vx, vy) => 0x8007 | xy(vx, vy),
+            Opcode::SHL(vx, vy) => 0x800E | xy(vx, vy),
+            Opcode::SNER(vx, vy) => 0x9000 | xy(vx, vy),
+            Opcode::LDI(address) => 0xA000 | address & 0xFFF,
+            Opcode::JPR(address) => 0xB000 | address & 0xFFF,
+            Opcode::RND(vx, kk) => 0xC000 | xkk(vx, kk),
+            Opcode::DRW(vx, vy, rows) => 0xD000 | xy(vx, vy) | n(rows),
+            Opcode::SKP(vx) => 0xE09E | x(vx),
+            Opcode::SKNP(vx) => 0xE0A1 | x(vx),
+            Opcode::LDDT(vx) => 0xF007 | x(vx),
+            Opcode::LDK(vx) => 0xF00A | x(vx),
+            Opcode::DTLD(vx) => 0xF015 | x(vx),
+            Opcode::STLD(vx) => 0xF018 | x(vx),
+            Opcode::ADDI(vx) => 0xF01E | x(vx),
+            Opcode::LDF(vx) => 0xF029 | x(vx),
+            Opcode::LDB(vx) => 0xF033 | x(vx),
+            Opcode::LDIR(vx) => 0xF055 | x(vx),
+            Opcode::LDRI(vx) => 0xF065 | x(vx),
+            Opcode::LDHF(vx) => 0xF030 | x(vx),
+            Opcode::STRPL(vx) => 0xF075 | x(vx),
+            Opcode::LDRPL(vx) => 0xF085 | x(vx),
+            Opcode::LDIL => 0xF000,
+            Opcode::PLANE(planes) => 0xF001 | x(planes),
+            Opcode::AUDIO => 0xF002,
+            Opcode::PITCH(vx) => 0xF03A | x(vx),
+        }
+    }
+}
+
+pub fn decode(opcode: u16) -> Result<Opcode, DecodeError> {
+    let op = match opcode & 0xF000 {
+        0x0000 => match opcode {
             0x00E0 => Opcode::CLS,
             0x00EE => Opcode::RET,
-            _ => Opcode::SYS,
+            0x00C0..=0x00CF => Opcode::SCD(opcode as u8 & 0xF),
+            0x00D0..=0x00DF => Opcode::SCU(opcode as u8 & 0xF),
+            0x00FB => Opcode::SCR,
+            0x00FC => Opcode::SCL,
+            0x00FD => Opcode::EXIT,
+            0x00FE => Opcode::LOW,
+            0x00FF => Opcode::HIGH,
+            _ => Opcode::SYS(opcode & 0x0FFF),
         },
         0x1000 => Opcode::JP(opcode & 0x0FFF),
         0x2000 => Opcode::CALL(opcode & 0x0FFF),
-        0x3000 => Opcode::SE(((opcode & 0xF00) >> 8) as u8, opcode as u8 & 0xFF),
-        0x4000 => Opcode::SNE(((opcode & 0xF00) >> 8) as u8, opcode as u8 & 0xFF),
-        0x5000 => Opcode::SER(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
-        0x6000 => Opcode::LD(((opcode & 0xF00) >> 8) as u8, opcode as u8 & 0xFF),
-        0x7000 => Opcode::ADD(((opcode & 0xF00) >> 8) as u8, opcode as u8 & 0xFF),
+        0x3000 => Opcode::SE(((opcode & 0xF00) >> 8) as u8, opcode as u8),
+        0x4000 => Opcode::SNE(((opcode & 0xF00) >> 8) as u8, opcode as u8),
+        0x5000 => match opcode & 0x000F {
+            0x0000 => Opcode::SER(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
+            0x0002 => Opcode::SAVE(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
+            0x0003 => Opcode::LOAD(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
+            _ => return Err(DecodeError::UnknownOpcode(opcode)),
+        },
+        0x6000 => Opcode::LD(((opcode & 0xF00) >> 8) as u8, opcode as u8),
+        0x7000 => Opcode::ADD(((opcode & 0xF00) >> 8) as u8, opcode as u8),
         0x8000 => match opcode & 0x000F {
             0x0000 => Opcode::LDR(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
             0x0001 => Opcode::OR(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
@@ -58,15 +226,18 @@ pub fn decode(opcode: u16) -> Opcode {
             0x0003 => Opcode::XOR(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
             0x0004 => Opcode::ADDR(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
             0x0005 => Opcode::SUBR(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
-            0x0006 => Opcode::SHR(((opcode & 0xF00) >> 8) as u8),
+            0x0006 => Opcode::SHR(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
             0x0007 => Opcode::SUBN(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
-            0x000E => Opcode::SHL(((opcode & 0xF00) >> 8) as u8),
-            _ => panic!("unkown opcode"),
+            0x000E => Opcode::SHL(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
+            _ => return Err(DecodeError::UnknownOpcode(opcode)),
+        },
+        0x9000 => match opcode & 0x000F {
+            0x0000 => Opcode::SNER(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
+            _ => return Err(DecodeError::UnknownOpcode(opcode)),
         },
-        0x9000 => Opcode::SNER(((opcode & 0xF00) >> 8) as u8, ((opcode & 0xF0) >> 4) as u8),
         0xA000 => Opcode::LDI(opcode & 0xFFF),
         0xB000 => Opcode::JPR(opcode & 0xFFF),
-        0xC000 => Opcode::RND(((opcode & 0xF00) >> 8) as u8, opcode as u8 & 0xFF),
+        0xC000 => Opcode::RND(((opcode & 0xF00) >> 8) as u8, opcode as u8),
         0xD000 => Opcode::DRW(
             ((opcode & 0xF00) >> 8) as u8,
             ((opcode & 0xF0) >> 4) as u8,
@@ -75,9 +246,13 @@ pub fn decode(opcode: u16) -> Opcode {
         0xE000 => match opcode & 0x00FF {
             0x009E => Opcode::SKP(((opcode & 0xF00) >> 8) as u8),
             0x00A1 => Opcode::SKNP(((opcode & 0xF00) >> 8) as u8),
-            _ => panic!("unkown opcode"),
+            _ => return Err(DecodeError::UnknownOpcode(opcode)),
         },
         0xF000 => match opcode & 0x00FF {
+            0x0000 if opcode == 0xF000 => Opcode::LDIL,
+            0x0001 => Opcode::PLANE(((opcode & 0xF00) >> 8) as u8),
+            0x0002 if opcode == 0xF002 => Opcode::AUDIO,
+            0x003A => Opcode::PITCH(((opcode & 0xF00) >> 8) as u8),
             0x0007 => Opcode::LDDT(((opcode & 0xF00) >> 8) as u8),
             0x000A => Opcode::LDK(((opcode & 0xF00) >> 8) as u8),
             0x0015 => Opcode::DTLD(((opcode & 0xF00) >> 8) as u8),
@@ -87,12 +262,133 @@ pub fn decode(opcode: u16) -> Opcode {
             0x0033 => Opcode::LDB(((opcode & 0xF00) >> 8) as u8),
             0x0055 => Opcode::LDIR(((opcode & 0xF00) >> 8) as u8),
             0x0065 => Opcode::LDRI(((opcode & 0xF00) >> 8) as u8),
-            _ => {
-                let s = format!("unkown opcode {:x}", opcode);
-                web_sys::console::log_1(&s.into());
-                panic!(format!("unkown opcode: {}", opcode))
-            }
+            0x0030 => Opcode::LDHF(((opcode & 0xF00) >> 8) as u8),
+            0x0075 => Opcode::STRPL(((opcode & 0xF00) >> 8) as u8),
+            0x0085 => Opcode::LDRPL(((opcode & 0xF00) >> 8) as u8),
+            _ => return Err(DecodeError::UnknownOpcode(opcode)),
         },
-        _ => panic!("unknown opcode"),
+        _ => return Err(DecodeError::UnknownOpcode(opcode)),
+    };
+
+    Ok(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_known_opcode() {
+        assert_eq!(Ok(Opcode::DRW(1, 2, 3)), decode(0xD123));
+    }
+
+    #[test]
+    fn decode_super_chip_opcodes() {
+        assert_eq!(Ok(Opcode::SCD(0xA)), decode(0x00CA));
+        assert_eq!(Ok(Opcode::SCR), decode(0x00FB));
+        assert_eq!(Ok(Opcode::SCL), decode(0x00FC));
+        assert_eq!(Ok(Opcode::EXIT), decode(0x00FD));
+        assert_eq!(Ok(Opcode::LOW), decode(0x00FE));
+        assert_eq!(Ok(Opcode::HIGH), decode(0x00FF));
+        assert_eq!(Ok(Opcode::LDHF(3)), decode(0xF330));
+        assert_eq!(Ok(Opcode::STRPL(7)), decode(0xF775));
+        assert_eq!(Ok(Opcode::LDRPL(7)), decode(0xF785));
+    }
+
+    #[test]
+    fn decode_xo_chip_opcodes() {
+        assert_eq!(Ok(Opcode::SCU(4)), decode(0x00D4));
+        assert_eq!(Ok(Opcode::SAVE(1, 5)), decode(0x5152));
+        assert_eq!(Ok(Opcode::LOAD(5, 1)), decode(0x5513));
+        assert_eq!(Ok(Opcode::LDIL), decode(0xF000));
+        assert_eq!(Ok(Opcode::PLANE(3)), decode(0xF301));
+        assert_eq!(Ok(Opcode::AUDIO), decode(0xF002));
+        assert_eq!(Ok(Opcode::PITCH(9)), decode(0xF93A));
+        assert_eq!(Err(DecodeError::UnknownOpcode(0xF100)), decode(0xF100));
+        assert_eq!(Err(DecodeError::UnknownOpcode(0x5121)), decode(0x5121));
+    }
+
+    #[test]
+    fn decode_only_treats_00xx_as_screen_instructions() {
+        assert_eq!(Ok(Opcode::SYS(0x1E0)), decode(0x01E0));
+        assert_eq!(Ok(Opcode::SYS(0x2FF)), decode(0x02FF));
+    }
+
+    #[test]
+    fn encode_inverts_decode_for_every_word() {
+        for word in 0..=u16::MAX {
+            if let Ok(opcode) = decode(word) {
+                assert_eq!(word, opcode.encode(), "{:?}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_round_trips_every_variant() {
+        let opcodes = [
+            Opcode::SYS(0x123),
+            Opcode::CLS,
+            Opcode::RET,
+            Opcode::SCD(0xA),
+            Opcode::SCU(0x4),
+            Opcode::SCR,
+            Opcode::SCL,
+            Opcode::EXIT,
+            Opcode::LOW,
+            Opcode::HIGH,
+            Opcode::JP(0x345),
+            Opcode::CALL(0x456),
+            Opcode::SE(0x1, 0x23),
+            Opcode::SNE(0x2, 0x34),
+            Opcode::SER(0x3, 0x4),
+            Opcode::SAVE(0x1, 0x5),
+            Opcode::LOAD(0x5, 0x1),
+            Opcode::LD(0x4, 0x56),
+            Opcode::ADD(0x5, 0x67),
+            Opcode::LDR(0x6, 0x7),
+            Opcode::OR(0x7, 0x8),
+            Opcode::AND(0x8, 0x9),
+            Opcode::XOR(0x9, 0xA),
+            Opcode::ADDR(0xA, 0xB),
+            Opcode::SUBR(0xB, 0xC),
+            Opcode::SHR(0xC, 0xD),
+            Opcode::SUBN(0xD, 0xE),
+            Opcode::SHL(0xE, 0xF),
+            Opcode::SNER(0xF, 0x0),
+            Opcode::LDI(0x567),
+            Opcode::JPR(0x678),
+            Opcode::RND(0x1, 0xFF),
+            Opcode::DRW(0x1, 0x2, 0x3),
+            Opcode::SKP(0x2),
+            Opcode::SKNP(0x3),
+            Opcode::LDDT(0x4),
+            Opcode::LDK(0x5),
+            Opcode::DTLD(0x6),
+            Opcode::STLD(0x7),
+            Opcode::ADDI(0x8),
+            Opcode::LDF(0x9),
+            Opcode::LDB(0xA),
+            Opcode::LDIR(0xB),
+            Opcode::LDRI(0xC),
+            Opcode::LDHF(0xD),
+            Opcode::STRPL(0xE),
+            Opcode::LDRPL(0xF),
+            Opcode::LDIL,
+            Opcode::PLANE(0x3),
+            Opcode::AUDIO,
+            Opcode::PITCH(0x9),
+        ];
+
+        for &opcode in opcodes.iter() {
+            assert_eq!(Ok(opcode), decode(opcode.encode()));
+        }
+    }
+
+    #[test]
+    fn decode_unknown_opcode_returns_error() {
+        assert_eq!(Err(DecodeError::UnknownOpcode(0x8008)), decode(0x8008));
+        assert_eq!(Err(DecodeError::UnknownOpcode(0xE1FF)), decode(0xE1FF));
+        assert_eq!(Err(DecodeError::UnknownOpcode(0x9121)), decode(0x9121));
+        assert_eq!(Err(DecodeError::UnknownOpcode(0xF0FF)), decode(0xF0FF));
     }
 }
@@ -0,0 +1,80 @@
+use crate::chip8::cpu::LoadError;
+use crate::chip8::quirks::Quirks;
+
+/// The `Platform` type. Selects which instruction set extensions the cpu
+/// understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    /// The quirks programs written for this platform expect.
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::cosmac_vip(),
+            Platform::SuperChip => Quirks::super_chip(),
+            Platform::XoChip => Quirks::xo_chip(),
+        }
+    }
+
+    /// The size of the addressable memory in bytes.
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => 0x1000,
+            Platform::XoChip => 0x10000,
+        }
+    }
+
+    /// Checks that `rom` fits in memory when loaded at `address`.
+    pub fn check_rom(self, rom: &[u8], address: u16) -> Result<(), LoadError> {
+        let start = address as usize;
+        let memory_size = self.memory_size();
+        if start >= memory_size {
+            return Err(LoadError::AddressOutOfRange(address));
+        }
+        if rom.len() > memory_size - start {
+            return Err(LoadError::TooLarge {
+                len: rom.len(),
+                max: memory_size - start,
+            });
+        }
+        Ok(())
+    }
+
+    /// The instructions per 60 Hz frame programs for this platform usually
+    /// expect.
+    pub fn tick_rate(self) -> u32 {
+        match self {
+            Platform::Chip8 => 15,
+            Platform::SuperChip => 30,
+            Platform::XoChip => 1000,
+        }
+    }
+
+    /// Whether the SUPER-CHIP 1.1 instructions are available.
+    pub fn has_super_chip(self) -> bool {
+        match self {
+            Platform::Chip8 => false,
+            Platform::SuperChip | Platform::XoChip => true,
+        }
+    }
+
+    /// Whether the XO-CHIP instructions are available.
+    pub fn has_xo_chip(self) -> bool {
+        self == Platform::XoChip
+    }
+
+    /// Looks up a platform by name: `chip8`, `schip` or `xochip`.
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name {
+            "chip8" => Some(Platform::Chip8),
+            "schip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use crate::chip8::disasm::{code_addresses, format_opcode, Syntax};
+use crate::chip8::opcode::{decode, Opcode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// How many addresses and loops the report lists.
+const REPORT_ROWS: usize = 10;
+
+/// Loops taking at least this share of all instructions are marked hot.
+const HOT_SHARE: f64 = 0.1;
+
+/// Running minimum, maximum and average of a series of samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    pub count: u64,
+    pub total: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Stats {
+    fn add(&mut self, sample: f64) {
+        if self.count == 0 || sample < self.min {
+            self.min = sample;
+        }
+        if self.count == 0 || sample > self.max {
+            self.max = sample;
+        }
+        self.count += 1;
+        self.total += sample;
+    }
+
+    pub fn average(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total / self.count as f64
+        }
+    }
+}
+
+/// A backward jump the program took repeatedly, and the code it repeats.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotLoop {
+    /// The address jumped back to.
+    pub start: u16,
+    /// The address of the jump.
+    pub end: u16,
+    pub iterations: u64,
+    /// Instructions executed between `start` and `end`.
+    pub instructions: u64,
+    /// What the loop polls, when it looks like a busy-wait: `delay timer`
+    /// or `keypad`.
+    pub busy_wait: Option<&'static str>,
+}
+
+/// The `Profiler` type. Counts how often each address and each kind of
+/// instruction executes, the sprites drawn per frame and how long frames
+/// take. Enable it with `Cpu::enable_profiler`.
+pub struct Profiler {
+    address_counts: Vec<u64>,
+    opcode_counts: BTreeMap<&'static str, u64>,
+    instructions: u64,
+    draws_this_frame: u64,
+    draws_per_frame: Stats,
+    frame_times: Stats,
+    /// Times each backward jump was taken, by jump and target address.
+    back_jumps: BTreeMap<(u16, u16), u64>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler {
+            address_counts: vec![0; 0x10000],
+            opcode_counts: BTreeMap::new(),
+            instructions: 0,
+            draws_this_frame: 0,
+            draws_per_frame: Stats::default(),
+            frame_times: Stats::default(),
+            back_jumps: BTreeMap::new(),
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// How often the instruction at `address` executed.
+    pub fn count(&self, address: u16) -> u64 {
+        self.address_counts[address as usize]
+    }
+
+    /// How often each kind of instruction executed, by `Opcode::name`.
+    pub fn opcode_counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.opcode_counts
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.draws_per_frame.count
+    }
+
+    /// DXYN instructions per frame.
+    pub fn draws_per_frame(&self) -> Stats {
+        self.draws_per_frame
+    }
+
+    /// Frame times in milliseconds, as passed to `record_frame_time`.
+    pub fn frame_times(&self) -> Stats {
+        self.frame_times
+    }
+
+    /// Records the instruction `opcode` at `pc` executing, after which the
+    /// program continued at `next_pc`.
+    pub(crate) fn record(&mut self, pc: u16, opcode: u16, next_pc: u16) {
+        self.instructions += 1;
+        self.address_counts[pc as usize] += 1;
+
+        let opcode = match decode(opcode) {
+            Ok(opcode) => opcode,
+            Err(_) => return,
+        };
+        *self.opcode_counts.entry(opcode.name()).or_insert(0) += 1;
+        match opcode {
+            Opcode::DRW(..) => self.draws_this_frame += 1,
+            // Returns go back to wherever the call was; that's no loop.
+            Opcode::RET | Opcode::CALL(_) => (),
+            _ if next_pc <= pc => *self.back_jumps.entry((pc, next_pc)).or_insert(0) += 1,
+            _ => (),
+        }
+    }
+
+    /// Call at the end of every 60 Hz frame.
+    pub(crate) fn end_frame(&mut self) {
+        self.draws_per_frame.add(self.draws_this_frame as f64);
+        self.draws_this_frame = 0;
+    }
+
+    /// Adds how long the host took to run a frame. The emulator has no
+    /// clock of its own, so whoever drives the frames measures them.
+    pub fn record_frame_time(&mut self, milliseconds: f64) {
+        self.frame_times.add(milliseconds);
+    }
+
+    /// The loops that took the most instructions, busiest first. `memory`
+    /// is used to tell busy-waits from other loops.
+    pub fn hot_loops(&self, memory: &[u8]) -> Vec<HotLoop> {
+        let mut loops: Vec<HotLoop> = self
+            .back_jumps
+            .iter()
+            .map(|(&(end, start), &iterations)| {
+                let addresses = start as usize..=end as usize;
+                let busy_wait = addresses
+                    .clone()
+                    .filter(|&address| self.address_counts[address] > 0)
+                    .filter_map(|address| {
+                        let word = memory.get(address..address + 2)?;
+                        decode((word[0] as u16) << 8 | word[1] as u16).ok()
+                    })
+                    .find_map(|opcode| match opcode {
+                        Opcode::LDDT(_) => Some("delay timer"),
+                        Opcode::LDK(_) | Opcode::SKP(_) | Opcode::SKNP(_) => Some("keypad"),
+                        _ => None,
+                    });
+                HotLoop {
+                    start,
+                    end,
+                    iterations,
+                    instructions: self.address_counts[addresses].iter().sum(),
+                    busy_wait,
+                }
+            })
+            .collect();
+        loops.sort_by_key(|hot_loop| std::cmp::Reverse(hot_loop.instructions));
+        loops
+    }
+
+    fn share(&self, count: u64) -> f64 {
+        if self.instructions == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.instructions as f64
+        }
+    }
+
+    /// A summary of the profile: totals, draws and frame times, the hottest
+    /// addresses, instructions by kind and the hot loops, with busy-waits
+    /// and loops taking a large share of the time marked.
+    pub fn report(&self, memory: &[u8]) -> String {
+        let mut report = String::new();
+
+        writeln!(
+            report,
+            "instructions: {} over {} frames",
+            self.instructions,
+            self.frames()
+        )
+        .unwrap();
+        writeln!(
+            report,
+            "draws per frame: average {:.1}, max {}",
+            self.draws_per_frame.average(),
+            self.draws_per_frame.max
+        )
+        .unwrap();
+        if self.frame_times.count > 0 {
+            writeln!(
+                report,
+                "frame time: average {:.2} ms, min {:.2} ms, max {:.2} ms",
+                self.frame_times.average(),
+                self.frame_times.min,
+                self.frame_times.max
+            )
+            .unwrap();
+        }
+
+        let mut addresses: Vec<(usize, u64)> = self
+            .address_counts
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        writeln!(report, "\nhottest addresses:").unwrap();
+        for &(address, count) in addresses.iter().take(REPORT_ROWS) {
+            let instruction = memory
+                .get(address..address + 4)
+                .or_else(|| memory.get(address..address + 2))
+                .and_then(|bytes| {
+                    let opcode = decode((bytes[0] as u16) << 8 | bytes[1] as u16).ok()?;
+                    let long = bytes
+                        .get(2..4)
+                        .map_or(0, |long| (long[0] as u16) << 8 | long[1] as u16);
+                    Some(format_opcode(opcode, long, Syntax::Cowgod))
+                })
+                .unwrap_or_default();
+            writeln!(
+                report,
+                "  0x{:03X}  {:>10}  {:5.1}%  {}",
+                address,
+                count,
+                self.share(count),
+                instruction
+            )
+            .unwrap();
+        }
+
+        let mut opcodes: Vec<(&str, u64)> = self
+            .opcode_counts
+            .iter()
+            .map(|(&name, &count)| (name, count))
+            .collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        writeln!(report, "\ninstructions by kind:").unwrap();
+        for (name, count) in opcodes {
+            writeln!(
+                report,
+                "  {:<6}  {:>10}  {:5.1}%",
+                name,
+                count,
+                self.share(count)
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nhot loops:").unwrap();
+        for hot_loop in self.hot_loops(memory).iter().take(REPORT_ROWS) {
+            let share = self.share(hot_loop.instructions);
+            let mut line = format!(
+                "  0x{:03X}..0x{:03X}  {:>10} iterations  {:>10} instructions  {:5.1}%",
+                hot_loop.start, hot_loop.end, hot_loop.iterations, hot_loop.instructions, share
+            );
+            if share >= HOT_SHARE * 100.0 {
+                line.push_str("  HOT");
+            }
+            if let Some(source) = hot_loop.busy_wait {
+                write!(line, "  busy-wait on the {}", source).unwrap();
+            }
+            writeln!(report, "{}", line).unwrap();
+        }
+
+        report
+    }
+
+    /// Every instruction recursive descent finds in `rom` plus every
+    /// address that executed, each with its execution count.
+    fn coverage(&self, rom: &[u8], origin: u16) -> Vec<(u16, u64)> {
+        let mut addresses: BTreeSet<u16> = code_addresses(rom, origin).into_iter().collect();
+        addresses.extend(
+            self.address_counts
+                .iter()
+                .enumerate()
+                .filter(|&(_, &count)| count > 0)
+                .map(|(address, _)| address as u16),
+        );
+        addresses
+            .into_iter()
+            .map(|address| (address, self.count(address)))
+            .collect()
+    }
+
+    /// A coverage map of `rom`, loaded at `origin`:
+    /// `{"origin":512,"size":246,"hit":2,"found":3,"instructions":[{"address":512,"count":1},...]}`.
+    /// Instructions that never ran have a count of 0.
+    pub fn coverage_json(&self, rom: &[u8], origin: u16) -> String {
+        let coverage = self.coverage(rom, origin);
+        let hit = coverage.iter().filter(|&&(_, count)| count > 0).count();
+
+        let mut json = format!(
+            "{{\"origin\":{},\"size\":{},\"hit\":{},\"found\":{},\"instructions\":[",
+            origin,
+            rom.len(),
+            hit,
+            coverage.len()
+        );
+        for (index, (address, count)) in coverage.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            write!(json, "\n  {{\"address\":{},\"count\":{}}}", address, count).unwrap();
+        }
+        json.push_str("\n]}\n");
+        json
+    }
+
+    /// The coverage map in the lcov tracefile format, with an address
+    /// standing in for each line of `source`.
+    pub fn coverage_lcov(&self, source: &str, rom: &[u8], origin: u16) -> String {
+        let coverage = self.coverage(rom, origin);
+        let hit = coverage.iter().filter(|&&(_, count)| count > 0).count();
+
+        let mut lcov = format!("TN:\nSF:{}\n", source);
+        for (address, count) in coverage.iter() {
+            writeln!(lcov, "DA:{},{}", address, count).unwrap();
+        }
+        writeln!(lcov, "LF:{}\nLH:{}\nend_of_record", coverage.len(), hit).unwrap();
+        lcov
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::cpu::Cpu;
+
+    /// 0x200: v0 := 10, delay := v0
+    /// 0x204: v1 := delay, if v1 != 0 then jump 0x204
+    /// 0x20A: sprite v0 v0 1, jump 0x20A
+    /// 0x20E: a call nothing makes
+    const PROGRAM: [u8; 16] = [
+        0x60, 0x0A, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0xD0, 0x01, 0x12, 0x0A, 0x00,
+        0xE0,
+    ];
+
+    fn profiled_cpu(program: &[u8]) -> Cpu {
+        let mut cpu = Cpu::default();
+        cpu.load_rom(program).unwrap();
+        cpu.enable_profiler();
+        cpu
+    }
+
+    #[test]
+    fn counts_addresses_and_opcodes() {
+        let mut cpu = profiled_cpu(&PROGRAM);
+        for _ in 0..5 {
+            cpu.step();
+        }
+        cpu.tick_timers();
+        for _ in 0..3 {
+            cpu.step();
+        }
+
+        let profiler = cpu.profiler().unwrap();
+        assert_eq!(8, profiler.instructions());
+        assert_eq!(1, profiler.count(0x200));
+        assert_eq!(2, profiler.count(0x204));
+        assert_eq!(2, profiler.count(0x206));
+        assert_eq!(Some(&2), profiler.opcode_counts().get("LDDT"));
+        assert_eq!(Some(&1), profiler.opcode_counts().get("DTLD"));
+        assert_eq!(1, profiler.frames());
+    }
+
+    #[test]
+    fn counts_draws_per_frame() {
+        // sprite v0 v0 1, jump 0x200
+        let mut cpu = profiled_cpu(&[0xD0, 0x01, 0x12, 0x00]);
+        cpu.quirks.display_wait = false;
+        for frame in 0..3 {
+            for _ in 0..(frame + 1) * 2 {
+                cpu.step();
+            }
+            cpu.tick_timers();
+        }
+
+        let draws = cpu.profiler().unwrap().draws_per_frame();
+        assert_eq!(3, draws.count);
+        assert_eq!(1.0, draws.min);
+        assert_eq!(3.0, draws.max);
+        assert_eq!(2.0, draws.average());
+    }
+
+    #[test]
+    fn finds_busy_waits_on_the_delay_timer() {
+        let mut cpu = profiled_cpu(&PROGRAM);
+        for _ in 0..4 {
+            for _ in 0..10 {
+                cpu.step();
+            }
+            cpu.tick_timers();
+        }
+
+        let profiler = cpu.profiler().unwrap();
+        let loops = profiler.hot_loops(cpu.memory());
+        assert_eq!(0x204, loops[0].start);
+        assert_eq!(0x208, loops[0].end);
+        assert_eq!(Some("delay timer"), loops[0].busy_wait);
+        assert_eq!(
+            loops[0].instructions,
+            profiler.count(0x204) + profiler.count(0x206) + profiler.count(0x208)
+        );
+
+        let report = profiler.report(cpu.memory());
+        assert!(report.starts_with("instructions: 40 over 4 frames\n"));
+        assert!(report.contains("HOT  busy-wait on the delay timer\n"));
+        assert!(report.contains("LD V1, DT"));
+    }
+
+    #[test]
+    fn exports_coverage() {
+        let mut cpu = profiled_cpu(&PROGRAM);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        let profiler = cpu.profiler().unwrap();
+
+        let json = profiler.coverage_json(&PROGRAM, 0x200);
+        assert!(json.starts_with(
+            "{\"origin\":512,\"size\":16,\"hit\":3,\"found\":7,\"instructions\":[\n  \
+             {\"address\":512,\"count\":1},"
+        ));
+        assert!(json.contains("{\"address\":522,\"count\":0}"));
+        assert!(!json.contains("\"address\":526"));
+
+        let lcov = profiler.coverage_lcov("game.ch8", &PROGRAM, 0x200);
+        assert!(lcov.starts_with("TN:\nSF:game.ch8\nDA:512,1\n"));
+        assert!(lcov.ends_with("LF:7\nLH:3\nend_of_record\n"));
+    }
+
+    #[test]
+    fn frame_times_are_averaged() {
+        let mut profiler = Profiler::new();
+        profiler.record_frame_time(2.0);
+        profiler.record_frame_time(4.0);
+
+        let times = profiler.frame_times();
+        assert_eq!((2.0, 4.0, 3.0), (times.min, times.max, times.average()));
+    }
+}
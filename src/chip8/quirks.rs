@@ -0,0 +1,94 @@
+/// The `Quirks` type. Interpreters for different platforms disagree on the
+/// behaviour of a handful of instructions; each flag selects one side of such
+/// a disagreement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY1, 8XY2 and 8XY3 reset VF to zero.
+    pub vf_reset: bool,
+    /// FX55 and FX65 leave I pointing past the last register they touched.
+    pub increment_i: bool,
+    /// 8XY6 and 8XYE shift VY into VX instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// BNNN jumps to NNN + VX, where X is the highest nibble of NNN, instead
+    /// of NNN + V0.
+    pub jump_with_vx: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clip_sprites: bool,
+    /// DXYN blocks the cpu until the next 60 Hz frame.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the RCA COSMAC VIP.
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            vf_reset: true,
+            increment_i: true,
+            shift_uses_vy: true,
+            jump_with_vx: false,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators.
+    pub fn chip48() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            increment_i: false,
+            shift_uses_vy: false,
+            jump_with_vx: true,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1, which kept CHIP-48's behaviour for every quirk
+    /// modelled here.
+    pub fn super_chip() -> Quirks {
+        Quirks::chip48()
+    }
+
+    /// XO-CHIP, as implemented by Octo.
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            increment_i: true,
+            shift_uses_vy: true,
+            jump_with_vx: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+
+    /// Looks up a preset by name: `vip`, `chip48`, `schip` or `xochip`.
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::super_chip()),
+            "xochip" => Some(Quirks::xo_chip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::cosmac_vip()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name_finds_presets() {
+        assert_eq!(Some(Quirks::cosmac_vip()), Quirks::from_name("vip"));
+        assert_eq!(Some(Quirks::chip48()), Quirks::from_name("chip48"));
+        assert_eq!(Some(Quirks::super_chip()), Quirks::from_name("schip"));
+        assert_eq!(Some(Quirks::xo_chip()), Quirks::from_name("xochip"));
+        assert_eq!(None, Quirks::from_name("chip9"));
+    }
+}
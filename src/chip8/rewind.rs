@@ -0,0 +1,386 @@
+use crate::chip8::cpu::Cpu;
+use crate::chip8::state::StateError;
+use crate::chip8::timer::TIMER_FREQUENCY;
+use std::collections::VecDeque;
+
+/// The `Rewind` type. Keeps a bounded history of machine snapshots, taken
+/// every `interval` frames, that the machine can be stepped back through.
+///
+/// Only the newest snapshot is stored in full. Each older one is stored as
+/// the bytes that differ from the snapshot taken after it, which is small
+/// since a frame rarely touches more than a handful of bytes of memory.
+/// Dropping the oldest snapshot therefore never invalidates the others.
+pub struct Rewind {
+    interval: u32,
+    capacity: usize,
+    frames_since_capture: u32,
+    snapshots: VecDeque<Snapshot>,
+}
+
+enum Snapshot {
+    Full(Vec<u8>),
+    /// Differences from the next newer snapshot, see `encode_delta`.
+    Delta(Vec<u8>),
+}
+
+impl Rewind {
+    /// Creates a rewind buffer holding `depth_secs` seconds of history,
+    /// snapshotting every `interval` 60 Hz frames.
+    pub fn new(depth_secs: f64, interval: u32) -> Rewind {
+        let interval = interval.max(1);
+        let frames = (depth_secs.max(0.0) * TIMER_FREQUENCY).ceil() as usize;
+
+        Rewind {
+            interval,
+            capacity: frames.div_ceil(interval as usize),
+            frames_since_capture: 0,
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    /// How many seconds of history the buffer holds when full.
+    pub fn depth_secs(&self) -> f64 {
+        (self.capacity * self.interval as usize) as f64 / TIMER_FREQUENCY
+    }
+
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    /// The number of snapshots that can currently be stepped back through.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.frames_since_capture = 0;
+    }
+
+    /// Call once at the end of every frame; snapshots `cpu` every
+    /// `interval` frames.
+    pub fn record_frame(&mut self, cpu: &Cpu) {
+        self.frames_since_capture += 1;
+        if self.frames_since_capture >= self.interval {
+            self.frames_since_capture = 0;
+            self.capture(cpu);
+        }
+    }
+
+    /// Snapshots `cpu` right away, dropping the oldest snapshot when the
+    /// buffer is full.
+    pub fn capture(&mut self, cpu: &Cpu) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let state = cpu.save_state();
+        if let Some(Snapshot::Full(newest)) = self.snapshots.pop_back() {
+            let previous = if newest.len() == state.len() {
+                Snapshot::Delta(encode_delta(&state, &newest))
+            } else {
+                Snapshot::Full(newest)
+            };
+            self.snapshots.push_back(previous);
+        }
+        self.snapshots.push_back(Snapshot::Full(state));
+
+        while self.snapshots.len() > self.capacity {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Restores `cpu` to the newest snapshot and forgets it, so repeated
+    /// calls walk back through history. A newest snapshot equal to the
+    /// current state, as `record_frame` leaves at the end of every frame, is
+    /// skipped so the first call already goes back. Returns false once the
+    /// history is used up.
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> Result<bool, StateError> {
+        let mut newest = match self.pop_newest()? {
+            Some(state) => state,
+            None => return Ok(false),
+        };
+        if newest == cpu.save_state() {
+            newest = match self.pop_newest()? {
+                Some(state) => state,
+                None => return Ok(false),
+            };
+        }
+
+        self.frames_since_capture = 0;
+        cpu.load_state(&newest)?;
+        Ok(true)
+    }
+
+    /// Removes the newest snapshot, turning the one before it back into a
+    /// full snapshot.
+    fn pop_newest(&mut self) -> Result<Option<Vec<u8>>, StateError> {
+        let newest = match self.snapshots.pop_back() {
+            Some(Snapshot::Full(state)) => state,
+            Some(Snapshot::Delta(_)) => unreachable!("newest snapshot is always full"),
+            None => return Ok(None),
+        };
+
+        if let Some(Snapshot::Delta(delta)) = self.snapshots.back() {
+            let previous = apply_delta(&newest, delta)?;
+            *self.snapshots.back_mut().unwrap() = Snapshot::Full(previous);
+        }
+
+        Ok(Some(newest))
+    }
+
+    /// Total bytes held by the snapshots, for keeping an eye on memory use.
+    pub fn stored_bytes(&self) -> usize {
+        self.snapshots
+            .iter()
+            .map(|snapshot| match snapshot {
+                Snapshot::Full(bytes) | Snapshot::Delta(bytes) => bytes.len(),
+            })
+            .sum()
+    }
+}
+
+impl Default for Rewind {
+    /// Ten seconds of history at one snapshot per frame.
+    fn default() -> Self {
+        Rewind::new(10.0, 1)
+    }
+}
+
+/// Encodes `target` as its differences from `base`, which must be the same
+/// length: a sequence of (unchanged run length, changed run length, changed
+/// bytes) with the lengths as LEB128 varints.
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut position = 0;
+
+    while position < target.len() {
+        let unchanged = (position..target.len())
+            .take_while(|&i| base[i] == target[i])
+            .count();
+        position += unchanged;
+        if position == target.len() {
+            break;
+        }
+
+        let changed = (position..target.len())
+            .take_while(|&i| base[i] != target[i])
+            .count();
+        write_varint(&mut delta, unchanged);
+        write_varint(&mut delta, changed);
+        delta.extend_from_slice(&target[position..position + changed]);
+        position += changed;
+    }
+
+    delta
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, StateError> {
+    let corrupt = StateError::Invalid("corrupt rewind delta");
+    let mut target = base.to_vec();
+    let mut position = 0;
+    let mut cursor = 0;
+
+    while cursor < delta.len() {
+        position += read_varint(delta, &mut cursor).ok_or(corrupt)?;
+        let changed = read_varint(delta, &mut cursor).ok_or(corrupt)?;
+        if position + changed > target.len() || cursor + changed > delta.len() {
+            return Err(corrupt);
+        }
+
+        target[position..position + changed].copy_from_slice(&delta[cursor..cursor + changed]);
+        position += changed;
+        cursor += changed;
+    }
+
+    Ok(target)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], cursor: &mut usize) -> Option<usize> {
+    let mut value = 0usize;
+    let mut shift = 0;
+
+    loop {
+        let byte = *data.get(*cursor)?;
+        *cursor += 1;
+        value |= ((byte & 0x7F) as usize).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::{Platform, Quirks};
+
+    /// A program that counts V0 up, stores it in memory and draws it, one
+    /// iteration per frame.
+    fn counting_cpu() -> Cpu {
+        // A300: I = 0x300, 7001: V0 += 1, F033: BCD of V0, F029: I = glyph,
+        // D115: draw, A300: I = 0x300, 1202: loop
+        let rom = [
+            0xA3, 0x00, 0x70, 0x01, 0xF0, 0x33, 0xF0, 0x29, 0xD1, 0x15, 0xA3, 0x00, 0x12, 0x02,
+        ];
+        let mut cpu = Cpu::new(Quirks::chip48());
+        cpu.load_rom(&rom).unwrap();
+        cpu.step();
+        cpu
+    }
+
+    fn run_frame(cpu: &mut Cpu) {
+        for _ in 0..6 {
+            cpu.step();
+        }
+        cpu.tick_timers();
+    }
+
+    #[test]
+    fn step_back_walks_back_frame_by_frame() {
+        let mut cpu = counting_cpu();
+        let mut rewind = Rewind::new(1.0, 1);
+        let mut history = Vec::new();
+
+        for _ in 0..20 {
+            run_frame(&mut cpu);
+            rewind.record_frame(&cpu);
+            history.push(cpu.save_state());
+        }
+
+        // The newest snapshot is the frame that just ran, so the first step
+        // goes back to the one before it.
+        for expected in history.iter().rev().skip(1) {
+            assert_eq!(Ok(true), rewind.step_back(&mut cpu));
+            assert_eq!(expected, &cpu.save_state());
+        }
+        assert_eq!(Ok(false), rewind.step_back(&mut cpu));
+    }
+
+    #[test]
+    fn step_back_restores_the_newest_snapshot_after_running_on() {
+        let mut cpu = counting_cpu();
+        let mut rewind = Rewind::new(1.0, 4);
+
+        run_frame(&mut cpu);
+        rewind.capture(&cpu);
+        let captured = cpu.save_state();
+        run_frame(&mut cpu);
+
+        assert_eq!(Ok(true), rewind.step_back(&mut cpu));
+        assert_eq!(captured, cpu.save_state());
+        assert_eq!(Ok(false), rewind.step_back(&mut cpu));
+    }
+
+    #[test]
+    fn buffer_is_bounded_by_depth() {
+        let mut cpu = counting_cpu();
+        let mut rewind = Rewind::new(0.5, 1);
+
+        for _ in 0..100 {
+            run_frame(&mut cpu);
+            rewind.record_frame(&cpu);
+        }
+
+        assert_eq!(30, rewind.len());
+        assert_eq!(0.5, rewind.depth_secs());
+    }
+
+    #[test]
+    fn snapshots_are_taken_every_interval_frames() {
+        let mut cpu = counting_cpu();
+        let mut rewind = Rewind::new(10.0, 4);
+        let mut captured = Vec::new();
+
+        for frame in 1..=12 {
+            run_frame(&mut cpu);
+            rewind.record_frame(&cpu);
+            if frame % 4 == 0 {
+                captured.push(cpu.save_state());
+            }
+        }
+
+        assert_eq!(3, rewind.len());
+        for expected in captured.iter().rev().skip(1) {
+            rewind.step_back(&mut cpu).unwrap();
+            assert_eq!(expected, &cpu.save_state());
+        }
+    }
+
+    #[test]
+    fn older_snapshots_are_stored_as_small_deltas() {
+        let mut cpu = Cpu::for_platform(Platform::XoChip);
+        cpu.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut rewind = Rewind::new(1.0, 1);
+
+        for _ in 0..60 {
+            run_frame(&mut cpu);
+            rewind.record_frame(&cpu);
+        }
+
+        let full = cpu.save_state().len();
+        assert!(
+            rewind.stored_bytes() < full * 2,
+            "{} bytes",
+            rewind.stored_bytes()
+        );
+    }
+
+    #[test]
+    fn snapshots_survive_resolution_changes() {
+        let mut cpu = Cpu::for_platform(Platform::SuperChip);
+        cpu.load_rom(&[0x00, 0xFF, 0x60, 0x01, 0x00, 0xFE]).unwrap();
+        let mut rewind = Rewind::default();
+
+        let mut history = Vec::new();
+        for _ in 0..3 {
+            cpu.step();
+            rewind.capture(&cpu);
+            history.push(cpu.save_state());
+        }
+
+        for expected in history.iter().rev().skip(1) {
+            rewind.step_back(&mut cpu).unwrap();
+            assert_eq!(expected, &cpu.save_state());
+        }
+    }
+
+    #[test]
+    fn delta_round_trips() {
+        let base = vec![0u8; 300];
+        let mut target = base.clone();
+        target[0] = 1;
+        target[150] = 2;
+        target[151] = 3;
+        target[299] = 4;
+
+        let delta = encode_delta(&base, &target);
+
+        assert!(delta.len() < 20);
+        assert_eq!(Ok(target), apply_delta(&base, &delta));
+        assert_eq!(Ok(base.clone()), apply_delta(&base, &[]));
+    }
+
+    #[test]
+    fn corrupt_delta_is_rejected() {
+        assert!(apply_delta(&[0; 4], &[2, 5, 1, 1, 1, 1, 1]).is_err());
+        assert!(apply_delta(&[0; 4], &[0x80]).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+/// Seed used by `Rng::default`, so a cpu that is never seeded still behaves
+/// the same on every run.
+pub const DEFAULT_SEED: u64 = 0x5EED_C8C8;
+
+/// The `Rng` type. A small xorshift64* generator. Its whole state is one
+/// word, which makes it cheap to snapshot along with the rest of the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Rng::new(DEFAULT_SEED)
+    }
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift gets stuck on a zero state, and similar seeds would start
+        // out with similar sequences, so scramble the seed first.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        Rng {
+            state: if z == 0 { DEFAULT_SEED } else { z },
+        }
+    }
+
+    /// Restores a generator from a value returned by `state`.
+    pub fn from_state(state: u64) -> Option<Rng> {
+        if state == 0 {
+            None
+        } else {
+            Some(Rng { state })
+        }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+
+        for _ in 0..100 {
+            assert_eq!(a.next_u8(), b.next_u8());
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_sequences() {
+        let a: Vec<u8> = (0..16)
+            .scan(Rng::new(1), |rng, _| Some(rng.next_u8()))
+            .collect();
+        let b: Vec<u8> = (0..16)
+            .scan(Rng::new(2), |rng, _| Some(rng.next_u8()))
+            .collect();
+
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn zero_seed_is_usable() {
+        let mut rng = Rng::new(0);
+
+        assert!((0..16).any(|_| rng.next_u8() != 0));
+    }
+
+    #[test]
+    fn restoring_state_continues_sequence() {
+        let mut rng = Rng::new(7);
+        rng.next_u64();
+
+        let mut restored = Rng::from_state(rng.state()).unwrap();
+
+        assert_eq!(rng.next_u64(), restored.next_u64());
+        assert_eq!(None, Rng::from_state(0));
+    }
+
+    #[test]
+    fn bytes_cover_the_whole_range() {
+        let mut rng = Rng::new(3);
+        let mut seen = [false; 256];
+
+        for _ in 0..10_000 {
+            seen[rng.next_u8() as usize] = true;
+        }
+
+        assert!(seen.iter().all(|&seen| seen));
+    }
+}
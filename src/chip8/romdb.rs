@@ -0,0 +1,485 @@
+use crate::chip8::cpu::{Cpu, LoadError};
+use crate::chip8::disasm::DEFAULT_ORIGIN;
+use crate::chip8::font::{Font, FONT_ADDRESS};
+use crate::chip8::json::Json;
+use crate::chip8::platform::Platform;
+use crate::chip8::quirks::Quirks;
+use crate::chip8::screen::DEFAULT_PALETTE;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The database built into the emulator, covering the roms shipped with it.
+/// A full chip-8-database `programs.json` can be merged on top.
+static EMBEDDED: &str = include_str!("../../roms/programs.json");
+
+/// Returned when a rom database can't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseError {
+    pub message: String,
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rom database: {}", self.message)
+    }
+}
+
+/// What the database knows about a rom, with the defaults of its platform
+/// filled in for whatever the entry leaves out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    /// Instructions per 60 Hz frame.
+    pub tick_rate: u32,
+    /// Keypad keys by the action they perform in the game: `up`, `left`,
+    /// `a`, `player2Down` and so on.
+    pub keys: BTreeMap<String, u8>,
+    /// Background, first plane, second plane and both planes, as 0xRRGGBB.
+    pub palette: Option<[u32; 4]>,
+    pub font: Option<Font>,
+    pub start_address: u16,
+}
+
+impl RomInfo {
+    /// What a rom the database doesn't know gets: the defaults of
+    /// `platform`, loaded at 0x200.
+    pub fn for_platform(platform: Platform) -> RomInfo {
+        RomInfo {
+            title: String::new(),
+            platform,
+            quirks: platform.quirks(),
+            tick_rate: platform.tick_rate(),
+            keys: BTreeMap::new(),
+            palette: None,
+            font: None,
+            start_address: DEFAULT_ORIGIN,
+        }
+    }
+
+    /// Sets `cpu` up for the rom, then loads it: platform, quirks, font and
+    /// load address. Without a font the default one goes back in, so nothing
+    /// is left over from the previous rom. On error the cpu is left
+    /// untouched.
+    pub fn load(&self, cpu: &mut Cpu, rom: &[u8]) -> Result<(), LoadError> {
+        self.platform.check_rom(rom, self.start_address)?;
+
+        cpu.set_platform(self.platform);
+        cpu.quirks = self.quirks;
+        cpu.load_font(self.font.unwrap_or_default(), FONT_ADDRESS)
+            .expect("built-in fonts fit at the default address");
+        cpu.load_rom_at(rom, self.start_address)
+    }
+}
+
+/// The `RomDatabase` type. Looks roms up by the SHA-1 of their contents in
+/// databases in the chip-8-database `programs.json` format: a list of
+/// programs, each with its roms keyed by hash.
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    /// Program title and rom entry by lowercase hex SHA-1.
+    roms: BTreeMap<String, (String, Json)>,
+}
+
+impl RomDatabase {
+    /// The database shipped with the emulator.
+    pub fn embedded() -> RomDatabase {
+        RomDatabase::parse(EMBEDDED).expect("embedded rom database is valid")
+    }
+
+    pub fn parse(text: &str) -> Result<RomDatabase, DatabaseError> {
+        let mut database = RomDatabase::default();
+        database.merge(text)?;
+        Ok(database)
+    }
+
+    /// Adds the programs in `text`. Roms already known are overlaid field by
+    /// field, so overrides only need the fields they change. On error the
+    /// database is left untouched.
+    pub fn merge(&mut self, text: &str) -> Result<(), DatabaseError> {
+        let error = |message: String| DatabaseError { message };
+        let json = Json::parse(text).map_err(|err| error(err.to_string()))?;
+        let programs = json
+            .as_array()
+            .ok_or_else(|| error("expected a list of programs".to_string()))?;
+
+        let mut merged = self.roms.clone();
+        for (index, program) in programs.iter().enumerate() {
+            let title = program.get("title").and_then(Json::as_str);
+            let roms = program
+                .get("roms")
+                .and_then(Json::as_object)
+                .ok_or_else(|| error(format!("program {} has no roms", index)))?;
+            for (hash, entry) in roms {
+                if entry.as_object().is_none() {
+                    return Err(error(format!("rom {} is not an object", hash)));
+                }
+                let (known_title, known_entry) = merged
+                    .entry(hash.to_ascii_lowercase())
+                    .or_insert_with(|| (String::new(), Json::Object(BTreeMap::new())));
+                if let Some(title) = title {
+                    *known_title = title.to_string();
+                }
+                known_entry.merge(entry.clone());
+            }
+        }
+
+        self.roms = merged;
+        Ok(())
+    }
+
+    /// The number of roms known.
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    /// Everything known about `rom`. `None` when it isn't in the database, or
+    /// only runs on platforms the emulator lacks.
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        self.lookup_hash(&sha1_hex(rom))
+    }
+
+    /// Like `lookup`, given the hex SHA-1 of the rom.
+    pub fn lookup_hash(&self, sha1: &str) -> Option<RomInfo> {
+        let (title, entry) = self.roms.get(&sha1.to_ascii_lowercase())?;
+        let (id, platform, mut quirks) = entry
+            .get("platforms")?
+            .as_array()?
+            .iter()
+            .filter_map(Json::as_str)
+            .find_map(|id| {
+                platform_defaults(id).map(|(platform, quirks)| (id, platform, quirks))
+            })?;
+        if let Some(overrides) = entry.get("quirkyPlatforms").and_then(|q| q.get(id)) {
+            apply_quirks(&mut quirks, overrides);
+        }
+
+        Some(RomInfo {
+            title: title.clone(),
+            platform,
+            quirks,
+            tick_rate: entry
+                .get("tickrate")
+                .and_then(Json::as_f64)
+                .filter(|&rate| rate >= 1.0 && rate <= u32::MAX as f64)
+                .map_or(platform.tick_rate(), |rate| rate as u32),
+            keys: entry
+                .get("keys")
+                .and_then(Json::as_object)
+                .map(|keys| {
+                    keys.iter()
+                        .filter_map(|(action, key)| Some((action.clone(), hex_key(key)?)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            palette: entry
+                .get("colors")
+                .and_then(|colors| colors.get("pixels"))
+                .and_then(Json::as_array)
+                .and_then(palette),
+            font: entry
+                .get("fontStyle")
+                .and_then(Json::as_str)
+                .and_then(font_style),
+            start_address: entry
+                .get("startAddress")
+                .and_then(Json::as_f64)
+                .filter(|&address| (0.0..=u16::MAX as f64).contains(&address))
+                .map_or(DEFAULT_ORIGIN, |address| address as u16),
+        })
+    }
+}
+
+/// The platform and quirks the database's platform ids stand for. Ids the
+/// emulator can't run, such as `chip8x` and `megachip8`, give `None`.
+fn platform_defaults(id: &str) -> Option<(Platform, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Platform::Chip8, Quirks::cosmac_vip())),
+        "modernChip8" => Some((
+            Platform::Chip8,
+            Quirks {
+                vf_reset: false,
+                display_wait: false,
+                ..Quirks::cosmac_vip()
+            },
+        )),
+        "chip48" => Some((Platform::Chip8, Quirks::chip48())),
+        "superchip1" | "superchip" => Some((Platform::SuperChip, Quirks::super_chip())),
+        "xochip" => Some((Platform::XoChip, Quirks::xo_chip())),
+        _ => None,
+    }
+}
+
+/// Applies the database's quirk flags, each of which turns on the behaviour
+/// it names. `memoryIncrementByX` isn't modelled and is ignored.
+fn apply_quirks(quirks: &mut Quirks, overrides: &Json) {
+    let overrides = overrides.as_object().into_iter().flatten();
+    for (name, on) in overrides.filter_map(|(name, on)| Some((name, on.as_bool()?))) {
+        match name.as_str() {
+            "shift" => quirks.shift_uses_vy = !on,
+            "memoryLeaveIUnchanged" => quirks.increment_i = !on,
+            "wrap" => quirks.clip_sprites = !on,
+            "jump" => quirks.jump_with_vx = on,
+            "vblank" => quirks.display_wait = on,
+            "logic" => quirks.vf_reset = on,
+            _ => (),
+        }
+    }
+}
+
+fn hex_key(key: &Json) -> Option<u8> {
+    key.as_f64()
+        .filter(|&key| key.fract() == 0.0 && (0.0..16.0).contains(&key))
+        .map(|key| key as u8)
+}
+
+/// A palette from `#rrggbb` pixel colors, taking the default for planes the
+/// entry has no color for.
+fn palette(pixels: &[Json]) -> Option<[u32; 4]> {
+    let mut palette = DEFAULT_PALETTE;
+    for (color, pixel) in palette.iter_mut().zip(pixels.iter()) {
+        *color = pixel
+            .as_str()
+            .and_then(|pixel| pixel.strip_prefix('#'))
+            .filter(|hex| hex.len() == 6)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())?;
+    }
+    Some(palette)
+}
+
+/// The font for a `fontStyle`. SUPER-CHIP kept the CHIP-48 small font.
+fn font_style(style: &str) -> Option<Font> {
+    match style {
+        "vip" => Some(Font::CosmacVip),
+        "dream6800" => Some(Font::Dream6800),
+        "eti660" => Some(Font::Eti660),
+        "fish" => Some(Font::FishNChips),
+        "schip" => Some(Font::Chip48),
+        _ => None,
+    }
+}
+
+/// The SHA-1 of `data` in lowercase hex, which the database keys roms by.
+pub fn sha1_hex(data: &[u8]) -> String {
+    sha1(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, &word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *value = value.wrapping_add(*added);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TETRIS: &[u8] = include_bytes!("../../roms/tetris.rom");
+
+    #[test]
+    fn sha1_matches_known_digests() {
+        assert_eq!("da39a3ee5e6b4b0d3255bfef95601890afd80709", sha1_hex(b""));
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", sha1_hex(b"abc"));
+        // Two blocks of padding.
+        assert_eq!(
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+            sha1_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")
+        );
+    }
+
+    #[test]
+    fn embedded_database_knows_the_bundled_rom() {
+        let info = RomDatabase::embedded().lookup(TETRIS).unwrap();
+
+        assert_eq!("Tetris", info.title);
+        assert_eq!(Platform::Chip8, info.platform);
+        assert_eq!(Quirks::cosmac_vip(), info.quirks);
+        assert_eq!(Platform::Chip8.tick_rate(), info.tick_rate);
+        assert_eq!(Some(&5), info.keys.get("left"));
+        assert_eq!(DEFAULT_ORIGIN, info.start_address);
+        assert_eq!(None, RomDatabase::embedded().lookup(&[0x12, 0x00]));
+    }
+
+    #[test]
+    fn lookup_reads_every_field() {
+        let database = RomDatabase::parse(
+            r##"[{"title": "Demo", "roms": {"ABCDEF": {
+                "platforms": ["megachip8", "superchip", "xochip"],
+                "quirkyPlatforms": {"superchip": {"shift": false, "vblank": true},
+                                    "xochip": {"jump": true}},
+                "tickrate": 50,
+                "keys": {"up": 2, "fire": 16},
+                "colors": {"pixels": ["#102030", "#ffcc00"]},
+                "fontStyle": "eti660",
+                "startAddress": 1536
+            }}}]"##,
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(RomInfo {
+                title: "Demo".to_string(),
+                platform: Platform::SuperChip,
+                quirks: Quirks {
+                    shift_uses_vy: true,
+                    display_wait: true,
+                    ..Quirks::super_chip()
+                },
+                tick_rate: 50,
+                keys: vec![("up".to_string(), 2)].into_iter().collect(),
+                palette: Some([0x102030, 0xFFCC00, DEFAULT_PALETTE[2], DEFAULT_PALETTE[3]]),
+                font: Some(Font::Eti660),
+                start_address: 0x600,
+            }),
+            database.lookup_hash("abcdef")
+        );
+    }
+
+    #[test]
+    fn lookup_skips_roms_for_platforms_it_lacks() {
+        let database =
+            RomDatabase::parse(r#"[{"roms": {"ab": {"platforms": ["chip8x"]}}}]"#).unwrap();
+
+        assert_eq!(1, database.len());
+        assert_eq!(None, database.lookup_hash("ab"));
+    }
+
+    #[test]
+    fn merge_overlays_user_overrides() {
+        let mut database = RomDatabase::embedded();
+        database
+            .merge(
+                r#"[{"roms": {"5F518084744BF3CB8733F6E5454DFD1634320563": {
+                    "tickrate": 20, "keys": {"a": 8}}}}]"#,
+            )
+            .unwrap();
+
+        let info = database.lookup(TETRIS).unwrap();
+        assert_eq!("Tetris", info.title);
+        assert_eq!(20, info.tick_rate);
+        assert_eq!(Some(&8), info.keys.get("a"));
+        assert_eq!(Some(&5), info.keys.get("left"));
+    }
+
+    #[test]
+    fn merge_rejects_malformed_databases_and_keeps_the_old_one() {
+        let mut database = RomDatabase::embedded();
+
+        assert!(database.merge("{}").is_err());
+        assert!(database.merge(r#"[{"title": "No roms"}]"#).is_err());
+        assert!(database
+            .merge(r#"[{"roms": {"ab": {}}}, {"roms": {"cd": 1}}]"#)
+            .is_err());
+        assert!(database.merge("[").is_err());
+        assert!(database.merge(&"[".repeat(200_000)).is_err());
+        assert_eq!(1, database.len());
+    }
+
+    #[test]
+    fn load_sets_up_the_cpu() {
+        let info = RomDatabase::parse(
+            r#"[{"roms": {"ab": {"platforms": ["xochip"], "fontStyle": "vip",
+                "startAddress": 768}}}]"#,
+        )
+        .unwrap()
+        .lookup_hash("ab")
+        .unwrap();
+        let mut cpu = Cpu::default();
+
+        info.load(&mut cpu, &[0x13, 0x00]).unwrap();
+
+        assert_eq!(Platform::XoChip, cpu.platform);
+        assert_eq!(Quirks::xo_chip(), cpu.quirks);
+        assert_eq!(0x300, cpu.pc());
+        let font = FONT_ADDRESS as usize;
+        assert_eq!(Font::CosmacVip.glyphs(), &cpu.memory()[font..font + 80]);
+    }
+
+    #[test]
+    fn load_leaves_the_cpu_untouched_when_the_rom_does_not_fit() {
+        let mut cpu = Cpu::for_platform(Platform::XoChip);
+        cpu.load_rom(&[0x12, 0x00]).unwrap();
+        let before = cpu.save_state();
+
+        assert_eq!(
+            Err(LoadError::TooLarge {
+                len: 0x2000,
+                max: 0xE00
+            }),
+            RomInfo::for_platform(Platform::Chip8).load(&mut cpu, &[0; 0x2000])
+        );
+        assert_eq!(before, cpu.save_state());
+    }
+
+    #[test]
+    fn load_puts_the_default_font_back() {
+        let mut cpu = Cpu::default();
+        cpu.load_font(Font::FishNChips, FONT_ADDRESS).unwrap();
+
+        RomInfo::for_platform(Platform::SuperChip)
+            .load(&mut cpu, &[0x12, 0x00])
+            .unwrap();
+
+        assert_eq!(Platform::SuperChip, cpu.platform);
+        assert_eq!(Quirks::super_chip(), cpu.quirks);
+        assert_eq!(0x200, cpu.pc());
+        let font = FONT_ADDRESS as usize;
+        assert_eq!(Font::default().glyphs(), &cpu.memory()[font..font + 80]);
+    }
+}
@@ -1,20 +1,94 @@
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+use crate::chip8::state::{StateError, StateReader, StateWriter};
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+pub const PLANE_COUNT: usize = 2;
+
+/// Colors for the four combinations of the two bitplanes, as 0xRRGGBB:
+/// background, first plane, second plane and both planes.
+pub const DEFAULT_PALETTE: [u32; 4] = [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555];
 
 /// The `Screen` type. Represents the chip8 screen. Each pixel is represented by
-/// a bit in a bitfield.
+/// a bit in a bitfield. The screen starts out in the 64x32 low resolution mode
+/// and can be switched to the 128x64 SUPER-CHIP high resolution mode.
+///
+/// XO-CHIP adds a second bitfield, so the screen has two bitplanes. Drawing,
+/// clearing and scrolling only affect the selected planes; by default only the
+/// first plane is selected, which behaves like the original single plane
+/// screen.
 pub struct Screen {
-    pixels: [u8; WIDTH * HEIGHT / 8],
+    planes: [Vec<u8>; PLANE_COUNT],
+    selected_planes: u8,
+    width: usize,
+    height: usize,
     dirty: bool,
 }
 
-impl Screen {
-    pub fn new() -> Screen {
+impl Default for Screen {
+    fn default() -> Self {
         Screen {
-            pixels: [0; WIDTH * HEIGHT / 8],
+            planes: [
+                vec![0; LORES_WIDTH * LORES_HEIGHT / 8],
+                vec![0; LORES_WIDTH * LORES_HEIGHT / 8],
+            ],
+            selected_planes: 1,
+            width: LORES_WIDTH,
+            height: LORES_HEIGHT,
             dirty: false,
         }
     }
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        Screen::default()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_WIDTH
+    }
+
+    /// Switches between the low and high resolution modes. The screen is
+    /// cleared on every switch.
+    pub fn set_hires(&mut self, hires: bool) {
+        if hires {
+            self.width = HIRES_WIDTH;
+            self.height = HIRES_HEIGHT;
+        } else {
+            self.width = LORES_WIDTH;
+            self.height = LORES_HEIGHT;
+        }
+        for plane in self.planes.iter_mut() {
+            *plane = vec![0; self.width * self.height / 8];
+        }
+        self.dirty = true;
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    /// Selects the planes later drawing, clearing and scrolling apply to. Bit 0
+    /// is the first plane and bit 1 the second.
+    pub fn select_planes(&mut self, mask: u8) {
+        self.selected_planes = mask & 0b11;
+    }
+
+    /// The number of selected planes, which is how many sprites a single draw
+    /// consumes.
+    pub fn selected_plane_count(&self) -> usize {
+        self.selected_planes.count_ones() as usize
+    }
 
     pub fn is_dirty(&self) -> bool {
         self.dirty
@@ -24,61 +98,179 @@ impl Screen {
         self.dirty = false;
     }
 
+    /// Forces the next frame to be rendered, e.g. after the palette changed.
+    pub fn set_dirty(&mut self) {
+        self.dirty = true;
+    }
+
     pub fn clear(&mut self) {
         self.dirty = true;
-        self.pixels = [0; WIDTH * HEIGHT / 8];
+        for plane in self.selected() {
+            self.planes[plane].iter_mut().for_each(|pixel| *pixel = 0);
+        }
+    }
+
+    /// Draws `data` at `x`, `y`, wrapping pixels that fall off an edge around
+    /// to the other side. With several planes selected, `data` holds one
+    /// sprite per plane, back to back. Returns true if any pixel was switched
+    /// off.
+    pub fn draw_sprite(&mut self, x: usize, y: usize, data: &[u8]) -> bool {
+        self.draw(x, y, data, 1, false)
+    }
+
+    /// Like `draw_sprite`, but pixels that fall off an edge are dropped. The
+    /// starting position still wraps.
+    pub fn draw_sprite_clipped(&mut self, x: usize, y: usize, data: &[u8]) -> bool {
+        self.draw(x, y, data, 1, true)
     }
 
-    pub fn draw_sprite(&mut self, x: usize, mut y: usize, data: &[u8]) -> bool {
+    /// Draws a 16x16 SUPER-CHIP sprite, two bytes per row.
+    pub fn draw_large_sprite(&mut self, x: usize, y: usize, data: &[u8], clip: bool) -> bool {
+        self.draw(x, y, data, 2, clip)
+    }
+
+    fn draw(&mut self, x: usize, y: usize, data: &[u8], bytes_per_row: usize, clip: bool) -> bool {
         self.dirty = true;
 
+        let planes = self.selected();
+        if planes.is_empty() {
+            return false;
+        }
+
+        let x = x % self.width;
+        let y = y % self.height;
+        let sprite_len = data.len() / planes.len();
+
+        let mut collision = false;
+        for (plane, sprite) in planes.into_iter().zip(data.chunks(sprite_len.max(1))) {
+            for (row, line) in sprite.chunks(bytes_per_row).enumerate() {
+                if clip && y + row >= self.height {
+                    break;
+                }
+
+                for (column, byte) in line.iter().enumerate() {
+                    let byte_x = x + column * 8;
+                    if clip && byte_x >= self.width {
+                        break;
+                    }
+
+                    collision |= self.draw_byte(plane, byte_x, y + row, *byte, clip);
+                }
+            }
+        }
+
+        collision
+    }
+
+    fn draw_byte(&mut self, plane: usize, x: usize, y: usize, line: u8, clip: bool) -> bool {
         let mut collision = false;
-        for line in data {
-            println!("Line: {:b}", line);
 
-            let first_pixel_offset = x % 8;
-            let index = self.index(x, y);
-            let first_row = line >> first_pixel_offset as u8;
-            if self.pixels[index] & first_row > 0 {
+        let first_pixel_offset = x % 8;
+        let index = self.index(x, y);
+        let pixels = &mut self.planes[plane];
+        let first_row = line >> first_pixel_offset as u8;
+        if pixels[index] & first_row > 0 {
+            collision = true;
+        }
+        pixels[index] ^= first_row;
+
+        let clip_second_byte = clip && x + 8 >= self.width;
+        if first_pixel_offset > 0 && !clip_second_byte {
+            let next_index = self.index(x + 8, y);
+            let pixels = &mut self.planes[plane];
+            let second_row = line << (8 - first_pixel_offset);
+            if pixels[next_index] & second_row > 0 {
                 collision = true;
             }
-            println!("Current pixel: {:b}", self.pixels[index]);
+            pixels[next_index] ^= second_row;
+        }
 
-            self.pixels[index] = first_row ^ self.pixels[index];
+        collision
+    }
 
-            println!("Offset: {}", first_pixel_offset);
-            println!("First row: {:b}", first_row);
+    /// Scrolls the screen down by `n` pixels. Rows scrolled in at the top are
+    /// blank.
+    pub fn scroll_down(&mut self, n: usize) {
+        let shift = n.min(self.height) * self.width / 8;
 
-            println!("Pixel after: {:b}", self.pixels[index]);
+        for plane in self.selected() {
+            let pixels = &mut self.planes[plane];
+            pixels.rotate_right(shift);
+            pixels[..shift].iter_mut().for_each(|pixel| *pixel = 0);
+        }
+        self.dirty = true;
+    }
 
-            if first_pixel_offset > 0 {
-                let next_index = self.index(x + 8, y);
-                println!("Current next pixel: {:b}", self.pixels[next_index]);
+    /// Scrolls the screen up by `n` pixels. Rows scrolled in at the bottom are
+    /// blank.
+    pub fn scroll_up(&mut self, n: usize) {
+        let shift = n.min(self.height) * self.width / 8;
+
+        for plane in self.selected() {
+            let pixels = &mut self.planes[plane];
+            let len = pixels.len();
+            pixels.rotate_left(shift);
+            pixels[len - shift..]
+                .iter_mut()
+                .for_each(|pixel| *pixel = 0);
+        }
+        self.dirty = true;
+    }
 
-                let second_pixel_offset = 8 - first_pixel_offset;
-                let second_row = line << second_pixel_offset;
-                if self.pixels[next_index] & second_row > 0 {
-                    collision = true;
-                }
-                self.pixels[next_index] = second_row ^ self.pixels[next_index];
+    /// Scrolls the screen right by `n` pixels. Columns scrolled in at the left
+    /// are blank.
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll_horizontally(n as isize);
+    }
 
-                println!("Next offset: {}", first_pixel_offset);
-                println!("Second row: {:b}", second_row);
-                println!("Pixel after: {:b}", self.pixels[next_index]);
+    /// Scrolls the screen left by `n` pixels. Columns scrolled in at the right
+    /// are blank.
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll_horizontally(-(n as isize));
+    }
+
+    fn scroll_horizontally(&mut self, offset: isize) {
+        for plane in self.selected() {
+            let mut pixels = vec![0; self.planes[plane].len()];
+
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let source = x as isize - offset;
+                    if source >= 0
+                        && (source as usize) < self.width
+                        && self.get_plane(plane, source as usize, y)
+                    {
+                        pixels[self.index(x, y)] |= 0x80 >> (x % 8);
+                    }
+                }
             }
-            y += 1;
-        }
 
-        collision
+            self.planes[plane] = pixels;
+        }
+        self.dirty = true;
     }
 
-    pub fn get_screen_data(&self) -> [u8; WIDTH * HEIGHT] {
-        let mut pixels = [0; WIDTH * HEIGHT];
+    /// Returns one byte per pixel for the active resolution, 255 for pixels
+    /// that are on in any plane and 0 for pixels that are off.
+    pub fn get_screen_data(&self) -> Vec<u8> {
+        self.get_plane_data()
+            .into_iter()
+            .map(|color| if color > 0 { 255 } else { 0 })
+            .collect()
+    }
 
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                if self.get(x, y) {
-                    pixels[y * WIDTH + x] = 255;
+    /// Returns one byte per pixel for the active resolution, holding the
+    /// pixel's color index: bit 0 is set when the pixel is on in the first
+    /// plane and bit 1 when it is on in the second.
+    pub fn get_plane_data(&self) -> Vec<u8> {
+        let mut pixels = vec![0; self.width * self.height];
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                for plane in 0..PLANE_COUNT {
+                    if self.get_plane(plane, x, y) {
+                        pixels[y * self.width + x] |= 1 << plane;
+                    }
                 }
             }
         }
@@ -86,20 +278,70 @@ impl Screen {
         pixels
     }
 
-    fn get(&self, x: usize, y: usize) -> bool {
+    /// Returns the pixels as packed RGB bytes, colored with `palette` indexed
+    /// by the plane combination of each pixel.
+    pub fn get_rgb_data(&self, palette: &[u32; 4]) -> Vec<u8> {
+        self.get_plane_data()
+            .into_iter()
+            .flat_map(|color| {
+                let rgb = palette[color as usize];
+                vec![(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]
+            })
+            .collect()
+    }
+
+    pub(crate) fn save(&self, writer: &mut StateWriter) {
+        writer.bool(self.is_hires());
+        writer.u8(self.selected_planes);
+        for plane in self.planes.iter() {
+            writer.bytes(plane);
+        }
+    }
+
+    pub(crate) fn load(reader: &mut StateReader) -> Result<Screen, StateError> {
+        let mut screen = Screen::new();
+        screen.set_hires(reader.bool()?);
+
+        let selected_planes = reader.u8()?;
+        if selected_planes > 0b11 {
+            return Err(StateError::Invalid("unknown bitplanes selected"));
+        }
+        screen.selected_planes = selected_planes;
+
+        for plane in screen.planes.iter_mut() {
+            let len = plane.len();
+            plane.copy_from_slice(reader.bytes(len)?);
+        }
+
+        Ok(screen)
+    }
+
+    fn selected(&self) -> Vec<usize> {
+        (0..PLANE_COUNT)
+            .filter(|plane| self.selected_planes & (1 << plane) != 0)
+            .collect()
+    }
+
+    /// Whether the pixel at `x`, `y` is on in any plane.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        (0..PLANE_COUNT).any(|plane| self.get_plane(plane, x, y))
+    }
+
+    fn get_plane(&self, plane: usize, x: usize, y: usize) -> bool {
         let offset = (8 - x % 8) - 1;
         let pixel_mask = 1 << offset;
-        self.pixels[self.index(x, y)] & pixel_mask == pixel_mask
+        self.planes[plane][self.index(x, y)] & pixel_mask == pixel_mask
     }
 
     fn index(&self, mut x: usize, mut y: usize) -> usize {
-        x %= WIDTH;
-        y %= HEIGHT;
-        (y * WIDTH + x) / 8
+        x %= self.width;
+        y %= self.height;
+        (y * self.width + x) / 8
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -107,7 +349,7 @@ mod tests {
     fn screen_is_cleared_by_default() {
         let screen = Screen::new();
 
-        assert!(screen.pixels.iter().all(|p| p == &0));
+        assert!(screen.planes.iter().flatten().all(|p| p == &0));
     }
 
     #[test]
@@ -171,7 +413,7 @@ mod tests {
         screen.draw_sprite(0, 0, &sprite);
         screen.clear();
 
-        assert!(screen.pixels.iter().all(|p| p == &0));
+        assert!(screen.planes.iter().flatten().all(|p| p == &0));
     }
 
     #[test]
@@ -206,4 +448,197 @@ mod tests {
         assert_eq!(true, screen.get(14, 0));
         assert_eq!(false, screen.get(15, 0));
     }
+
+    #[test]
+    fn draw_wraps_around_edges() {
+        let mut screen = Screen::new();
+
+        screen.draw_sprite(60, 31, &[0xFF, 0xFF]);
+
+        assert!(screen.get(63, 31));
+        assert!(screen.get(0, 31));
+        assert!(screen.get(3, 31));
+        assert!(screen.get(60, 0));
+        assert!(screen.get(3, 0));
+    }
+
+    #[test]
+    fn draw_clipped_drops_pixels_past_edges() {
+        let mut screen = Screen::new();
+
+        screen.draw_sprite_clipped(60, 31, &[0xFF, 0xFF]);
+
+        assert!(screen.get(60, 31));
+        assert!(screen.get(63, 31));
+        assert!(!screen.get(0, 31));
+        assert!(!screen.get(60, 0));
+        assert!(!screen.get(0, 0));
+    }
+
+    #[test]
+    fn draw_clipped_wraps_starting_position() {
+        let mut screen = Screen::new();
+
+        screen.draw_sprite_clipped(64 + 2, 32 + 1, &[0x80]);
+
+        assert!(screen.get(2, 1));
+    }
+
+    #[test]
+    fn hires_mode_doubles_resolution() {
+        let mut screen = Screen::new();
+
+        screen.set_hires(true);
+
+        assert!(screen.is_hires());
+        assert_eq!(128, screen.width());
+        assert_eq!(64, screen.height());
+        assert_eq!(128 * 64, screen.get_screen_data().len());
+    }
+
+    #[test]
+    fn switching_resolution_clears_the_screen() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 0, &[0xFF]);
+
+        screen.set_hires(true);
+
+        assert!(screen.planes.iter().flatten().all(|p| p == &0));
+    }
+
+    #[test]
+    fn hires_sprites_wrap_at_hires_edges() {
+        let mut screen = Screen::new();
+        screen.set_hires(true);
+
+        screen.draw_sprite(124, 63, &[0xFF, 0xFF]);
+
+        assert!(screen.get(127, 63));
+        assert!(screen.get(0, 63));
+        assert!(screen.get(124, 0));
+        assert!(!screen.get(64, 63));
+    }
+
+    #[test]
+    fn draw_large_sprite_draws_sixteen_by_sixteen() {
+        let mut screen = Screen::new();
+        screen.set_hires(true);
+
+        let collision = screen.draw_large_sprite(3, 2, &[0xFF; 32], false);
+
+        assert!(!collision);
+        for y in 2..18 {
+            for x in 3..19 {
+                assert!(screen.get(x, y));
+            }
+            assert!(!screen.get(2, y));
+            assert!(!screen.get(19, y));
+        }
+        assert!(screen.draw_large_sprite(3, 2, &[0x01, 0x00], false));
+    }
+
+    #[test]
+    fn scroll_down_moves_rows_down() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 0, &[0x80]);
+
+        screen.scroll_down(4);
+
+        assert!(!screen.get(0, 0));
+        assert!(screen.get(0, 4));
+    }
+
+    #[test]
+    fn scroll_right_and_left_move_columns() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(62, 0, &[0xC0]);
+
+        screen.scroll_right(4);
+        assert!(!screen.get(62, 0));
+        assert!(!screen.get(63, 0));
+        assert!(!screen.get(2, 0));
+
+        screen.draw_sprite(0, 1, &[0x80]);
+        screen.scroll_right(4);
+        assert!(screen.get(4, 1));
+
+        screen.scroll_left(4);
+        assert!(screen.get(0, 1));
+        screen.scroll_left(4);
+        assert!(!screen.get(0, 1));
+    }
+
+    #[test]
+    fn draw_with_both_planes_uses_one_sprite_per_plane() {
+        let mut screen = Screen::new();
+        screen.select_planes(0b11);
+
+        screen.draw_sprite(0, 0, &[0xF0, 0x0F]);
+
+        let colors = screen.get_plane_data();
+        assert_eq!(&[1, 1, 1, 1, 2, 2, 2, 2], &colors[..8]);
+    }
+
+    #[test]
+    fn draw_on_second_plane_only() {
+        let mut screen = Screen::new();
+        screen.select_planes(0b10);
+
+        screen.draw_sprite(0, 0, &[0x80]);
+
+        assert_eq!(2, screen.get_plane_data()[0]);
+        assert_eq!(255, screen.get_screen_data()[0]);
+    }
+
+    #[test]
+    fn rgb_data_uses_palette_per_plane_combination() {
+        let mut screen = Screen::new();
+        screen.select_planes(0b11);
+        screen.draw_sprite(0, 0, &[0xC0, 0xA0]);
+
+        let rgb = screen.get_rgb_data(&[0x000000, 0x112233, 0x445566, 0x778899]);
+
+        assert_eq!(LORES_WIDTH * LORES_HEIGHT * 3, rgb.len());
+        assert_eq!(
+            &[0x77, 0x88, 0x99, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66],
+            &rgb[..9]
+        );
+        assert_eq!(&[0, 0, 0], &rgb[9..12]);
+    }
+
+    #[test]
+    fn clear_and_scroll_only_touch_selected_planes() {
+        let mut screen = Screen::new();
+        screen.select_planes(0b11);
+        screen.draw_sprite(0, 0, &[0x80, 0x80]);
+
+        screen.select_planes(0b01);
+        screen.scroll_down(1);
+        screen.select_planes(0b10);
+        screen.clear();
+
+        let colors = screen.get_plane_data();
+        assert_eq!(0, colors[0]);
+        assert_eq!(1, colors[64]);
+    }
+
+    #[test]
+    fn scroll_up_moves_rows_up() {
+        let mut screen = Screen::new();
+        screen.draw_sprite(0, 4, &[0x80]);
+
+        screen.scroll_up(4);
+
+        assert!(screen.get(0, 0));
+        assert!(!screen.get(0, 4));
+    }
+
+    #[test]
+    fn draw_with_no_planes_selected_draws_nothing() {
+        let mut screen = Screen::new();
+        screen.select_planes(0);
+
+        assert!(!screen.draw_sprite(0, 0, &[]));
+        assert!(screen.get_plane_data().iter().all(|&color| color == 0));
+    }
 }
@@ -0,0 +1,331 @@
+use crate::chip8::platform::Platform;
+use crate::chip8::quirks::Quirks;
+use std::fmt;
+
+/// Identifies a save state.
+pub const MAGIC: &[u8; 4] = b"C8SS";
+/// The format version written by `StateWriter`. Bump it whenever the payload
+/// layout changes; older states are rejected rather than misread.
+pub const VERSION: u16 = 3;
+
+/// Magic, version, payload length and payload checksum.
+const HEADER_LEN: usize = 4 + 2 + 4 + 4;
+
+/// Returned when a save state can't be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported save state version {} (expected {})",
+                version, VERSION
+            ),
+            StateError::ChecksumMismatch => write!(f, "save state is corrupt (checksum mismatch)"),
+            StateError::Invalid(what) => write!(f, "save state is invalid: {}", what),
+        }
+    }
+}
+
+/// Builds a save state payload. `finish` prepends the header.
+pub(crate) struct StateWriter {
+    payload: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter {
+            payload: Vec::new(),
+        }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.payload.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.payload.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.payload.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, value: f64) {
+        self.u64(value.to_bits());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.payload.extend_from_slice(bytes);
+    }
+
+    pub fn platform(&mut self, platform: Platform) {
+        self.u8(match platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        });
+    }
+
+    pub fn quirks(&mut self, quirks: &Quirks) {
+        let flags = [
+            quirks.vf_reset,
+            quirks.increment_i,
+            quirks.shift_uses_vy,
+            quirks.jump_with_vx,
+            quirks.clip_sprites,
+            quirks.display_wait,
+        ];
+        self.u8(flags
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (bit, &flag)| bits | (flag as u8) << bit));
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        state.extend_from_slice(MAGIC);
+        state.extend_from_slice(&VERSION.to_le_bytes());
+        state.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        state.extend_from_slice(&crc32(&self.payload).to_le_bytes());
+        state.extend_from_slice(&self.payload);
+        state
+    }
+}
+
+/// Reads back a payload written by `StateWriter`, after checking the header
+/// and checksum.
+pub(crate) struct StateReader<'a> {
+    payload: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn open(state: &'a [u8]) -> Result<StateReader<'a>, StateError> {
+        if state.len() < 4 || &state[..4] != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        if state.len() < HEADER_LEN {
+            return Err(StateError::Truncated);
+        }
+
+        let version = u16::from_le_bytes([state[4], state[5]]);
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let len = u32::from_le_bytes([state[6], state[7], state[8], state[9]]) as usize;
+        let checksum = u32::from_le_bytes([state[10], state[11], state[12], state[13]]);
+        let payload = &state[HEADER_LEN..];
+        if payload.len() < len {
+            return Err(StateError::Truncated);
+        }
+        if payload.len() > len {
+            return Err(StateError::Invalid("trailing data"));
+        }
+        if crc32(payload) != checksum {
+            return Err(StateError::ChecksumMismatch);
+        }
+
+        Ok(StateReader {
+            payload,
+            position: 0,
+        })
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.position + len > self.payload.len() {
+            return Err(StateError::Truncated);
+        }
+
+        let bytes = &self.payload[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag is neither 0 nor 1")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, StateError> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+
+    pub fn platform(&mut self) -> Result<Platform, StateError> {
+        match self.u8()? {
+            0 => Ok(Platform::Chip8),
+            1 => Ok(Platform::SuperChip),
+            2 => Ok(Platform::XoChip),
+            _ => Err(StateError::Invalid("unknown platform")),
+        }
+    }
+
+    pub fn quirks(&mut self) -> Result<Quirks, StateError> {
+        let bits = self.u8()?;
+        if bits >> 6 != 0 {
+            return Err(StateError::Invalid("unknown quirk flags"));
+        }
+
+        let flag = |bit: u8| bits & (1 << bit) != 0;
+        Ok(Quirks {
+            vf_reset: flag(0),
+            increment_i: flag(1),
+            shift_uses_vy: flag(2),
+            jump_with_vx: flag(3),
+            clip_sprites: flag(4),
+            display_wait: flag(5),
+        })
+    }
+
+    /// Fails unless the whole payload was consumed.
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.position != self.payload.len() {
+            return Err(StateError::Invalid("trailing data"));
+        }
+
+        Ok(())
+    }
+}
+
+/// CRC-32 as used by zip and PNG.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_state() -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.u8(1);
+        writer.u16(0x0203);
+        writer.u64(0x0405_0607_0809_0A0B);
+        writer.f64(0.25);
+        writer.platform(Platform::XoChip);
+        writer.quirks(&Quirks::cosmac_vip());
+        writer.finish()
+    }
+
+    #[test]
+    fn crc32_matches_reference_value() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let state = sample_state();
+        let mut reader = StateReader::open(&state).unwrap();
+
+        assert_eq!(Ok(1), reader.u8());
+        assert_eq!(Ok(0x0203), reader.u16());
+        assert_eq!(Ok(0x0405_0607_0809_0A0B), reader.u64());
+        assert_eq!(Ok(0.25), reader.f64());
+        assert_eq!(Ok(Platform::XoChip), reader.platform());
+        assert_eq!(Ok(Quirks::cosmac_vip()), reader.quirks());
+        assert_eq!(Ok(()), reader.finish());
+    }
+
+    #[test]
+    fn open_rejects_bad_magic() {
+        let mut state = sample_state();
+        state[0] = b'X';
+
+        assert_eq!(Some(StateError::BadMagic), StateReader::open(&state).err());
+        assert_eq!(Some(StateError::BadMagic), StateReader::open(&[]).err());
+    }
+
+    #[test]
+    fn open_rejects_other_versions() {
+        let mut state = sample_state();
+        state[4] = 99;
+
+        assert_eq!(
+            Some(StateError::UnsupportedVersion(99)),
+            StateReader::open(&state).err()
+        );
+    }
+
+    #[test]
+    fn open_rejects_corrupt_payload() {
+        let mut state = sample_state();
+        let last = state.len() - 1;
+        state[last] ^= 0xFF;
+
+        assert_eq!(
+            Some(StateError::ChecksumMismatch),
+            StateReader::open(&state).err()
+        );
+    }
+
+    #[test]
+    fn open_rejects_truncated_state() {
+        let state = sample_state();
+
+        assert_eq!(
+            Some(StateError::Truncated),
+            StateReader::open(&state[..state.len() - 1]).err()
+        );
+        assert_eq!(
+            Some(StateError::Truncated),
+            StateReader::open(&state[..8]).err()
+        );
+    }
+
+    #[test]
+    fn reading_past_the_payload_fails() {
+        let state = StateWriter::new().finish();
+        let mut reader = StateReader::open(&state).unwrap();
+
+        assert_eq!(Err(StateError::Truncated), reader.u16());
+    }
+
+    #[test]
+    fn finish_rejects_unread_data() {
+        let state = sample_state();
+        let reader = StateReader::open(&state).unwrap();
+
+        assert_eq!(Err(StateError::Invalid("trailing data")), reader.finish());
+    }
+}
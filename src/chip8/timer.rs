@@ -0,0 +1,104 @@
+use crate::chip8::state::{StateError, StateReader, StateWriter};
+
+/// How often the delay and sound timers count down, in Hz.
+pub const TIMER_FREQUENCY: f64 = 60.0;
+
+/// The `Timers` type. Holds the delay and sound timers. They count down at
+/// `TIMER_FREQUENCY` regardless of how many instructions the cpu executes, so
+/// they are driven by elapsed time rather than by `Cpu::step`.
+#[derive(Default)]
+pub struct Timers {
+    pub delay: u8,
+    pub sound: u8,
+    accumulator: f64,
+}
+
+impl Timers {
+    pub fn new() -> Timers {
+        Timers::default()
+    }
+
+    /// Counts both timers down by one, stopping at zero.
+    pub fn tick(&mut self) {
+        self.delay = self.delay.saturating_sub(1);
+        self.sound = self.sound.saturating_sub(1);
+    }
+
+    /// Advances the timers by `elapsed_secs` of real time, ticking once for
+    /// every full 60th of a second. Leftover time carries over to the next
+    /// update. Returns the number of ticks.
+    pub fn update(&mut self, elapsed_secs: f64) -> u32 {
+        self.accumulator += elapsed_secs * TIMER_FREQUENCY;
+
+        let mut ticks = 0;
+        while self.accumulator >= 1.0 {
+            self.tick();
+            self.accumulator -= 1.0;
+            ticks += 1;
+        }
+
+        ticks
+    }
+
+    pub(crate) fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.delay);
+        writer.u8(self.sound);
+        writer.f64(self.accumulator);
+    }
+
+    pub(crate) fn load(reader: &mut StateReader) -> Result<Timers, StateError> {
+        let delay = reader.u8()?;
+        let sound = reader.u8()?;
+        let accumulator = reader.f64()?;
+        if !(0.0..1.0).contains(&accumulator) {
+            return Err(StateError::Invalid("timer accumulator out of range"));
+        }
+
+        Ok(Timers {
+            delay,
+            sound,
+            accumulator,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tick_stops_at_zero() {
+        let mut timers = Timers::new();
+        timers.delay = 1;
+
+        timers.tick();
+        timers.tick();
+
+        assert_eq!(0, timers.delay);
+        assert_eq!(0, timers.sound);
+    }
+
+    #[test]
+    fn update_ticks_sixty_times_per_second() {
+        let mut timers = Timers::new();
+        timers.delay = 100;
+        timers.sound = 100;
+
+        timers.update(1.0);
+
+        assert_eq!(40, timers.delay);
+        assert_eq!(40, timers.sound);
+    }
+
+    #[test]
+    fn update_carries_over_partial_ticks() {
+        let mut timers = Timers::new();
+        timers.delay = 10;
+
+        timers.update(0.5 / TIMER_FREQUENCY);
+        assert_eq!(10, timers.delay);
+
+        timers.update(0.5 / TIMER_FREQUENCY);
+        assert_eq!(9, timers.delay);
+    }
+}
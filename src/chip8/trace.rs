@@ -0,0 +1,302 @@
+use crate::chip8::disasm::{format_opcode, Syntax};
+use crate::chip8::fault::FaultKind;
+use crate::chip8::opcode::{decode, Opcode};
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+/// How many instructions a trace keeps unless told otherwise.
+pub const DEFAULT_TRACE_CAPACITY: usize = 4096;
+
+/// A value an instruction changed, and what it was before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// V0 to VF.
+    Register {
+        index: u8,
+        from: u8,
+        to: u8,
+    },
+    I {
+        from: u16,
+        to: u16,
+    },
+}
+
+/// One executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+    /// The word following the opcode, which F000 loads into I.
+    pub long: u16,
+    pub changes: Vec<Change>,
+    /// Set when the instruction faulted instead of executing.
+    pub fault: Option<FaultKind>,
+}
+
+impl TraceEntry {
+    pub fn decoded(&self) -> Option<Opcode> {
+        decode(self.opcode).ok()
+    }
+
+    fn instruction(&self) -> String {
+        match self.decoded() {
+            Some(opcode) => format_opcode(opcode, self.long, Syntax::Cowgod),
+            None => "???".to_string(),
+        }
+    }
+}
+
+/// The `Trace` type. Records the last `capacity` instructions the cpu
+/// executed, with what each one changed. Enable it with `Cpu::enable_trace`;
+/// a cpu without a trace does no tracing work at all.
+pub struct Trace {
+    capacity: usize,
+    entries: VecDeque<TraceEntry>,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Trace::new(DEFAULT_TRACE_CAPACITY)
+    }
+}
+
+impl Trace {
+    pub fn new(capacity: usize) -> Trace {
+        Trace {
+            capacity: capacity.max(1),
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The recorded instructions, oldest first.
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+
+    /// Records an instruction given the registers and I from before and
+    /// after it ran, dropping the oldest entry when full.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn record(
+        &mut self,
+        pc: u16,
+        opcode: u16,
+        long: u16,
+        (registers_before, i_before): ([u8; 16], u16),
+        registers: &[u8; 16],
+        i: u16,
+        fault: Option<FaultKind>,
+    ) {
+        let mut changes: Vec<Change> = (0..16)
+            .filter(|&index| registers_before[index] != registers[index])
+            .map(|index| Change::Register {
+                index: index as u8,
+                from: registers_before[index],
+                to: registers[index],
+            })
+            .collect();
+        if i_before != i {
+            changes.push(Change::I {
+                from: i_before,
+                to: i,
+            });
+        }
+
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TraceEntry {
+            pc,
+            opcode,
+            long,
+            changes,
+            fault,
+        });
+    }
+
+    /// One line per instruction: address, opcode, Cowgod mnemonic and the
+    /// changes it made, such as
+    /// `0x204  8AB4  ADD VA, VB            VA 0xFF -> 0x01, VF 0x00 -> 0x01`.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for entry in self.entries() {
+            let mut line = format!(
+                "0x{:03X}  {:04X}  {:<20}",
+                entry.pc,
+                entry.opcode,
+                entry.instruction()
+            );
+            let changes: Vec<String> = entry
+                .changes
+                .iter()
+                .map(|change| match change {
+                    Change::Register { index, from, to } => {
+                        format!("V{:X} 0x{:02X} -> 0x{:02X}", index, from, to)
+                    }
+                    Change::I { from, to } => format!("I 0x{:03X} -> 0x{:03X}", from, to),
+                })
+                .collect();
+            line.push_str(&changes.join(", "));
+            if let Some(fault) = entry.fault {
+                write!(line, "fault: {}", fault).unwrap();
+            }
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// A JSON array with an object per instruction, oldest first:
+    /// `{"pc":516,"opcode":"8AB4","instruction":"ADD VA, VB",
+    /// "changes":{"va":[255,1],"vf":[0,1]}}`, plus `"fault"` when it faulted.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (index, entry) in self.entries().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            write!(
+                json,
+                "\n  {{\"pc\":{},\"opcode\":\"{:04X}\",\"instruction\":\"{}\",\"changes\":{{",
+                entry.pc,
+                entry.opcode,
+                entry.instruction()
+            )
+            .unwrap();
+            for (index, change) in entry.changes.iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                match change {
+                    Change::Register { index, from, to } => {
+                        write!(json, "\"v{:x}\":[{},{}]", index, from, to)
+                    }
+                    Change::I { from, to } => write!(json, "\"i\":[{},{}]", from, to),
+                }
+                .unwrap();
+            }
+            json.push('}');
+            if let Some(fault) = entry.fault {
+                write!(json, ",\"fault\":\"{}\"", fault).unwrap();
+            }
+            json.push('}');
+        }
+        json.push_str("\n]\n");
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::cpu::Cpu;
+
+    fn traced_cpu(program: &[u16], capacity: usize) -> Cpu {
+        let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+        let mut cpu = Cpu::default();
+        cpu.load_rom(&rom).unwrap();
+        cpu.enable_trace(capacity);
+        cpu
+    }
+
+    #[test]
+    fn records_what_each_instruction_changed() {
+        // va := 0x0F, vb := 2, va += vb, i := 0x300, clear
+        let mut cpu = traced_cpu(&[0x6A0F, 0x6B02, 0x8AB4, 0xA300, 0x00E0], 16);
+        for _ in 0..5 {
+            cpu.step();
+        }
+
+        let entries: Vec<&TraceEntry> = cpu.trace().unwrap().entries().collect();
+        assert_eq!(5, entries.len());
+        assert_eq!(0x204, entries[2].pc);
+        assert_eq!(0x8AB4, entries[2].opcode);
+        assert_eq!(Some(Opcode::ADDR(0xA, 0xB)), entries[2].decoded());
+        assert!(entries[2].changes.contains(&Change::Register {
+            index: 0xA,
+            from: 0x0F,
+            to: 0x11
+        }));
+        assert_eq!(vec![Change::I { from: 0, to: 0x300 }], entries[3].changes);
+        assert!(entries[4].changes.is_empty());
+    }
+
+    #[test]
+    fn keeps_only_the_newest_entries() {
+        // v0 += 1, jump 0x200
+        let mut cpu = traced_cpu(&[0x7001, 0x1200], 3);
+        for _ in 0..10 {
+            cpu.step();
+        }
+
+        let pcs: Vec<u16> = cpu
+            .trace()
+            .unwrap()
+            .entries()
+            .map(|entry| entry.pc)
+            .collect();
+        assert_eq!(vec![0x202, 0x200, 0x202], pcs);
+    }
+
+    #[test]
+    fn records_faults() {
+        let mut cpu = traced_cpu(&[0x00EE], 4);
+        cpu.step();
+
+        let trace = cpu.trace().unwrap();
+        assert_eq!(
+            Some(FaultKind::StackUnderflow),
+            trace.entries().next().unwrap().fault
+        );
+        assert_eq!(
+            "0x200  00EE  RET                 fault: stack underflow\n",
+            trace.to_text()
+        );
+    }
+
+    #[test]
+    fn untraced_cpus_record_nothing() {
+        let mut cpu = traced_cpu(&[0x7001], 4);
+        cpu.disable_trace();
+        cpu.step();
+
+        assert!(cpu.trace().is_none());
+    }
+
+    #[test]
+    fn exports_text_and_json() {
+        // v1 := 5, i := long 0x1234
+        let mut cpu = traced_cpu(&[0x6105, 0xF000, 0x1234], 4);
+        cpu.set_platform(crate::chip8::Platform::XoChip);
+        cpu.step();
+        cpu.step();
+
+        let trace = cpu.trace().unwrap();
+        assert_eq!(
+            "0x200  6105  LD V1, 0x05         V1 0x00 -> 0x05\n\
+             0x202  F000  LD I, LONG 0x1234   I 0x000 -> 0x1234\n",
+            trace.to_text()
+        );
+        assert_eq!(
+            "[\n  {\"pc\":512,\"opcode\":\"6105\",\"instruction\":\"LD V1, 0x05\",\"changes\":{\"v1\":[0,5]}},\n  \
+             {\"pc\":514,\"opcode\":\"F000\",\"instruction\":\"LD I, LONG 0x1234\",\"changes\":{\"i\":[0,4660]}}\n]\n",
+            trace.to_json()
+        );
+    }
+}
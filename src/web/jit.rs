@@ -0,0 +1,58 @@
+use crate::chip8;
+use js_sys::{Function, Object, Reflect, Uint8Array, WebAssembly};
+use wasm_bindgen::{JsCast, JsValue};
+
+/// Runs the blocks the `chip8::Jit` compiles with the browser's own
+/// WebAssembly engine. Every block imports the same one page memory.
+pub struct BrowserRuntime {
+    memory: WebAssembly::Memory,
+    imports: Object,
+}
+
+fn describe(err: JsValue) -> String {
+    err.as_string().unwrap_or_else(|| format!("{:?}", err))
+}
+
+impl BrowserRuntime {
+    pub fn new() -> Result<BrowserRuntime, JsValue> {
+        let descriptor = Object::new();
+        Reflect::set(&descriptor, &"initial".into(), &1.into())?;
+        let memory = WebAssembly::Memory::new(&descriptor)?;
+
+        let env = Object::new();
+        Reflect::set(&env, &"state".into(), &memory)?;
+        let imports = Object::new();
+        Reflect::set(&imports, &"env".into(), &env)?;
+
+        Ok(BrowserRuntime { memory, imports })
+    }
+
+    fn state(&self) -> Uint8Array {
+        Uint8Array::new(&self.memory.buffer()).subarray(0, chip8::JIT_STATE_SIZE as u32)
+    }
+}
+
+impl chip8::Runtime for BrowserRuntime {
+    type Instance = Function;
+
+    fn instantiate(&mut self, module: &[u8]) -> Result<Function, String> {
+        // Blocks are far below the size browsers allow compiling
+        // synchronously on the main thread.
+        let module = WebAssembly::Module::new(&Uint8Array::from(module)).map_err(describe)?;
+        let instance = WebAssembly::Instance::new(&module, &self.imports).map_err(describe)?;
+        Reflect::get(&instance.exports(), &"run".into())
+            .and_then(|run| run.dyn_into::<Function>())
+            .map_err(describe)
+    }
+
+    fn run(&mut self, run: &Function, state: &mut [u8; chip8::JIT_STATE_SIZE]) -> u16 {
+        self.state().copy_from(state);
+        let next = run
+            .call0(&JsValue::NULL)
+            .ok()
+            .and_then(|next| next.as_f64())
+            .expect("compiled block failed");
+        self.state().copy_to(state);
+        next as u16
+    }
+}
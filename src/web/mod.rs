@@ -0,0 +1,800 @@
+//! Browser glue for the emulator: the wasm-bindgen API, the animation frame
+//! loop and the WebGL renderer.
+
+mod jit;
+mod render;
+mod time;
+mod webgl;
+
+use crate::chip8;
+use render::Renderer;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+struct Data {
+    pub game_time: time::GameTime,
+    pub cpu: chip8::Cpu,
+    /// The loaded program, kept for coverage reports.
+    pub rom: Vec<u8>,
+    /// Where the loaded program starts in memory.
+    pub origin: u16,
+    pub renderer: Renderer,
+    pub fault: Option<chip8::CpuFault>,
+    pub rewind: chip8::Rewind,
+    pub rewinding: bool,
+    /// Labels of the loaded program, when it was compiled from source.
+    pub symbols: BTreeMap<String, u16>,
+    /// `:breakpoint` names by address, when compiled from source.
+    pub breakpoints: BTreeMap<u16, String>,
+    pub debugger: chip8::Debugger,
+    /// Set when the debugger stopped the machine; frames don't run until
+    /// `resume`.
+    pub paused: bool,
+    pub stop: Option<chip8::Stop>,
+    /// Elapsed time not yet run, in frames.
+    pub pending_frames: f64,
+    /// Runs frames instead of the debugger while it's on.
+    pub jit: Option<chip8::Jit<jit::BrowserRuntime>>,
+    /// Picks the platform, quirks, speed and colors of known roms.
+    pub database: chip8::RomDatabase,
+    /// What the database knows about the loaded rom.
+    pub rom_info: Option<chip8::RomInfo>,
+    /// The platform, quirks and font picked with `set_platform`,
+    /// `set_quirks` and `set_font`, put back for roms the database doesn't
+    /// know.
+    pub platform: chip8::Platform,
+    pub quirks: chip8::Quirks,
+    pub font: Vec<u8>,
+    pub font_address: u16,
+}
+
+thread_local! {
+    static DATA: RefCell<Data> = RefCell::new(Data {
+        game_time: time::GameTime::new(now()),
+        cpu: new_cpu(),
+        rom: Vec::new(),
+        origin: chip8::DEFAULT_ORIGIN,
+        renderer: Renderer::new().expect("failed to initialize renderer"),
+        fault: None,
+        rewind: chip8::Rewind::default(),
+        rewinding: false,
+        symbols: BTreeMap::new(),
+        breakpoints: BTreeMap::new(),
+        debugger: new_debugger(),
+        paused: false,
+        stop: None,
+        pending_frames: 0.0,
+        jit: None,
+        database: chip8::RomDatabase::embedded(),
+        rom_info: None,
+        platform: chip8::Platform::default(),
+        quirks: chip8::Quirks::default(),
+        font: chip8::Font::default().glyphs().to_vec(),
+        font_address: chip8::FONT_ADDRESS,
+    });
+}
+
+/// Creates the cpu with a random seed, so games differ between page loads
+/// unless `set_seed` is called.
+fn new_cpu() -> chip8::Cpu {
+    let mut cpu = chip8::Cpu::new(chip8::Quirks::default());
+    cpu.seed_rng((js_sys::Math::random() * u32::MAX as f64) as u64);
+    cpu
+}
+
+fn new_debugger() -> chip8::Debugger {
+    chip8::Debugger::new(chip8::Platform::default().tick_rate())
+}
+
+/// Resets the machine and loads `rom`, so a new rom can be swapped in while
+/// another one runs. Roms found in the rom database get the platform,
+/// quirks, speed, font and colors it lists, and load where it says unless
+/// `address` is given. Other roms get the platform, quirks and font last
+/// picked, load at `address`, 0x200 unless given, and run at the speed their
+/// platform usually expects.
+#[wasm_bindgen]
+pub fn load(rom: Vec<u8>, address: Option<u16>) -> Result<(), JsValue> {
+    console_error_panic_hook::set_once();
+
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        let rom_info = data.database.lookup(&rom);
+        let (origin, tick_rate, palette) = match &rom_info {
+            Some(info) => {
+                let info = chip8::RomInfo {
+                    start_address: address.unwrap_or(info.start_address),
+                    ..info.clone()
+                };
+                info.load(&mut data.cpu, &rom)
+                    .map_err(|err| JsValue::from_str(&err.to_string()))?;
+                (info.start_address, info.tick_rate, info.palette)
+            }
+            None => {
+                let origin = address.unwrap_or(chip8::DEFAULT_ORIGIN);
+                let data = &mut *data;
+                // Check everything before switching platforms, so a failed
+                // load leaves the running program alone. The font was checked
+                // against the memory it was set in, which may be larger.
+                data.platform
+                    .check_rom(&rom, origin)
+                    .map_err(|err| JsValue::from_str(&err.to_string()))?;
+                if data.font_address as usize + data.font.len() > data.platform.memory_size() {
+                    let err = chip8::FontError::OutOfMemory(data.font_address);
+                    return Err(JsValue::from_str(&err.to_string()));
+                }
+                data.cpu.set_platform(data.platform);
+                data.cpu.quirks = data.quirks;
+                data.cpu
+                    .load_custom_font(&data.font, chip8::FontSize::Small, data.font_address)
+                    .map_err(|err| JsValue::from_str(&err.to_string()))?;
+                data.cpu
+                    .load_rom_at(&rom, origin)
+                    .map_err(|err| JsValue::from_str(&err.to_string()))?;
+                (origin, data.cpu.platform.tick_rate(), None)
+            }
+        };
+        data.debugger.set_cycles_per_frame(tick_rate);
+        data.renderer
+            .set_palette(palette.unwrap_or(chip8::DEFAULT_PALETTE));
+        data.rom_info = rom_info;
+        if let Some(jit) = data.jit.as_mut() {
+            jit.clear();
+        }
+        data.rom = rom;
+        data.origin = origin;
+        data.fault = None;
+        data.rewind.clear();
+        data.symbols.clear();
+        data.breakpoints.clear();
+        data.debugger.clear();
+        data.debugger.reset();
+        data.paused = false;
+        data.stop = None;
+
+        Ok(())
+    })
+}
+
+/// Compiles Octo source and loads the result, keeping its labels and
+/// breakpoints for the debugger.
+#[wasm_bindgen]
+pub fn load_octo(source: &str) -> Result<(), JsValue> {
+    let program = chip8::compile_octo(source, "<source>")
+        .map_err(|err| JsValue::from_str(&err.to_string()))?;
+    let chip8::Program {
+        rom,
+        origin,
+        symbols,
+        breakpoints,
+    } = program;
+    load(rom, Some(origin))?;
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        for &address in breakpoints.keys() {
+            data.debugger.set_breakpoint(address, None);
+        }
+        data.symbols = symbols;
+        data.breakpoints = breakpoints;
+    });
+
+    Ok(())
+}
+
+/// Adds entries in the chip-8-database `programs.json` format to the rom
+/// database, overriding the fields they give for roms it already knows.
+/// Takes effect on the next `load`.
+#[wasm_bindgen]
+pub fn merge_rom_database(json: &str) -> Result<(), JsValue> {
+    DATA.with(|data| {
+        data.borrow_mut()
+            .database
+            .merge(json)
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    })
+}
+
+/// The title of the loaded rom, when the rom database knows it.
+#[wasm_bindgen]
+pub fn rom_title() -> Option<String> {
+    DATA.with(|data| {
+        data.borrow()
+            .rom_info
+            .as_ref()
+            .map(|info| info.title.clone())
+    })
+}
+
+/// The keypad key performing `action` in the loaded rom, such as `up`,
+/// `left` or `a`, when the rom database lists one.
+#[wasm_bindgen]
+pub fn action_key(action: &str) -> Option<u8> {
+    DATA.with(|data| {
+        data.borrow()
+            .rom_info
+            .as_ref()
+            .and_then(|info| info.keys.get(action).copied())
+    })
+}
+
+/// Sets the instructions run per 60 Hz frame, overriding what `load` picked.
+#[wasm_bindgen]
+pub fn set_tick_rate(cycles_per_frame: u32) {
+    DATA.with(|data| {
+        data.borrow_mut()
+            .debugger
+            .set_cycles_per_frame(cycles_per_frame)
+    });
+}
+
+/// Selects the platform the cpu emulates, `chip8`, `schip` or `xochip`, along
+/// with the quirks its programs expect. Call this before `load`; roms in the
+/// rom database pick their own.
+#[wasm_bindgen]
+pub fn set_platform(name: &str) -> Result<(), JsValue> {
+    let platform = chip8::Platform::from_name(name)
+        .ok_or_else(|| JsValue::from_str(&format!("unknown platform: {}", name)))?;
+
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.cpu.set_platform(platform);
+        data.platform = platform;
+        data.quirks = data.cpu.quirks;
+    });
+
+    Ok(())
+}
+
+/// Reseeds the random number generator behind CXNN. Runs with the same seed
+/// and the same input produce the same frames.
+#[wasm_bindgen]
+pub fn set_seed(seed: u32) {
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.cpu.seed_rng(seed as u64);
+    });
+}
+
+/// Sets the four colors used for the bitplane combinations, as 0xRRGGBB:
+/// background, first plane, second plane and both planes.
+#[wasm_bindgen]
+pub fn set_palette(colors: Vec<u32>) -> Result<(), JsValue> {
+    if colors.len() != 4 {
+        return Err(JsValue::from_str("palette needs exactly four colors"));
+    }
+
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.renderer
+            .set_palette([colors[0], colors[1], colors[2], colors[3]]);
+        data.cpu.screen.set_dirty();
+    });
+
+    Ok(())
+}
+
+/// Selects the quirks preset the cpu runs with: `vip`, `chip48`, `schip` or
+/// `xochip`.
+#[wasm_bindgen]
+pub fn set_quirks(preset: &str) -> Result<(), JsValue> {
+    let quirks = chip8::Quirks::from_name(preset)
+        .ok_or_else(|| JsValue::from_str(&format!("unknown quirks preset: {}", preset)))?;
+
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.cpu.quirks = quirks;
+        data.quirks = quirks;
+    });
+
+    Ok(())
+}
+
+/// Loads a built-in font: `chip48`, `vip`, `dream6800`, `eti660`, `fish`, or
+/// `schip` for the big font. Without an address it goes where the default
+/// font of its size lives.
+#[wasm_bindgen]
+pub fn set_font(name: &str, address: Option<u16>) -> Result<(), JsValue> {
+    let font = chip8::Font::from_name(name)
+        .ok_or_else(|| JsValue::from_str(&format!("unknown font: {}", name)))?;
+
+    set_custom_font(font.glyphs(), font.size() == chip8::FontSize::Big, address)
+}
+
+/// Loads a font blob: sixteen 5 byte glyphs, or ten or sixteen 10 byte glyphs
+/// when `big` is set.
+#[wasm_bindgen]
+pub fn set_custom_font(glyphs: &[u8], big: bool, address: Option<u16>) -> Result<(), JsValue> {
+    let (size, default_address) = if big {
+        (chip8::FontSize::Big, chip8::BIG_FONT_ADDRESS)
+    } else {
+        (chip8::FontSize::Small, chip8::FONT_ADDRESS)
+    };
+
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        let address = address.unwrap_or(default_address);
+        data.cpu
+            .load_custom_font(glyphs, size, address)
+            .map_err(|err| JsValue::from_str(&err.to_string()))?;
+        if size == chip8::FontSize::Small {
+            data.font = glyphs.to_vec();
+            data.font_address = address;
+        }
+
+        Ok(())
+    })
+}
+
+/// Snapshots the whole machine into a versioned binary blob that
+/// `load_state` accepts.
+#[wasm_bindgen]
+pub fn save_state() -> Vec<u8> {
+    DATA.with(|data| data.borrow().cpu.save_state())
+}
+
+/// Restores a snapshot taken by `save_state`. Corrupt or incompatible data is
+/// rejected and leaves the running machine untouched.
+#[wasm_bindgen]
+pub fn load_state(state: &[u8]) -> Result<(), JsValue> {
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.cpu
+            .load_state(state)
+            .map_err(|err| JsValue::from_str(&err.to_string()))?;
+        data.fault = None;
+        data.rewind.clear();
+        data.cpu.screen.set_dirty();
+
+        Ok(())
+    })
+}
+
+/// Sizes the rewind history: `depth_secs` seconds, snapshotting every
+/// `interval` frames. Clears the history recorded so far.
+#[wasm_bindgen]
+pub fn set_rewind(depth_secs: f64, interval: u32) {
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.rewind = chip8::Rewind::new(depth_secs, interval);
+    });
+}
+
+/// While rewinding, every frame steps the machine back one snapshot instead
+/// of running it forward.
+#[wasm_bindgen]
+pub fn set_rewinding(rewinding: bool) {
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.rewinding = rewinding;
+    });
+}
+
+fn window() -> web_sys::Window {
+    web_sys::window().expect("no global `window` exists")
+}
+
+fn request_animation_frame(f: &Closure<dyn FnMut()>) {
+    window()
+        .request_animation_frame(f.as_ref().unchecked_ref())
+        .expect("should register `requestAnimationFrame` OK");
+}
+
+fn now() -> f64 {
+    window()
+        .performance()
+        .expect("performance on window should be available")
+        .now()
+}
+
+#[wasm_bindgen]
+pub fn start() -> Result<(), JsValue> {
+    let f = Rc::new(RefCell::new(None));
+    let g = f.clone();
+
+    *g.borrow_mut() = Some(Closure::wrap(Box::new(move || {
+        DATA.with(|data| {
+            let mut data = data.borrow_mut();
+
+            data.game_time.update(now());
+            let elapsed_secs = data.game_time.elapsed_secs();
+
+            if data.rewinding {
+                let data = &mut *data;
+                match data.rewind.step_back(&mut data.cpu) {
+                    Ok(true) => {
+                        data.fault = None;
+                        data.cpu.screen.set_dirty();
+                    }
+                    Ok(false) => (),
+                    Err(err) => {
+                        let message = format!("rewind failed: {}", err);
+                        web_sys::console::error_1(&message.into());
+                        data.rewind.clear();
+                    }
+                }
+            } else if data.fault.is_none() && !data.paused {
+                let data = &mut *data;
+                data.pending_frames += elapsed_secs * chip8::TIMER_FREQUENCY;
+                while data.pending_frames >= 1.0 && !data.paused && data.fault.is_none() {
+                    data.pending_frames -= 1.0;
+                    let frame_start = now();
+                    let stop = match data.jit.as_mut() {
+                        Some(jit) => {
+                            let cycles = data.debugger.cycles_per_frame();
+                            match jit.run_frame(&mut data.cpu, cycles) {
+                                chip8::StepOutcome::Fault(fault) => Some(chip8::Stop::Fault(fault)),
+                                chip8::StepOutcome::Exited => Some(chip8::Stop::Exited),
+                                _ => None,
+                            }
+                        }
+                        None => data.debugger.run_frame(&mut data.cpu),
+                    };
+                    if let Some(profiler) = data.cpu.profiler_mut() {
+                        profiler.record_frame_time(now() - frame_start);
+                    }
+                    match stop {
+                        None => data.rewind.record_frame(&data.cpu),
+                        Some(stop) => handle_stop(data, stop),
+                    }
+                }
+            }
+
+            if data.cpu.screen.is_dirty() {
+                data.renderer.render(&data.cpu.screen);
+                data.cpu.screen.reset_dirty();
+            }
+        });
+
+        request_animation_frame(f.borrow().as_ref().unwrap());
+    }) as Box<dyn FnMut()>));
+    request_animation_frame(g.borrow().as_ref().unwrap());
+    Ok(())
+}
+
+/// Records why the debugger stopped the machine. Breakpoints and
+/// watchpoints pause it, faults halt it and exits leave it idle.
+fn handle_stop(data: &mut Data, stop: chip8::Stop) {
+    match stop {
+        chip8::Stop::Fault(fault) => {
+            let message = format!("cpu halted: {}", fault);
+            web_sys::console::error_1(&message.into());
+            data.fault = Some(fault);
+        }
+        chip8::Stop::Exited => data.pending_frames = 0.0,
+        _ => data.paused = true,
+    }
+    data.stop = Some(stop);
+    data.cpu.screen.set_dirty();
+}
+
+/// Sets a breakpoint at `address`. With a condition such as
+/// `v3 == 0x10 && [i] != 0` the machine only stops when it holds.
+#[wasm_bindgen]
+pub fn set_breakpoint(address: u16, condition: Option<String>) -> Result<(), JsValue> {
+    let condition = condition
+        .map(|text| chip8::Condition::parse(&text))
+        .transpose()
+        .map_err(|err| JsValue::from_str(&err.to_string()))?;
+
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.debugger.set_breakpoint(address, condition);
+    });
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn remove_breakpoint(address: u16) -> bool {
+    DATA.with(|data| data.borrow_mut().debugger.remove_breakpoint(address))
+}
+
+/// Stops after instructions that touch `len` bytes of memory from `start`.
+/// `kind` is `read`, `write` or `access` for both.
+#[wasm_bindgen]
+pub fn add_watchpoint(start: u16, len: u16, kind: &str) -> Result<(), JsValue> {
+    let range = watch_range(start, len)?;
+    let watch = chip8::Watch::from_name(kind)
+        .ok_or_else(|| JsValue::from_str(&format!("unknown watchpoint kind: {}", kind)))?;
+
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.debugger.add_watchpoint(range, watch);
+    });
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn remove_watchpoint(start: u16, len: u16, kind: &str) -> Result<bool, JsValue> {
+    let range = watch_range(start, len)?;
+    let watch = chip8::Watch::from_name(kind)
+        .ok_or_else(|| JsValue::from_str(&format!("unknown watchpoint kind: {}", kind)))?;
+
+    Ok(DATA.with(|data| data.borrow_mut().debugger.remove_watchpoint(range, watch)))
+}
+
+fn watch_range(start: u16, len: u16) -> Result<std::ops::Range<u16>, JsValue> {
+    let end = start
+        .checked_add(len)
+        .ok_or_else(|| JsValue::from_str("watchpoint past the end of memory"))?;
+    Ok(start..end)
+}
+
+/// Removes every breakpoint and watchpoint.
+#[wasm_bindgen]
+pub fn clear_breakpoints() {
+    DATA.with(|data| data.borrow_mut().debugger.clear());
+}
+
+/// Looks up the address of a label of a program loaded with `load_octo`.
+#[wasm_bindgen]
+pub fn symbol_address(name: &str) -> Option<u16> {
+    DATA.with(|data| data.borrow().symbols.get(name).copied())
+}
+
+#[wasm_bindgen]
+pub fn pause() {
+    DATA.with(|data| data.borrow_mut().paused = true);
+}
+
+#[wasm_bindgen]
+pub fn resume() {
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.paused = false;
+        data.stop = None;
+        data.pending_frames = 0.0;
+    });
+}
+
+#[wasm_bindgen]
+pub fn is_paused() -> bool {
+    DATA.with(|data| data.borrow().paused)
+}
+
+/// Describes why the machine last stopped, if it did.
+#[wasm_bindgen]
+pub fn stop_reason() -> Option<String> {
+    DATA.with(|data| data.borrow().stop.map(|stop| stop.to_string()))
+}
+
+/// Runs the debugger command `step` while paused and describes where it
+/// stopped.
+fn debug_step(step: impl FnOnce(&mut chip8::Debugger, &mut chip8::Cpu) -> chip8::Stop) -> String {
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+        let data = &mut *data;
+
+        data.paused = true;
+        let stop = step(&mut data.debugger, &mut data.cpu);
+        handle_stop(data, stop);
+        stop.to_string()
+    })
+}
+
+#[wasm_bindgen]
+pub fn step_into() -> String {
+    debug_step(|debugger, cpu| debugger.step_into(cpu))
+}
+
+/// Steps one instruction, running a called subroutine through to its
+/// return.
+#[wasm_bindgen]
+pub fn step_over() -> String {
+    debug_step(|debugger, cpu| debugger.step_over(cpu))
+}
+
+/// Runs until the current subroutine returns.
+#[wasm_bindgen]
+pub fn step_out() -> String {
+    debug_step(|debugger, cpu| debugger.step_out(cpu))
+}
+
+/// Runs until `frame` frames have passed since the program was loaded.
+#[wasm_bindgen]
+pub fn run_until_frame(frame: f64) -> String {
+    debug_step(|debugger, cpu| debugger.run_until_frame(cpu, frame as u64))
+}
+
+/// Frames run since the program was loaded.
+#[wasm_bindgen]
+pub fn frame() -> f64 {
+    DATA.with(|data| data.borrow().debugger.frame() as f64)
+}
+
+/// V0 to VF.
+#[wasm_bindgen]
+pub fn registers() -> Vec<u8> {
+    DATA.with(|data| data.borrow().cpu.registers().to_vec())
+}
+
+#[wasm_bindgen]
+pub fn pc() -> u16 {
+    DATA.with(|data| data.borrow().cpu.pc())
+}
+
+#[wasm_bindgen]
+pub fn i() -> u16 {
+    DATA.with(|data| data.borrow().cpu.i())
+}
+
+#[wasm_bindgen]
+pub fn sp() -> u8 {
+    DATA.with(|data| data.borrow().cpu.sp())
+}
+
+/// The return addresses on the stack, oldest first.
+#[wasm_bindgen]
+pub fn stack() -> Vec<u16> {
+    DATA.with(|data| data.borrow().cpu.stack().to_vec())
+}
+
+/// The delay and sound timers.
+#[wasm_bindgen]
+pub fn timers() -> Vec<u8> {
+    DATA.with(|data| {
+        let data = data.borrow();
+        vec![data.cpu.timers.delay, data.cpu.timers.sound]
+    })
+}
+
+/// Up to `len` bytes of memory from `start`, fewer past the end of memory.
+#[wasm_bindgen]
+pub fn read_memory(start: u16, len: u16) -> Vec<u8> {
+    DATA.with(|data| {
+        let data = data.borrow();
+        let memory = data.cpu.memory();
+        let start = (start as usize).min(memory.len());
+        let end = (start + len as usize).min(memory.len());
+        memory[start..end].to_vec()
+    })
+}
+
+/// Records the last `capacity` instructions executed for `export_trace`, or
+/// stops tracing when `capacity` is 0.
+#[wasm_bindgen]
+pub fn set_trace(capacity: u32) {
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        if capacity == 0 {
+            data.cpu.disable_trace();
+        } else {
+            data.cpu.enable_trace(capacity as usize);
+        }
+    });
+}
+
+/// The traced instructions as `text` or `json`.
+#[wasm_bindgen]
+pub fn export_trace(format: &str) -> Result<String, JsValue> {
+    DATA.with(|data| {
+        let data = data.borrow();
+        let trace = data
+            .cpu
+            .trace()
+            .ok_or_else(|| JsValue::from_str("tracing is off"))?;
+
+        match format {
+            "text" => Ok(trace.to_text()),
+            "json" => Ok(trace.to_json()),
+            _ => Err(JsValue::from_str(&format!(
+                "unknown trace format: {}",
+                format
+            ))),
+        }
+    })
+}
+
+/// Turns the WebAssembly JIT on or off. It runs frames faster, but
+/// breakpoints, watchpoints, the trace and the profiler miss whatever runs
+/// as compiled code.
+#[wasm_bindgen]
+pub fn set_jit(enabled: bool) -> Result<(), JsValue> {
+    let jit = if enabled {
+        Some(chip8::Jit::new(jit::BrowserRuntime::new()?))
+    } else {
+        None
+    };
+    DATA.with(|data| data.borrow_mut().jit = jit);
+    Ok(())
+}
+
+/// Starts profiling, discarding any earlier profile, or stops it.
+#[wasm_bindgen]
+pub fn set_profiling(enabled: bool) {
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        if enabled {
+            data.cpu.enable_profiler();
+        } else {
+            data.cpu.disable_profiler();
+        }
+    });
+}
+
+/// The profile as text, with hot loops and busy-waits marked.
+#[wasm_bindgen]
+pub fn profile_report() -> Result<String, JsValue> {
+    DATA.with(|data| {
+        let data = data.borrow();
+        let profiler = data
+            .cpu
+            .profiler()
+            .ok_or_else(|| JsValue::from_str("profiling is off"))?;
+
+        Ok(profiler.report(data.cpu.memory()))
+    })
+}
+
+/// Which instructions of the loaded program ran, as `json` or `lcov`.
+#[wasm_bindgen]
+pub fn coverage(format: &str) -> Result<String, JsValue> {
+    DATA.with(|data| {
+        let data = data.borrow();
+        let profiler = data
+            .cpu
+            .profiler()
+            .ok_or_else(|| JsValue::from_str("profiling is off"))?;
+
+        match format {
+            "json" => Ok(profiler.coverage_json(&data.rom, data.origin)),
+            "lcov" => Ok(profiler.coverage_lcov("rom.ch8", &data.rom, data.origin)),
+            _ => Err(JsValue::from_str(&format!(
+                "unknown coverage format: {}",
+                format
+            ))),
+        }
+    })
+}
+
+fn check_key(key: u8) -> Result<(), JsValue> {
+    if key >= chip8::KEY_COUNT {
+        return Err(JsValue::from_str(&format!("invalid key: {}", key)));
+    }
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn key_down(key: u8) -> Result<(), JsValue> {
+    check_key(key)?;
+
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.cpu.keypad.key_down(key);
+    });
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn key_up(key: u8) -> Result<(), JsValue> {
+    check_key(key)?;
+
+    DATA.with(|data| {
+        let mut data = data.borrow_mut();
+
+        data.cpu.keypad.key_up(key);
+    });
+
+    Ok(())
+}
@@ -1,8 +1,8 @@
-use crate::chip8::screen::Screen;
-use crate::webgl;
-use crate::webgl::buffer;
-use crate::webgl::shader;
-use crate::webgl::texture;
+use crate::chip8::{Screen, DEFAULT_PALETTE};
+use crate::web::webgl;
+use crate::web::webgl::buffer;
+use crate::web::webgl::shader;
+use crate::web::webgl::texture;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::WebGlRenderingContext;
@@ -11,6 +11,7 @@ use web_sys::WebGlTexture;
 pub struct Renderer {
     context: WebGlRenderingContext,
     texture: WebGlTexture,
+    palette: [u32; 4],
 }
 
 impl Renderer {
@@ -56,7 +57,7 @@ impl Renderer {
 
         let texture = webgl::texture::create_texture(&context)?;
         context.active_texture(WebGlRenderingContext::TEXTURE0);
-        texture::update_texture(&context, &texture, 64, 32, &vec![0; 64 * 32])?;
+        texture::update_texture(&context, &texture, 64, 32, &[0; 64 * 32 * 3])?;
         texture::disable_mipmapping(&context);
 
         let texture_location = context.get_uniform_location(&program, "sampler");
@@ -74,14 +75,26 @@ impl Renderer {
 
         context.clear_color(1.0, 1.0, 0.0, 1.0);
         Ok(Renderer {
-            context: context,
-            texture: texture,
+            context,
+            texture,
+            palette: DEFAULT_PALETTE,
         })
     }
 
+    pub fn set_palette(&mut self, palette: [u32; 4]) {
+        self.palette = palette;
+    }
+
     pub fn render(&self, screen: &Screen) {
-        let data = screen.get_screen_data();
-        texture::update_texture(&self.context, &self.texture, 64, 32, &data.to_vec()).expect("");
+        let data = screen.get_rgb_data(&self.palette);
+        texture::update_texture(
+            &self.context,
+            &self.texture,
+            screen.width() as i32,
+            screen.height() as i32,
+            &data,
+        )
+        .expect("");
 
         self.context.clear(WebGlRenderingContext::COLOR_BUFFER_BIT);
         self.context
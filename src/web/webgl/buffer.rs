@@ -11,9 +11,9 @@ pub fn vertex_buffer_data(
     buffer: &WebGlBuffer,
     data: &[f32],
 ) -> Result<(), JsValue> {
-    context.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&buffer));
+    context.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(buffer));
     unsafe {
-        let array = js_sys::Float32Array::view(&data);
+        let array = js_sys::Float32Array::view(data);
 
         context.buffer_data_with_array_buffer_view(
             WebGlRenderingContext::ARRAY_BUFFER,
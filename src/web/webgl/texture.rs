@@ -22,22 +22,23 @@ pub fn create_texture(context: &WebGlRenderingContext) -> Result<WebGlTexture, J
     Ok(texture)
 }
 
+/// Uploads `data` as the texture's image, three bytes of RGB per pixel.
 pub fn update_texture(
     context: &WebGlRenderingContext,
     texture: &WebGlTexture,
     width: i32,
     height: i32,
-    data: &Vec<u8>,
+    data: &[u8],
 ) -> Result<(), JsValue> {
-    context.bind_texture(WebGlRenderingContext::TEXTURE_2D, Some(&texture));
+    context.bind_texture(WebGlRenderingContext::TEXTURE_2D, Some(texture));
     context.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
-        WebGlRenderingContext::TEXTURE_2D,       // target
-        0,                                       // mipmap
-        WebGlRenderingContext::LUMINANCE as i32, // format
-        width,                                   // width
-        height,                                  // height
-        0,                                       // border
-        WebGlRenderingContext::LUMINANCE,
+        WebGlRenderingContext::TEXTURE_2D, // target
+        0,                                 // mipmap
+        WebGlRenderingContext::RGB as i32, // format
+        width,                             // width
+        height,                            // height
+        0,                                 // border
+        WebGlRenderingContext::RGB,
         WebGlRenderingContext::UNSIGNED_BYTE,
         Some(data),
     )?;
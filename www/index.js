@@ -7,22 +7,35 @@ const key_map = {
     'z': 10, 'x': 0, 'c': 11, 'v': 15
 };
 
-var key_state = 0;
+// Keys for the actions the rom database lists for the loaded rom.
+const action_map = {
+    'ArrowUp': 'up', 'ArrowDown': 'down',
+    'ArrowLeft': 'left', 'ArrowRight': 'right',
+    ' ': 'a', 'Shift': 'b'
+};
+
+const action_key = key => key in action_map ? wasm.action_key(action_map[key]) : undefined;
 
 window.addEventListener("keydown", event => {
-    if (key_map[event.key]) {
-        key_state = key_state | 1 << (key_map[event.key]);
+    const action = action_key(event.key);
+    if (event.key in key_map) {
+        wasm.key_down(key_map[event.key]);
+    } else if (action !== undefined) {
+        wasm.key_down(action);
+    } else if (event.key === 'Backspace') {
+        wasm.set_rewinding(true);
     }
-
-    wasm.on_key_state_changed(key_state);
 });
 
 window.addEventListener("keyup", event => {
-    if (key_map[event.key]) {
-        key_state = key_state ^ 1 << (key_map[event.key]);
+    const action = action_key(event.key);
+    if (event.key in key_map) {
+        wasm.key_up(key_map[event.key]);
+    } else if (action !== undefined) {
+        wasm.key_up(action);
+    } else if (event.key === 'Backspace') {
+        wasm.set_rewinding(false);
     }
-
-    wasm.on_key_state_changed(key_state);
 });
 
 fetch('/roms/tetris.rom')